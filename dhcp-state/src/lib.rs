@@ -0,0 +1,494 @@
+//! The lease bookkeeping for the access point's DHCP server, kept apart from the network stack so
+//! it can be tested on the host. Packets come and go through a [`Transport`], and the time is
+//! passed in with each packet.
+
+#![no_std]
+
+use heapless::{String, Vec};
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::{
+    DhcpMessageType, DhcpOption, DhcpPacket, DhcpRepr, EthernetAddress, Ipv4Address,
+};
+
+/// Big enough for a packet from any client on a WiFi network
+pub const REQUEST_BUFFER_LEN: usize = 1500;
+/// Big enough for any reply the server builds
+const REPLY_BUFFER_LEN: usize = 576;
+/// How long an address a client declined is kept out of the pool
+const DECLINE_TIME: Duration = Duration::from_secs(10 * 60);
+/// The configured options, plus the renewal and rebinding times
+const MAX_OPTIONS: usize = 16;
+const OPTION_RENEWAL_TIME: u8 = 58;
+const OPTION_REBINDING_TIME: u8 = 59;
+const OPTION_HOSTNAME: u8 = 12;
+/// Longer hostnames from clients aren't kept
+pub const MAX_HOSTNAME_LEN: usize = 32;
+
+/// Where the server's packets come from and go to
+#[allow(async_fn_in_trait)]
+pub trait Transport {
+    type Error: core::fmt::Debug;
+
+    /// Wait for the next packet from a client, returning its length
+    async fn receive(&mut self, buffer: &mut [u8]) -> Result<usize, Self::Error>;
+
+    /// Broadcast a reply to the clients
+    async fn send(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+#[derive(Debug)]
+pub enum Error<E> {
+    /// the packet from the client couldn't be parsed, or the reply couldn't be built
+    Packet(smoltcp::wire::Error),
+    Transport(E),
+}
+
+/// What an address in the pool is being used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assignment {
+    Free,
+    /// offered to a client, who hasn't requested it yet
+    Offered {
+        identifier: EthernetAddress,
+    },
+    /// leased to a client until `lease_end`
+    Assigned {
+        identifier: EthernetAddress,
+        lease_end: Instant,
+    },
+    /// a client found something else using the address, so it's kept out of the pool until `until`
+    Declined {
+        until: Instant,
+    },
+}
+
+impl Assignment {
+    /// The client this address is offered or leased to
+    pub fn identifier(&self) -> Option<EthernetAddress> {
+        match self {
+            Assignment::Offered { identifier } | Assignment::Assigned { identifier, .. } => {
+                Some(*identifier)
+            }
+            Assignment::Free | Assignment::Declined { .. } => None,
+        }
+    }
+
+    /// Whether the address can be given to any client
+    fn is_available(&self, now: Instant) -> bool {
+        match self {
+            Assignment::Free => true,
+            Assignment::Offered { .. } => false,
+            Assignment::Assigned { lease_end, .. } => *lease_end <= now,
+            Assignment::Declined { until } => *until <= now,
+        }
+    }
+}
+
+/// An address in the pool that's only ever given to one client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reservation {
+    pub hardware_address: EthernetAddress,
+    pub address: Ipv4Address,
+}
+
+/// A running lease, as listed by [`DhcpServer::leases`]
+#[derive(Debug, PartialEq, Eq)]
+pub struct Lease<'a> {
+    pub address: Ipv4Address,
+    pub identifier: EthernetAddress,
+    /// the name the client sent, or empty if it didn't send one
+    pub hostname: &'a str,
+    pub lease_end: Instant,
+}
+
+pub struct ServerConfig<'a> {
+    /// the server's own address, which is also given out as the router and DNS server
+    pub server_address: Ipv4Address,
+    pub subnet_mask: Ipv4Address,
+    /// the first address in the pool, which counts up from here
+    pub pool_start: Ipv4Address,
+    /// how many addresses are in the pool, up to the server's capacity
+    pub pool_size: usize,
+    pub lease_time: Duration,
+    /// addresses in the pool kept for particular clients
+    pub reservations: &'a [Reservation],
+    /// extra options sent in every offer and ack
+    pub options: &'a [DhcpOption<'a>],
+}
+
+/// A DHCP server with room for a pool of up to `N` addresses
+pub struct DhcpServer<'a, const N: usize> {
+    config: ServerConfig<'a>,
+    assignments: [Assignment; N],
+    /// the hostname each client sent with its last request
+    hostnames: [String<MAX_HOSTNAME_LEN>; N],
+    reply: [u8; REPLY_BUFFER_LEN],
+}
+
+impl<'a, const N: usize> DhcpServer<'a, N> {
+    pub fn new(mut config: ServerConfig<'a>) -> Self {
+        config.pool_size = config.pool_size.min(N);
+        Self {
+            config,
+            assignments: [Assignment::Free; N],
+            hostnames: core::array::from_fn(|_| String::new()),
+            reply: [0; REPLY_BUFFER_LEN],
+        }
+    }
+
+    /// What each address in the pool is being used for
+    pub fn assignments(&self) -> &[Assignment] {
+        &self.assignments[..self.config.pool_size]
+    }
+
+    /// The leases that haven't ended by `now`
+    pub fn leases(&self, now: Instant) -> impl Iterator<Item = Lease<'_>> {
+        self.assignments()
+            .iter()
+            .zip(&self.hostnames)
+            .enumerate()
+            .filter_map(move |(index, (assignment, hostname))| match *assignment {
+                Assignment::Assigned {
+                    identifier,
+                    lease_end,
+                } if now < lease_end => Some(Lease {
+                    address: self.address(index),
+                    identifier,
+                    hostname,
+                    lease_end,
+                }),
+                _ => None,
+            })
+    }
+
+    /// The address of slot `index` in the pool
+    pub fn address(&self, index: usize) -> Ipv4Address {
+        let start = u32::from_be_bytes(self.config.pool_start.0);
+        Ipv4Address::from_bytes(&(start + index as u32).to_be_bytes())
+    }
+
+    /// The slot holding `address`, if it's in the pool
+    fn index_of(&self, address: Ipv4Address) -> Option<usize> {
+        let start = u32::from_be_bytes(self.config.pool_start.0);
+        let index = u32::from_be_bytes(address.0).wrapping_sub(start) as usize;
+        (index < self.config.pool_size).then_some(index)
+    }
+
+    /// The slot offered or leased to a client
+    fn slot_of(&self, identifier: EthernetAddress) -> Option<usize> {
+        self.assignments()
+            .iter()
+            .position(|a| a.identifier() == Some(identifier))
+    }
+
+    /// The slot reserved for the client with `hardware_address`, if there is one
+    fn reserved_slot(&self, hardware_address: EthernetAddress) -> Option<usize> {
+        self.config
+            .reservations
+            .iter()
+            .find(|r| r.hardware_address == hardware_address)
+            .and_then(|r| self.index_of(r.address))
+    }
+
+    /// Whether slot `index` can be given to the client with `hardware_address`
+    fn is_available_to(
+        &self,
+        index: usize,
+        now: Instant,
+        hardware_address: EthernetAddress,
+    ) -> bool {
+        !self.is_reserved_for_other(index, hardware_address)
+            && self.assignments[index].is_available(now)
+    }
+
+    /// Whether slot `index` is reserved for a client other than the one with `hardware_address`
+    fn is_reserved_for_other(&self, index: usize, hardware_address: EthernetAddress) -> bool {
+        self.config.reservations.iter().any(|r| {
+            r.hardware_address != hardware_address && self.index_of(r.address) == Some(index)
+        })
+    }
+
+    /// Put `assignment` in slot `index`, freeing any other slot its client had
+    fn assign(&mut self, index: usize, assignment: Assignment) {
+        let identifier = assignment.identifier();
+        if let Some(old) = identifier.and_then(|id| self.slot_of(id)) {
+            if old != index {
+                self.assignments[old] = Assignment::Free;
+            }
+        }
+        if self.assignments[index].identifier() != identifier {
+            self.hostnames[index].clear();
+        }
+        self.assignments[index] = assignment;
+    }
+
+    /// Handle one packet from a client, sending any reply through `transport`
+    pub async fn process_packet<T: Transport>(
+        &mut self,
+        now: Instant,
+        data: &[u8],
+        transport: &mut T,
+    ) -> Result<(), Error<T::Error>> {
+        let packet = DhcpPacket::new_checked(data).map_err(Error::Packet)?;
+        let request = DhcpRepr::parse(&packet).map_err(Error::Packet)?;
+        let identifier = request
+            .client_identifier
+            .unwrap_or(request.client_hardware_address);
+        let hostname = packet
+            .options()
+            .find(|option| option.kind == OPTION_HOSTNAME)
+            .and_then(|option| core::str::from_utf8(option.data).ok())
+            .and_then(|hostname| String::try_from(hostname).ok());
+        // requests, declines and releases name the server they're for
+        let for_another_server = request
+            .server_identifier
+            .is_some_and(|server| server != self.config.server_address);
+        match request.message_type {
+            DhcpMessageType::Discover => {
+                self.process_discover(now, &request, identifier, transport)
+                    .await
+            }
+            DhcpMessageType::Request if for_another_server => {
+                // the client took another server's offer, so ours can go to someone else
+                if let Some(index) = self.slot_of(identifier) {
+                    if let Assignment::Offered { .. } = self.assignments[index] {
+                        self.assignments[index] = Assignment::Free;
+                    }
+                }
+                Ok(())
+            }
+            DhcpMessageType::Request => {
+                self.process_request(now, &request, identifier, hostname, transport)
+                    .await
+            }
+            DhcpMessageType::Decline if !for_another_server => {
+                self.process_decline(now, &request, identifier);
+                Ok(())
+            }
+            DhcpMessageType::Release if !for_another_server => {
+                self.process_release(&request, identifier);
+                Ok(())
+            }
+            DhcpMessageType::Inform => {
+                let reply = self.reply(&request, DhcpMessageType::Ack, Ipv4Address::UNSPECIFIED);
+                self.send(reply, transport).await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The slot to offer a client: its reservation if it has one, then the address it asked for
+    /// if that's free, then the one it already has, then any free or expired slot, and finally
+    /// one offered to a client that never requested it
+    fn slot_for(
+        &self,
+        now: Instant,
+        request: &DhcpRepr<'_>,
+        identifier: EthernetAddress,
+    ) -> Option<usize> {
+        let hardware_address = request.client_hardware_address;
+        if let Some(index) = self.reserved_slot(hardware_address) {
+            return Some(index);
+        }
+        let find = |f: &dyn Fn(usize) -> bool| (0..self.config.pool_size).find(|&i| f(i));
+        request
+            .requested_ip
+            .and_then(|address| self.index_of(address))
+            .filter(|&index| {
+                self.assignments[index].identifier() == Some(identifier)
+                    || self.is_available_to(index, now, hardware_address)
+            })
+            .or_else(|| self.slot_of(identifier))
+            .or_else(|| find(&|i| self.is_available_to(i, now, hardware_address)))
+            .or_else(|| {
+                find(&|i| {
+                    matches!(self.assignments[i], Assignment::Offered { .. })
+                        && !self.is_reserved_for_other(i, hardware_address)
+                })
+            })
+    }
+
+    /// A reply to `request` from this server, with `options` added to offers and acks. Replies
+    /// giving the client an address carry the lease times.
+    fn reply(
+        &self,
+        request: &DhcpRepr<'_>,
+        message_type: DhcpMessageType,
+        your_ip: Ipv4Address,
+    ) -> DhcpRepr<'a> {
+        let server_address = self.config.server_address;
+        let is_nak = message_type == DhcpMessageType::Nak;
+        let lease_time = (!your_ip.is_unspecified()).then(|| self.config.lease_time.secs() as u32);
+        DhcpRepr {
+            message_type,
+            transaction_id: request.transaction_id,
+            secs: 0,
+            client_hardware_address: request.client_hardware_address,
+            client_ip: request.client_ip,
+            your_ip,
+            server_ip: Ipv4Address::UNSPECIFIED,
+            router: (!is_nak).then_some(server_address),
+            subnet_mask: (!is_nak).then_some(self.config.subnet_mask),
+            relay_agent_ip: Ipv4Address::UNSPECIFIED,
+            broadcast: false,
+            requested_ip: None,
+            client_identifier: None,
+            server_identifier: Some(server_address),
+            parameter_request_list: None,
+            dns_servers: (!is_nak).then(|| Vec::from_slice(&[server_address]).unwrap()),
+            max_size: None,
+            // the defaults from RFC 2131
+            lease_duration: lease_time,
+            renew_duration: lease_time.map(|t| t / 2),
+            rebind_duration: lease_time.map(|t| (t as u64 * 7 / 8) as u32),
+            additional_options: if is_nak { &[] } else { self.config.options },
+        }
+    }
+
+    async fn send<T: Transport>(
+        &mut self,
+        reply: DhcpRepr<'_>,
+        transport: &mut T,
+    ) -> Result<(), Error<T::Error>> {
+        // smoltcp reads the renewal and rebinding times, but doesn't write them
+        let renew = reply.renew_duration.map(u32::to_be_bytes);
+        let rebind = reply.rebind_duration.map(u32::to_be_bytes);
+        let times = [
+            (OPTION_RENEWAL_TIME, &renew),
+            (OPTION_REBINDING_TIME, &rebind),
+        ];
+        let mut options: Vec<DhcpOption<'_>, MAX_OPTIONS> = times
+            .into_iter()
+            .filter_map(|(kind, data)| {
+                Some(DhcpOption {
+                    kind,
+                    data: data.as_ref()?,
+                })
+            })
+            .collect();
+        options
+            .extend_from_slice(reply.additional_options)
+            .map_err(|_| Error::Packet(smoltcp::wire::Error))?;
+        let reply = DhcpRepr {
+            additional_options: &options,
+            ..reply
+        };
+
+        let len = reply.buffer_len();
+        let buffer = self
+            .reply
+            .get_mut(..len)
+            .ok_or(Error::Packet(smoltcp::wire::Error))?;
+        buffer.fill(0);
+        reply
+            .emit(&mut DhcpPacket::new_unchecked(&mut *buffer))
+            .map_err(Error::Packet)?;
+        transport
+            .send(&self.reply[..len])
+            .await
+            .map_err(Error::Transport)
+    }
+
+    async fn process_discover<T: Transport>(
+        &mut self,
+        now: Instant,
+        request: &DhcpRepr<'_>,
+        identifier: EthernetAddress,
+        transport: &mut T,
+    ) -> Result<(), Error<T::Error>> {
+        let Some(index) = self.slot_for(now, request, identifier) else {
+            log::warn!("No addresses left to offer {}", identifier);
+            return Ok(());
+        };
+        // a running lease stays in place until the client asks for something else
+        let is_leased = matches!(
+            self.assignments[index],
+            Assignment::Assigned { identifier: leased_to, lease_end } if leased_to == identifier && now < lease_end
+        );
+        if !is_leased {
+            self.assign(index, Assignment::Offered { identifier });
+        }
+        let reply = self.reply(request, DhcpMessageType::Offer, self.address(index));
+        self.send(reply, transport).await
+    }
+
+    /// Ack a request for the address offered or leased to the client, or for a free address in
+    /// the pool when the client isn't selecting an offer (as after this server restarts), and NAK
+    /// anything else. The transaction ID isn't checked, as renewals start new transactions.
+    /// Clients with a reservation are NAKed for any other address, so they come back for it.
+    async fn process_request<T: Transport>(
+        &mut self,
+        now: Instant,
+        request: &DhcpRepr<'_>,
+        identifier: EthernetAddress,
+        hostname: Option<String<MAX_HOSTNAME_LEN>>,
+        transport: &mut T,
+    ) -> Result<(), Error<T::Error>> {
+        // selecting and rebooting clients ask for an address, renewing and rebinding ones
+        // already have it
+        let requested = request
+            .requested_ip
+            .or((!request.client_ip.is_unspecified()).then_some(request.client_ip));
+        let selecting = request.server_identifier.is_some();
+        let hardware_address = request.client_hardware_address;
+        let reserved = self.reserved_slot(hardware_address);
+        let index = requested
+            .and_then(|address| self.index_of(address))
+            .filter(|&index| reserved.is_none_or(|reserved| reserved == index))
+            .filter(|&index| {
+                self.assignments[index].identifier() == Some(identifier)
+                    || (!selecting && self.is_available_to(index, now, hardware_address))
+            });
+
+        let reply = match index {
+            Some(index) => {
+                self.assign(
+                    index,
+                    Assignment::Assigned {
+                        identifier,
+                        lease_end: now + self.config.lease_time,
+                    },
+                );
+                if let Some(hostname) = hostname {
+                    self.hostnames[index] = hostname;
+                }
+                self.reply(request, DhcpMessageType::Ack, self.address(index))
+            }
+            None => {
+                log::info!("Refusing a request for {:?} from {}", requested, identifier);
+                self.reply(request, DhcpMessageType::Nak, Ipv4Address::UNSPECIFIED)
+            }
+        };
+        self.send(reply, transport).await
+    }
+
+    /// The client found the address it was given already in use, so keep it out of the pool
+    /// for a while
+    fn process_decline(
+        &mut self,
+        now: Instant,
+        request: &DhcpRepr<'_>,
+        identifier: EthernetAddress,
+    ) {
+        let index = request
+            .requested_ip
+            .and_then(|address| self.index_of(address))
+            .filter(|&index| self.assignments[index].identifier() == Some(identifier));
+        if let Some(index) = index {
+            log::warn!("{} declined {}", identifier, self.address(index));
+            self.assignments[index] = Assignment::Declined {
+                until: now + DECLINE_TIME,
+            };
+        }
+    }
+
+    /// The client has finished with its address
+    fn process_release(&mut self, request: &DhcpRepr<'_>, identifier: EthernetAddress) {
+        let index = self
+            .index_of(request.client_ip)
+            .filter(|&index| self.assignments[index].identifier() == Some(identifier));
+        if let Some(index) = index {
+            self.assignments[index] = Assignment::Free;
+        }
+    }
+}
@@ -0,0 +1,603 @@
+use core::future::Future;
+use core::pin::pin;
+use core::task::{Context, Poll, Waker};
+
+use dhcp_state::{Assignment, DhcpServer, Lease, Reservation, ServerConfig, Transport};
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::{
+    DhcpMessageType, DhcpOption, DhcpPacket, DhcpRepr, EthernetAddress, Ipv4Address,
+};
+
+const SERVER: Ipv4Address = Ipv4Address([169, 254, 1, 1]);
+const SUBNET_MASK: Ipv4Address = Ipv4Address([255, 255, 255, 0]);
+const POOL_START: Ipv4Address = Ipv4Address([169, 254, 1, 2]);
+const LEASE_TIME: Duration = Duration::from_secs(60);
+const ALICE: EthernetAddress = EthernetAddress([2, 0, 0, 0, 0, 1]);
+const BOB: EthernetAddress = EthernetAddress([2, 0, 0, 0, 0, 2]);
+
+/// Runs a future that never waits, as the transport below never does
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    match future
+        .as_mut()
+        .poll(&mut Context::from_waker(Waker::noop()))
+    {
+        Poll::Ready(output) => output,
+        Poll::Pending => panic!("the future waited"),
+    }
+}
+
+/// Keeps every packet the server sends
+#[derive(Default)]
+struct Recorder {
+    sent: Vec<Vec<u8>>,
+}
+
+impl Transport for Recorder {
+    type Error = ();
+
+    async fn receive(&mut self, _buffer: &mut [u8]) -> Result<usize, ()> {
+        Err(())
+    }
+
+    async fn send(&mut self, data: &[u8]) -> Result<(), ()> {
+        self.sent.push(data.to_vec());
+        Ok(())
+    }
+}
+
+/// The parts of a reply the tests look at
+#[derive(Debug, PartialEq)]
+struct Reply {
+    message_type: DhcpMessageType,
+    transaction_id: u32,
+    your_ip: Ipv4Address,
+    /// the lease, renewal and rebinding times
+    lease_times: Option<(u32, u32, u32)>,
+}
+
+const LEASE_TIMES: Option<(u32, u32, u32)> = Some((60, 30, 52));
+
+fn offer(transaction_id: u32, your_ip: Ipv4Address) -> Option<Reply> {
+    Some(Reply {
+        message_type: DhcpMessageType::Offer,
+        transaction_id,
+        your_ip,
+        lease_times: LEASE_TIMES,
+    })
+}
+
+fn ack(transaction_id: u32, your_ip: Ipv4Address) -> Option<Reply> {
+    Some(Reply {
+        message_type: DhcpMessageType::Ack,
+        transaction_id,
+        your_ip,
+        lease_times: LEASE_TIMES,
+    })
+}
+
+fn nak(transaction_id: u32) -> Option<Reply> {
+    Some(Reply {
+        message_type: DhcpMessageType::Nak,
+        transaction_id,
+        your_ip: Ipv4Address::UNSPECIFIED,
+        lease_times: None,
+    })
+}
+
+/// A packet from `client`, with no addresses or options
+fn packet(
+    message_type: DhcpMessageType,
+    transaction_id: u32,
+    client: EthernetAddress,
+) -> DhcpRepr<'static> {
+    DhcpRepr {
+        message_type,
+        transaction_id,
+        secs: 0,
+        client_hardware_address: client,
+        client_ip: Ipv4Address::UNSPECIFIED,
+        your_ip: Ipv4Address::UNSPECIFIED,
+        server_ip: Ipv4Address::UNSPECIFIED,
+        router: None,
+        subnet_mask: None,
+        relay_agent_ip: Ipv4Address::UNSPECIFIED,
+        broadcast: true,
+        requested_ip: None,
+        client_identifier: None,
+        server_identifier: None,
+        parameter_request_list: None,
+        dns_servers: None,
+        max_size: None,
+        lease_duration: None,
+        renew_duration: None,
+        rebind_duration: None,
+        additional_options: &[],
+    }
+}
+
+struct Harness<const N: usize> {
+    server: DhcpServer<'static, N>,
+    transport: Recorder,
+}
+
+impl<const N: usize> Harness<N> {
+    /// A server using all `N` addresses
+    fn new() -> Self {
+        Self::with_pool(N, &[])
+    }
+
+    fn with_pool(pool_size: usize, reservations: &'static [Reservation]) -> Self {
+        Self {
+            server: DhcpServer::new(ServerConfig {
+                server_address: SERVER,
+                subnet_mask: SUBNET_MASK,
+                pool_start: POOL_START,
+                pool_size,
+                lease_time: LEASE_TIME,
+                reservations,
+                options: &[],
+            }),
+            transport: Recorder::default(),
+        }
+    }
+
+    /// Send `request` at `seconds`, returning the reply if there is one
+    fn send(&mut self, seconds: u64, request: DhcpRepr<'_>) -> Option<Reply> {
+        let mut data = vec![0; request.buffer_len()];
+        request
+            .emit(&mut DhcpPacket::new_unchecked(&mut data[..]))
+            .unwrap();
+
+        let sent = self.transport.sent.len();
+        block_on(self.server.process_packet(
+            Instant::from_secs(seconds as i64),
+            &data,
+            &mut self.transport,
+        ))
+        .unwrap();
+        assert!(self.transport.sent.len() <= sent + 1, "more than one reply");
+
+        let data = self.transport.sent.get(sent)?;
+        let packet = DhcpPacket::new_checked(&data[..]).unwrap();
+        let reply = DhcpRepr::parse(&packet).unwrap();
+        assert_eq!(
+            reply.client_hardware_address,
+            request.client_hardware_address
+        );
+        assert_eq!(reply.server_identifier, Some(SERVER));
+        if reply.message_type != DhcpMessageType::Nak {
+            assert_eq!(reply.subnet_mask, Some(SUBNET_MASK));
+        }
+        let lease_times = match (
+            reply.lease_duration,
+            reply.renew_duration,
+            reply.rebind_duration,
+        ) {
+            (Some(lease), Some(renew), Some(rebind)) => Some((lease, renew, rebind)),
+            (None, None, None) => None,
+            times => panic!("only some of the lease times were sent: {times:?}"),
+        };
+        Some(Reply {
+            message_type: reply.message_type,
+            transaction_id: reply.transaction_id,
+            your_ip: reply.your_ip,
+            lease_times,
+        })
+    }
+
+    fn discover(
+        &mut self,
+        seconds: u64,
+        transaction_id: u32,
+        client: EthernetAddress,
+    ) -> Option<Reply> {
+        self.send(
+            seconds,
+            packet(DhcpMessageType::Discover, transaction_id, client),
+        )
+    }
+
+    /// Request an address offered by this server
+    fn select(
+        &mut self,
+        seconds: u64,
+        transaction_id: u32,
+        client: EthernetAddress,
+        address: Ipv4Address,
+    ) -> Option<Reply> {
+        self.send(
+            seconds,
+            DhcpRepr {
+                requested_ip: Some(address),
+                server_identifier: Some(SERVER),
+                ..packet(DhcpMessageType::Request, transaction_id, client)
+            },
+        )
+    }
+
+    /// Extend the lease on an address the client is using
+    fn renew(
+        &mut self,
+        seconds: u64,
+        transaction_id: u32,
+        client: EthernetAddress,
+        address: Ipv4Address,
+    ) -> Option<Reply> {
+        self.send(
+            seconds,
+            DhcpRepr {
+                client_ip: address,
+                ..packet(DhcpMessageType::Request, transaction_id, client)
+            },
+        )
+    }
+
+    /// Ask to keep using an address after restarting
+    fn reboot(
+        &mut self,
+        seconds: u64,
+        transaction_id: u32,
+        client: EthernetAddress,
+        address: Ipv4Address,
+    ) -> Option<Reply> {
+        self.send(
+            seconds,
+            DhcpRepr {
+                requested_ip: Some(address),
+                ..packet(DhcpMessageType::Request, transaction_id, client)
+            },
+        )
+    }
+
+    /// Discover and select an address, checking it's leased
+    fn lease(&mut self, seconds: u64, transaction_id: u32, client: EthernetAddress) -> Ipv4Address {
+        let offer = self.discover(seconds, transaction_id, client).unwrap();
+        assert_eq!(offer.message_type, DhcpMessageType::Offer);
+        assert_eq!(
+            self.select(seconds, transaction_id, client, offer.your_ip),
+            ack(transaction_id, offer.your_ip)
+        );
+        offer.your_ip
+    }
+}
+
+#[test]
+fn offers_then_acks_the_first_address() {
+    let mut harness = Harness::<4>::new();
+    assert_eq!(harness.discover(0, 7, ALICE), offer(7, POOL_START));
+    assert_eq!(
+        harness.server.assignments()[0],
+        Assignment::Offered { identifier: ALICE }
+    );
+
+    assert_eq!(harness.select(5, 7, ALICE, POOL_START), ack(7, POOL_START));
+    assert_eq!(
+        harness.server.assignments()[0],
+        Assignment::Assigned {
+            identifier: ALICE,
+            lease_end: Instant::from_secs(5) + LEASE_TIME,
+        }
+    );
+}
+
+#[test]
+fn clients_get_different_addresses() {
+    let mut harness = Harness::<4>::new();
+    assert_eq!(harness.lease(0, 1, ALICE), POOL_START);
+    assert_eq!(harness.lease(0, 2, BOB), harness.server.address(1));
+}
+
+#[test]
+fn naks_selecting_an_address_that_was_not_offered() {
+    let mut harness = Harness::<4>::new();
+    assert_eq!(harness.select(0, 1, ALICE, POOL_START), nak(1));
+    assert!(harness
+        .server
+        .assignments()
+        .iter()
+        .all(|a| *a == Assignment::Free));
+}
+
+#[test]
+fn naks_selecting_another_clients_offer() {
+    let mut harness = Harness::<4>::new();
+    harness.discover(0, 1, ALICE).unwrap();
+    assert_eq!(harness.select(0, 1, BOB, POOL_START), nak(1));
+    assert_eq!(harness.server.assignments()[0].identifier(), Some(ALICE));
+}
+
+#[test]
+fn selecting_another_servers_offer_frees_ours() {
+    let mut harness = Harness::<4>::new();
+    harness.discover(0, 1, ALICE).unwrap();
+    let request = DhcpRepr {
+        requested_ip: Some(Ipv4Address::new(192, 168, 0, 10)),
+        server_identifier: Some(Ipv4Address::new(192, 168, 0, 1)),
+        ..packet(DhcpMessageType::Request, 1, ALICE)
+    };
+    assert_eq!(harness.send(0, request), None);
+    assert_eq!(harness.server.assignments()[0], Assignment::Free);
+}
+
+#[test]
+fn rediscovering_keeps_the_address() {
+    let mut harness = Harness::<4>::new();
+    harness.lease(0, 1, ALICE);
+    harness.lease(0, 2, BOB);
+    assert_eq!(harness.lease(10, 3, ALICE), POOL_START);
+}
+
+#[test]
+fn offers_the_requested_address_if_it_is_free() {
+    let mut harness = Harness::<4>::new();
+    harness.lease(0, 1, ALICE);
+    let wanted = harness.server.address(2);
+    let discover = |address| DhcpRepr {
+        requested_ip: Some(address),
+        ..packet(DhcpMessageType::Discover, 2, BOB)
+    };
+    assert_eq!(harness.send(0, discover(wanted)), offer(2, wanted));
+    // Alice has the first address, so Bob keeps the address already offered
+    assert_eq!(harness.send(0, discover(POOL_START)), offer(2, wanted));
+}
+
+#[test]
+fn renewals_start_new_transactions() {
+    let mut harness = Harness::<4>::new();
+    harness.lease(0, 1, ALICE);
+    assert_eq!(
+        harness.renew(30, 99, ALICE, POOL_START),
+        ack(99, POOL_START)
+    );
+    assert_eq!(
+        harness.server.assignments()[0],
+        Assignment::Assigned {
+            identifier: ALICE,
+            lease_end: Instant::from_secs(30) + LEASE_TIME,
+        }
+    );
+}
+
+#[test]
+fn naks_renewing_another_clients_address() {
+    let mut harness = Harness::<4>::new();
+    harness.lease(0, 1, ALICE);
+    assert_eq!(harness.renew(30, 2, BOB, POOL_START), nak(2));
+}
+
+#[test]
+fn expired_leases_are_reused() {
+    let mut harness = Harness::<1>::new();
+    harness.lease(0, 1, ALICE);
+    assert_eq!(harness.discover(59, 2, BOB), None);
+    assert_eq!(harness.lease(60, 2, BOB), POOL_START);
+    assert_eq!(harness.server.assignments()[0].identifier(), Some(BOB));
+    assert_eq!(harness.renew(61, 3, ALICE, POOL_START), nak(3));
+}
+
+#[test]
+fn expired_leases_can_be_renewed_until_reused() {
+    let mut harness = Harness::<1>::new();
+    harness.lease(0, 1, ALICE);
+    assert_eq!(harness.renew(90, 2, ALICE, POOL_START), ack(2, POOL_START));
+}
+
+#[test]
+fn rebooting_clients_keep_their_address_after_the_server_restarts() {
+    let mut harness = Harness::<4>::new();
+    let address = harness.server.address(3);
+    assert_eq!(harness.reboot(0, 1, ALICE, address), ack(1, address));
+    assert_eq!(harness.server.assignments()[3].identifier(), Some(ALICE));
+
+    assert_eq!(harness.reboot(0, 2, BOB, address), nak(2));
+    assert_eq!(
+        harness.reboot(0, 3, BOB, Ipv4Address::new(192, 168, 0, 10)),
+        nak(3)
+    );
+}
+
+#[test]
+fn offers_nothing_when_the_pool_is_full() {
+    let mut harness = Harness::<2>::new();
+    harness.lease(0, 1, ALICE);
+    harness.lease(0, 2, BOB);
+    assert_eq!(
+        harness.discover(0, 3, EthernetAddress([2, 0, 0, 0, 0, 3])),
+        None
+    );
+}
+
+#[test]
+fn unrequested_offers_are_taken_over() {
+    let mut harness = Harness::<1>::new();
+    harness.discover(0, 1, ALICE).unwrap();
+    assert_eq!(harness.lease(0, 2, BOB), POOL_START);
+    assert_eq!(harness.select(0, 1, ALICE, POOL_START), nak(1));
+}
+
+#[test]
+fn client_identifiers_take_precedence() {
+    let mut harness = Harness::<4>::new();
+    let discover = |client, transaction_id| DhcpRepr {
+        client_identifier: Some(BOB),
+        ..packet(DhcpMessageType::Discover, transaction_id, client)
+    };
+    assert_eq!(harness.send(0, discover(ALICE, 1)), offer(1, POOL_START));
+    assert_eq!(harness.server.assignments()[0].identifier(), Some(BOB));
+
+    // the same client identifier from other hardware is the same client
+    let other = EthernetAddress([2, 0, 0, 0, 0, 3]);
+    assert_eq!(harness.send(0, discover(other, 2)), offer(2, POOL_START));
+}
+
+#[test]
+fn released_addresses_are_freed() {
+    let mut harness = Harness::<4>::new();
+    harness.lease(0, 1, ALICE);
+    let release = |client| DhcpRepr {
+        client_ip: POOL_START,
+        server_identifier: Some(SERVER),
+        ..packet(DhcpMessageType::Release, 2, client)
+    };
+    assert_eq!(harness.send(10, release(BOB)), None);
+    assert_eq!(harness.server.assignments()[0].identifier(), Some(ALICE));
+    assert_eq!(harness.send(10, release(ALICE)), None);
+    assert_eq!(harness.server.assignments()[0], Assignment::Free);
+}
+
+#[test]
+fn declined_addresses_are_kept_out_of_the_pool() {
+    let mut harness = Harness::<1>::new();
+    harness.lease(0, 1, ALICE);
+    let decline = DhcpRepr {
+        requested_ip: Some(POOL_START),
+        server_identifier: Some(SERVER),
+        ..packet(DhcpMessageType::Decline, 1, ALICE)
+    };
+    assert_eq!(harness.send(0, decline), None);
+    assert_eq!(
+        harness.server.assignments()[0],
+        Assignment::Declined {
+            until: Instant::from_secs(600)
+        }
+    );
+    assert_eq!(harness.discover(599, 2, BOB), None);
+    assert_eq!(harness.reboot(599, 3, BOB, POOL_START), nak(3));
+    assert_eq!(harness.discover(600, 4, BOB), offer(4, POOL_START));
+}
+
+#[test]
+fn informs_are_acked_without_an_address() {
+    let mut harness = Harness::<4>::new();
+    let inform = DhcpRepr {
+        client_ip: Ipv4Address::new(169, 254, 1, 200),
+        ..packet(DhcpMessageType::Inform, 1, ALICE)
+    };
+    assert_eq!(
+        harness.send(0, inform),
+        Some(Reply {
+            message_type: DhcpMessageType::Ack,
+            transaction_id: 1,
+            your_ip: Ipv4Address::UNSPECIFIED,
+            lease_times: None,
+        })
+    );
+    assert!(harness
+        .server
+        .assignments()
+        .iter()
+        .all(|a| *a == Assignment::Free));
+}
+
+#[test]
+fn ignores_garbage() {
+    let mut harness = Harness::<4>::new();
+    let result = block_on(harness.server.process_packet(
+        Instant::from_secs(0),
+        &[0; 10],
+        &mut harness.transport,
+    ));
+    assert!(result.is_err());
+    assert!(harness.transport.sent.is_empty());
+}
+
+#[test]
+fn the_pool_can_be_smaller_than_the_server() {
+    let mut harness = Harness::<4>::with_pool(2, &[]);
+    assert_eq!(harness.server.assignments().len(), 2);
+    harness.lease(0, 1, ALICE);
+    harness.lease(0, 2, BOB);
+    assert_eq!(
+        harness.discover(0, 3, EthernetAddress([2, 0, 0, 0, 0, 3])),
+        None
+    );
+    let outside = harness.server.address(3);
+    assert_eq!(harness.reboot(0, 4, ALICE, outside), nak(4));
+}
+
+const RESERVED: Ipv4Address = Ipv4Address([169, 254, 1, 3]);
+const RESERVATIONS: &[Reservation] = &[Reservation {
+    hardware_address: BOB,
+    address: RESERVED,
+}];
+
+#[test]
+fn reserved_addresses_go_to_their_client() {
+    let mut harness = Harness::<4>::with_pool(4, RESERVATIONS);
+    assert_eq!(harness.discover(0, 1, BOB), offer(1, RESERVED));
+
+    // even when it asks for another address
+    let discover = DhcpRepr {
+        requested_ip: Some(POOL_START),
+        ..packet(DhcpMessageType::Discover, 2, BOB)
+    };
+    assert_eq!(harness.send(0, discover), offer(2, RESERVED));
+    assert_eq!(harness.select(0, 2, BOB, RESERVED), ack(2, RESERVED));
+}
+
+#[test]
+fn reserved_addresses_are_kept_from_other_clients() {
+    let mut harness = Harness::<2>::with_pool(2, RESERVATIONS);
+    assert_eq!(harness.lease(0, 1, ALICE), POOL_START);
+    let carol = EthernetAddress([2, 0, 0, 0, 0, 3]);
+    assert_eq!(harness.discover(0, 2, carol), None);
+    assert_eq!(harness.reboot(0, 3, carol, RESERVED), nak(3));
+
+    // and an unrequested offer of the reserved address isn't taken over
+    assert_eq!(harness.discover(0, 4, BOB), offer(4, RESERVED));
+    assert_eq!(harness.discover(0, 5, carol), None);
+}
+
+#[test]
+fn clients_with_a_reservation_are_moved_onto_it() {
+    let mut harness = Harness::<4>::with_pool(4, RESERVATIONS);
+    assert_eq!(harness.renew(0, 1, BOB, POOL_START), nak(1));
+    assert_eq!(harness.reboot(0, 2, BOB, RESERVED), ack(2, RESERVED));
+}
+
+#[test]
+fn leases_list_running_leases_with_hostnames() {
+    let mut harness = Harness::<4>::new();
+    harness.discover(0, 1, ALICE).unwrap();
+    let select = DhcpRepr {
+        requested_ip: Some(POOL_START),
+        server_identifier: Some(SERVER),
+        additional_options: &[DhcpOption {
+            kind: 12,
+            data: b"laptop",
+        }],
+        ..packet(DhcpMessageType::Request, 1, ALICE)
+    };
+    assert_eq!(harness.send(0, select), ack(1, POOL_START));
+    harness.lease(10, 2, BOB);
+    // offered addresses aren't leased yet
+    harness
+        .discover(10, 3, EthernetAddress([2, 0, 0, 0, 0, 3]))
+        .unwrap();
+
+    let leases: Vec<_> = harness.server.leases(Instant::from_secs(30)).collect();
+    assert_eq!(
+        leases,
+        [
+            Lease {
+                address: POOL_START,
+                identifier: ALICE,
+                hostname: "laptop",
+                lease_end: Instant::from_secs(60),
+            },
+            Lease {
+                address: harness.server.address(1),
+                identifier: BOB,
+                hostname: "",
+                lease_end: Instant::from_secs(70),
+            },
+        ]
+    );
+
+    // renewing without a hostname keeps the old one, and expired leases aren't listed
+    assert_eq!(harness.renew(20, 4, ALICE, POOL_START), ack(4, POOL_START));
+    let leases: Vec<_> = harness.server.leases(Instant::from_secs(75)).collect();
+    assert_eq!(leases.len(), 1);
+    assert_eq!(leases[0].hostname, "laptop");
+}
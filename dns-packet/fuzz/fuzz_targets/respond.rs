@@ -0,0 +1,45 @@
+//! `cargo +nightly fuzz run respond` from `dns-packet`, for a longer search than `tests/fuzz.rs`
+
+#![no_main]
+
+use dns_packet::mdns::{MdnsResponder, Service};
+use dns_packet::{DnsPacket, Responder};
+use libfuzzer_sys::fuzz_target;
+use smoltcp::wire::Ipv4Address;
+
+fuzz_target!(|packet: &[u8]| {
+    if let Some(parsed) = DnsPacket::new_checked(packet) {
+        for i in 0..parsed.header().question_count().min(16) {
+            if let Some(question) = parsed.question(i) {
+                question.matches("picohttp.piconet.local");
+            }
+        }
+    }
+
+    let responder = Responder {
+        names: &["picohttp", "picohttp.piconet.local"],
+        address: Ipv4Address::new(169, 254, 1, 1),
+        wildcard: None,
+        ttl: 60,
+    };
+    let mut buffer = [0; 512];
+    if let Some(response) = responder.respond(packet, &mut buffer) {
+        assert!(response.len() >= 12 && response[..2] == packet[..2]);
+    }
+
+    let mdns = MdnsResponder {
+        hostname: "pico-matrix",
+        address: Ipv4Address::new(192, 168, 1, 20),
+        services: &[Service {
+            service_type: "_http._tcp",
+            port: 80,
+            txt: &["version=0.1.0"],
+        }],
+    };
+    let mut buffer = [0; 1500];
+    for legacy in [false, true] {
+        if let Some(response) = mdns.respond(packet, legacy, &mut buffer) {
+            assert!(response.len() >= 12);
+        }
+    }
+});
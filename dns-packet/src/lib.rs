@@ -0,0 +1,419 @@
+//! Reading DNS queries and writing the responses from the access point's DNS server and the
+//! multicast DNS responder, kept apart from the network stack so they can be tested on the host
+
+#![no_std]
+
+pub mod mdns;
+
+use heapless::Vec;
+use smoltcp::wire::Ipv4Address;
+
+/// a DNS header is 12 bytes
+const DNS_HEADER_SIZE: usize = 12;
+/// type, class, TTL and data length
+const RECORD_FIELDS_SIZE: usize = 10;
+/// a pointer to the question's name, then the fields and an IPv4 address
+const A_RECORD_SIZE: usize = 2 + RECORD_FIELDS_SIZE + 4;
+/// the root name, then the fields with no options
+const OPT_RECORD_SIZE: usize = 1 + RECORD_FIELDS_SIZE;
+/// the largest response allowed without EDNS
+const CLASSIC_UDP_SIZE: usize = 512;
+/// queries with more questions than this are refused
+const MAX_QUESTIONS: usize = 8;
+/// names are at most 255 bytes, so this many pointers is more than any real name needs
+const MAX_POINTERS: usize = 16;
+
+const TYPE_A: u16 = 1;
+const TYPE_OPT: u16 = 41;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+const CLASS_ANY: u16 = 255;
+
+const RCODE_FORMERR: u8 = 1;
+const RCODE_NXDOMAIN: u8 = 3;
+const RCODE_NOTIMP: u8 = 4;
+/// EDNS version not supported, in the OPT record's extended RCODE
+const EXTENDED_RCODE_BADVERS: u8 = 1;
+
+fn read_u16(buffer: &[u8], offset: usize) -> Option<u16> {
+    let bytes = buffer.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+/// The length of the name at the start of `buffer`, which ends with an empty label or a
+/// compression pointer
+fn name_length(buffer: &[u8]) -> Option<usize> {
+    let mut length = 0;
+    loop {
+        let size = *buffer.get(length)?;
+        match size {
+            0 => return Some(length + 1),
+            1..=63 => length += 1 + size as usize,
+            0xc0..=0xff => return (length + 2 <= buffer.len()).then_some(length + 2),
+            _ => return None,
+        }
+    }
+}
+
+/// The length of the resource record at the start of `buffer`, and its type
+fn record_length(buffer: &[u8]) -> Option<(usize, u16)> {
+    let name = name_length(buffer)?;
+    let record_type = read_u16(buffer, name)?;
+    let data_length = read_u16(buffer, name + 8)? as usize;
+    let length = name + RECORD_FIELDS_SIZE + data_length;
+    (length <= buffer.len()).then_some((length, record_type))
+}
+
+/// Whether the name at `offset` in `packet` is exactly `parts` joined with dots, ignoring case as
+/// DNS does. A trailing dot on a part is ignored.
+fn name_matches(packet: &[u8], offset: usize, parts: &[&str]) -> bool {
+    let mut expected = parts
+        .iter()
+        .map(|part| part.strip_suffix('.').unwrap_or(part))
+        .filter(|part| !part.is_empty())
+        .flat_map(|part| part.split('.'));
+    let mut labels = Labels::new(packet, offset);
+    loop {
+        match (labels.next(), expected.next()) {
+            (None, None) => return true,
+            (Some(Ok(label)), Some(expected))
+                if label.eq_ignore_ascii_case(expected.as_bytes()) => {}
+            _ => return false,
+        }
+    }
+}
+
+/// The labels of the name at `offset` in `packet`, following compression pointers
+struct Labels<'a> {
+    packet: &'a [u8],
+    offset: usize,
+    pointers: usize,
+    finished: bool,
+}
+
+impl<'a> Labels<'a> {
+    fn new(packet: &'a [u8], offset: usize) -> Self {
+        Self {
+            packet,
+            offset,
+            pointers: 0,
+            finished: false,
+        }
+    }
+}
+
+impl<'a> Iterator for Labels<'a> {
+    type Item = Result<&'a [u8], ()>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.finished {
+            let Some(&size) = self.packet.get(self.offset) else {
+                break;
+            };
+            match size {
+                0 => {
+                    self.finished = true;
+                    return None;
+                }
+                1..=63 => {
+                    let start = self.offset + 1;
+                    let label = self.packet.get(start..start + size as usize);
+                    self.offset = start + size as usize;
+                    if label.is_some() {
+                        return label.map(Ok);
+                    }
+                    break;
+                }
+                0xc0..=0xff => {
+                    // a pointer that loops would otherwise never finish
+                    self.pointers += 1;
+                    let Some(&low) = self.packet.get(self.offset + 1) else {
+                        break;
+                    };
+                    if self.pointers > MAX_POINTERS {
+                        break;
+                    }
+                    self.offset = ((size as usize & 0x3f) << 8) | low as usize;
+                }
+                _ => break,
+            }
+        }
+        if self.finished {
+            return None;
+        }
+        self.finished = true;
+        Some(Err(()))
+    }
+}
+
+pub struct DnsHeader<'a> {
+    buffer: &'a [u8],
+}
+impl<'a> DnsHeader<'a> {
+    /// try to create a dns header from a buffer
+    pub fn new_checked(buffer: &'a [u8]) -> Option<Self> {
+        if buffer.len() < DNS_HEADER_SIZE {
+            None
+        } else {
+            Some(Self { buffer })
+        }
+    }
+
+    pub fn is_query(&self) -> bool {
+        (self.buffer[2] >> 7 & 1) == 0
+    }
+
+    pub fn is_standard_query(&self) -> bool {
+        (self.buffer[2] >> 3 & 0xf) == 0
+    }
+
+    pub fn recursion_desired(&self) -> bool {
+        self.buffer[2] & 1 == 1
+    }
+
+    pub fn question_count(&self) -> u16 {
+        ((self.buffer[4] as u16) << 8) | self.buffer[5] as u16
+    }
+
+    /// the number of answer and authority records
+    pub fn record_count(&self) -> u16 {
+        u16::from_be_bytes([self.buffer[6], self.buffer[7]])
+            .saturating_add(u16::from_be_bytes([self.buffer[8], self.buffer[9]]))
+    }
+
+    pub fn additional_count(&self) -> u16 {
+        u16::from_be_bytes([self.buffer[10], self.buffer[11]])
+    }
+}
+
+pub struct DnsQuestion<'a> {
+    packet: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> DnsQuestion<'a> {
+    /// The question at `offset` in `packet`, if it fits
+    pub fn new_checked(packet: &'a [u8], offset: usize) -> Option<Self> {
+        Self::length(packet.get(offset..)?).map(|_| Self { packet, offset })
+    }
+
+    /// Given a buffer, calculate the length of the question part of the packet in bytes
+    pub fn length(buffer: &[u8]) -> Option<usize> {
+        let length = name_length(buffer)? + 4;
+        (length <= buffer.len()).then_some(length)
+    }
+
+    /// where the question starts in the packet
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    fn fields(&self) -> &'a [u8] {
+        let question = &self.packet[self.offset..];
+        &question[name_length(question).unwrap_or_default()..]
+    }
+
+    pub fn question_type(&self) -> u16 {
+        read_u16(self.fields(), 0).unwrap_or_default()
+    }
+
+    pub fn question_class(&self) -> u16 {
+        read_u16(self.fields(), 2).unwrap_or_default()
+    }
+
+    /// Whether the question is for exactly `name`, ignoring case as DNS does. A trailing dot on
+    /// `name` is ignored.
+    pub fn matches(&self, name: &str) -> bool {
+        name_matches(self.packet, self.offset, &[name])
+    }
+
+    /// Whether the question is for `parts` joined with dots, as [`Self::matches`] does
+    pub(crate) fn matches_parts(&self, parts: &[&str]) -> bool {
+        name_matches(self.packet, self.offset, parts)
+    }
+}
+
+pub struct DnsPacket<'a> {
+    buffer: &'a [u8],
+}
+
+impl<'a> DnsPacket<'a> {
+    pub fn new_checked(buffer: &'a [u8]) -> Option<Self> {
+        DnsHeader::new_checked(buffer).map(|_| Self { buffer })
+    }
+
+    pub fn header(&self) -> DnsHeader<'a> {
+        DnsHeader {
+            buffer: self.buffer,
+        }
+    }
+
+    /// get the `question`th question from this packet, counting from zero
+    pub fn question(&self, question: u16) -> Option<DnsQuestion<'a>> {
+        if question >= self.header().question_count() {
+            return None;
+        }
+        let mut offset = DNS_HEADER_SIZE;
+        for _ in 0..question {
+            offset += DnsQuestion::length(self.buffer.get(offset..)?)?;
+        }
+        DnsQuestion::new_checked(self.buffer, offset)
+    }
+}
+
+/// Answers queries for the device's names, and for every other name with a wildcard address or
+/// NXDOMAIN
+pub struct Responder<'a> {
+    /// the names answered with `address`
+    pub names: &'a [&'a str],
+    pub address: Ipv4Address,
+    /// the address for any other name, or `None` to say they don't exist
+    pub wildcard: Option<Ipv4Address>,
+    /// how long resolvers can cache the answers for, in seconds
+    pub ttl: u32,
+}
+
+impl Responder<'_> {
+    /// Write the response to `query` into `response`, returning it if there is one. Only A
+    /// records are answered; other types get an empty answer, so clients fall back to IPv4.
+    pub fn respond<'r>(&self, query: &[u8], response: &'r mut [u8]) -> Option<&'r [u8]> {
+        let header = DnsHeader::new_checked(query)?;
+        if !header.is_query() || response.len() < DNS_HEADER_SIZE {
+            return None;
+        }
+        if !header.is_standard_query() {
+            return Some(Self::error(query, response, RCODE_NOTIMP));
+        }
+        match self.answer(query, response) {
+            Some(length) => Some(&response[..length]),
+            None => Some(Self::error(query, response, RCODE_FORMERR)),
+        }
+    }
+
+    /// A response with no records, for a query that can't be answered
+    fn error<'r>(query: &[u8], response: &'r mut [u8], rcode: u8) -> &'r [u8] {
+        response[..DNS_HEADER_SIZE].fill(0);
+        response[..2].copy_from_slice(&query[..2]);
+        // a response with the query's opcode
+        response[2] = 0b1000_0000 | (query[2] & 0b0111_1000);
+        response[3] = rcode;
+        &response[..DNS_HEADER_SIZE]
+    }
+
+    /// Write the response to a standard query, returning its length, or `None` if the query is
+    /// malformed
+    fn answer(&self, query: &[u8], response: &mut [u8]) -> Option<usize> {
+        let header = DnsHeader::new_checked(query)?;
+        let question_count = header.question_count() as usize;
+        if question_count == 0 || question_count > MAX_QUESTIONS {
+            return None;
+        }
+
+        // the offset, type, class and whether it's one of our names for each question
+        let mut questions: Vec<(usize, u16, u16, Option<Ipv4Address>), MAX_QUESTIONS> = Vec::new();
+        let mut offset = DNS_HEADER_SIZE;
+        for _ in 0..question_count {
+            let question = DnsQuestion::new_checked(query, offset)?;
+            let is_ours = self.names.iter().any(|name| question.matches(name));
+            let address = if is_ours {
+                Some(self.address)
+            } else {
+                self.wildcard
+            };
+            questions
+                .push((
+                    offset,
+                    question.question_type(),
+                    question.question_class(),
+                    address,
+                ))
+                .ok()?;
+            offset += DnsQuestion::length(&query[offset..])?;
+        }
+        let questions_end = offset;
+
+        for _ in 0..header.record_count() {
+            offset += record_length(query.get(offset..)?)?.0;
+        }
+        // the UDP payload size and EDNS version, if the query has an OPT record
+        let mut edns = None;
+        for _ in 0..header.additional_count() {
+            let record = query.get(offset..)?;
+            let (length, record_type) = record_length(record)?;
+            if record_type == TYPE_OPT {
+                let name = name_length(record)?;
+                let payload_size = read_u16(record, name + 2)?;
+                edns = Some((payload_size, record[name + 5]));
+            }
+            offset += length;
+        }
+
+        let payload_size = match edns {
+            Some((size, _)) => (size as usize).max(CLASSIC_UDP_SIZE),
+            None => CLASSIC_UDP_SIZE,
+        };
+        let limit =
+            response.len().min(payload_size) - if edns.is_some() { OPT_RECORD_SIZE } else { 0 };
+        let bad_version = edns.is_some_and(|(_, version)| version != 0);
+
+        // questions are copied to the same offset, so any compression pointers stay valid
+        response
+            .get_mut(..questions_end)
+            .filter(|_| questions_end <= limit)?
+            .copy_from_slice(&query[..questions_end]);
+        let mut length = questions_end;
+        let mut answer_count = 0u16;
+        let mut truncated = false;
+        if !bad_version {
+            for &(question_offset, question_type, question_class, address) in &questions {
+                let Some(address) = address else { continue };
+                let class_matches = question_class == CLASS_IN || question_class == CLASS_ANY;
+                let type_matches = question_type == TYPE_A || question_type == TYPE_ANY;
+                if !(class_matches && type_matches) {
+                    continue;
+                }
+                if length + A_RECORD_SIZE > limit {
+                    truncated = true;
+                    break;
+                }
+                let record = &mut response[length..length + A_RECORD_SIZE];
+                record[..2].copy_from_slice(&(0xc000 | question_offset as u16).to_be_bytes());
+                record[2..4].copy_from_slice(&TYPE_A.to_be_bytes());
+                record[4..6].copy_from_slice(&CLASS_IN.to_be_bytes());
+                record[6..10].copy_from_slice(&self.ttl.to_be_bytes());
+                record[10..12].copy_from_slice(&4u16.to_be_bytes());
+                record[12..16].copy_from_slice(&address.0);
+                length += A_RECORD_SIZE;
+                answer_count += 1;
+            }
+        }
+
+        if edns.is_some() {
+            let our_payload_size = response.len().min(u16::MAX as usize) as u16;
+            let record = &mut response[length..length + OPT_RECORD_SIZE];
+            record.fill(0);
+            record[1..3].copy_from_slice(&TYPE_OPT.to_be_bytes());
+            record[3..5].copy_from_slice(&our_payload_size.to_be_bytes());
+            if bad_version {
+                record[5] = EXTENDED_RCODE_BADVERS;
+            }
+            length += OPT_RECORD_SIZE;
+        }
+
+        let all_unknown = questions.iter().all(|(.., address)| address.is_none());
+        let rcode = if all_unknown && !bad_version {
+            RCODE_NXDOMAIN
+        } else {
+            0
+        };
+        // a response, authoritative, possibly truncated, copying whether recursion was wanted
+        response[2] = 0b1000_0100
+            | if truncated { 0b10 } else { 0 }
+            | if header.recursion_desired() { 1 } else { 0 };
+        response[3] = rcode;
+        response[6..8].copy_from_slice(&answer_count.to_be_bytes());
+        response[8..10].fill(0);
+        response[10..12].copy_from_slice(&(edns.is_some() as u16).to_be_bytes());
+        Some(length)
+    }
+}
@@ -0,0 +1,373 @@
+//! Multicast DNS (RFC 6762) and DNS service discovery (RFC 6763), so the device can be found as
+//! `<hostname>.local`, and its services browsed, on a network whose resolver doesn't know it.
+//!
+//! Names aren't probed for conflicts before they're used, so two devices given the same hostname
+//! will both answer for it. The firmware's default hostname ends with part of the MAC address to
+//! keep them apart.
+
+use smoltcp::wire::Ipv4Address;
+
+use crate::{
+    name_length, name_matches, read_u16, record_length, DnsHeader, DnsQuestion, CLASS_ANY,
+    CLASS_IN, DNS_HEADER_SIZE, RECORD_FIELDS_SIZE, TYPE_A, TYPE_ANY,
+};
+
+/// The multicast group mDNS queries are sent to
+pub const MDNS_ADDRESS: Ipv4Address = Ipv4Address([224, 0, 0, 251]);
+pub const MDNS_PORT: u16 = 5353;
+/// The most services a responder can advertise
+pub const MAX_SERVICES: usize = 7;
+
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+/// the top bit of the class, asking for a unicast response in questions, and telling caches to
+/// replace what they have in unique records
+const CLASS_TOP_BIT: u16 = 0x8000;
+
+/// the TTLs RFC 6762 recommends for records naming a host, and for everything else
+const HOST_TTL: u32 = 120;
+const OTHER_TTL: u32 = 75 * 60;
+/// the longest TTL allowed in a response to a legacy unicast query
+const LEGACY_TTL: u32 = 10;
+
+const LOCAL: &str = "local";
+/// the name browsers ask for to find every service type on the network
+const SERVICE_TYPES: &str = "_services._dns-sd._udp";
+
+/// A service on the device, advertised as `<hostname>.<service_type>.local`
+pub struct Service<'a> {
+    /// the service type and protocol, like `_http._tcp`
+    pub service_type: &'a str,
+    pub port: u16,
+    /// `key=value` strings describing the service
+    pub txt: &'a [&'a str],
+}
+
+/// One of the records the responder can send
+#[derive(Clone, Copy)]
+enum Record {
+    /// `<hostname>.local` has the device's address
+    Address,
+    /// `_services._dns-sd._udp.local` points to a service's type
+    ServiceType(usize),
+    /// a service's type points to its instance
+    Pointer(usize),
+    /// where the instance is served from
+    Server(usize),
+    Text(usize),
+}
+
+impl Record {
+    fn bit(self) -> u32 {
+        match self {
+            Record::Address => 1,
+            Record::ServiceType(i) => 1 << (1 + 4 * i),
+            Record::Pointer(i) => 1 << (2 + 4 * i),
+            Record::Server(i) => 1 << (3 + 4 * i),
+            Record::Text(i) => 1 << (4 + 4 * i),
+        }
+    }
+
+    fn all(services: usize) -> impl Iterator<Item = Record> {
+        core::iter::once(Record::Address).chain((0..services).flat_map(|i| {
+            [
+                Record::ServiceType(i),
+                Record::Pointer(i),
+                Record::Server(i),
+                Record::Text(i),
+            ]
+        }))
+    }
+}
+
+/// A set of records, in the order they're written
+#[derive(Clone, Copy, Default)]
+struct Records(u32);
+
+impl Records {
+    fn insert(&mut self, record: Record) {
+        self.0 |= record.bit();
+    }
+
+    fn remove(&mut self, record: Record) {
+        self.0 &= !record.bit();
+    }
+
+    fn contains(self, record: Record) -> bool {
+        self.0 & record.bit() != 0
+    }
+
+    fn iter(self, services: usize) -> impl Iterator<Item = Record> {
+        Record::all(services).filter(move |&record| self.contains(record))
+    }
+}
+
+/// Writes a response, checking everything fits
+struct Writer<'r> {
+    buffer: &'r mut [u8],
+    length: usize,
+}
+
+impl Writer<'_> {
+    fn bytes(&mut self, data: &[u8]) -> Option<()> {
+        let end = self.length + data.len();
+        self.buffer.get_mut(self.length..end)?.copy_from_slice(data);
+        self.length = end;
+        Some(())
+    }
+
+    /// `parts` joined with dots, as uncompressed labels
+    fn name(&mut self, parts: &[&str]) -> Option<()> {
+        for label in parts.iter().flat_map(|part| part.split('.')) {
+            if label.is_empty() || label.len() > 63 {
+                return None;
+            }
+            self.bytes(&[label.len() as u8])?;
+            self.bytes(label.as_bytes())?;
+        }
+        self.bytes(&[0])
+    }
+}
+
+/// Answers mDNS queries for the device's hostname and services
+pub struct MdnsResponder<'a> {
+    /// a single label, answered for as `<hostname>.local`
+    pub hostname: &'a str,
+    pub address: Ipv4Address,
+    pub services: &'a [Service<'a>],
+}
+
+impl MdnsResponder<'_> {
+    /// Write the response to `query` into `response`, returning it if there's anything to say.
+    ///
+    /// Queries from a port other than 5353 are legacy unicast queries from ordinary resolvers,
+    /// which get a conventional reply to their own address, echoing the ID and questions. Any
+    /// other response should be sent to the multicast group.
+    ///
+    /// Pointers the querier says it already knows are left out. Only the IPv4 address is
+    /// known, so questions for other address types get no answer.
+    pub fn respond<'r>(
+        &self,
+        query: &[u8],
+        legacy: bool,
+        response: &'r mut [u8],
+    ) -> Option<&'r [u8]> {
+        let header = DnsHeader::new_checked(query)?;
+        if !header.is_query() || !header.is_standard_query() {
+            return None;
+        }
+        let services = self.services.len().min(MAX_SERVICES);
+
+        let mut answers = Records::default();
+        let mut additional = Records::default();
+        let mut offset = DNS_HEADER_SIZE;
+        for _ in 0..header.question_count() {
+            let question = DnsQuestion::new_checked(query, offset)?;
+            offset += DnsQuestion::length(&query[offset..])?;
+            let class = question.question_class() & !CLASS_TOP_BIT;
+            if class != CLASS_IN && class != CLASS_ANY {
+                continue;
+            }
+            let question_type = question.question_type();
+            let wants = |record_type| question_type == record_type || question_type == TYPE_ANY;
+
+            if question.matches_parts(&[self.hostname, LOCAL]) && wants(TYPE_A) {
+                answers.insert(Record::Address);
+            }
+            if question.matches_parts(&[SERVICE_TYPES, LOCAL]) && wants(TYPE_PTR) {
+                (0..services).for_each(|i| answers.insert(Record::ServiceType(i)));
+            }
+            for (i, service) in self.services[..services].iter().enumerate() {
+                if question.matches_parts(&[service.service_type, LOCAL]) && wants(TYPE_PTR) {
+                    answers.insert(Record::Pointer(i));
+                    // what the browser will ask for next, RFC 6763 section 12.1
+                    additional.insert(Record::Server(i));
+                    additional.insert(Record::Text(i));
+                    additional.insert(Record::Address);
+                }
+                let instance = [self.hostname, service.service_type, LOCAL];
+                if question.matches_parts(&instance) {
+                    if wants(TYPE_SRV) {
+                        answers.insert(Record::Server(i));
+                        additional.insert(Record::Address);
+                    }
+                    if wants(TYPE_TXT) {
+                        answers.insert(Record::Text(i));
+                    }
+                }
+            }
+        }
+        let questions_end = offset;
+
+        // known answer suppression, RFC 6762 section 7.1
+        for _ in 0..header.record_count() {
+            let record = query.get(offset..)?;
+            let (length, _) = record_length(record)?;
+            for i in 0..services {
+                if self.is_known(query, offset, Record::Pointer(i)) {
+                    answers.remove(Record::Pointer(i));
+                }
+                if self.is_known(query, offset, Record::ServiceType(i)) {
+                    answers.remove(Record::ServiceType(i));
+                }
+            }
+            offset += length;
+        }
+
+        if answers.0 == 0 {
+            return None;
+        }
+        let additional = Records(additional.0 & !answers.0);
+
+        let mut writer = Writer {
+            buffer: response,
+            length: 0,
+        };
+        // a response, authoritative, with the ID and questions only for legacy queries
+        let id = if legacy { &query[..2] } else { &[0, 0] };
+        writer.bytes(id)?;
+        writer.bytes(&[0b1000_0100, 0])?;
+        writer.bytes(&[0; 8])?;
+        if legacy {
+            writer.bytes(&query[DNS_HEADER_SIZE..questions_end])?;
+        }
+
+        let answer_count = self.write_records(&mut writer, answers, legacy);
+        if answer_count == 0 {
+            return None;
+        }
+        let additional_count = self.write_records(&mut writer, additional, legacy);
+        let length = writer.length;
+        if legacy {
+            response[4..6].copy_from_slice(&query[4..6]);
+        }
+        response[6..8].copy_from_slice(&answer_count.to_be_bytes());
+        response[10..12].copy_from_slice(&additional_count.to_be_bytes());
+        Some(&response[..length])
+    }
+
+    /// Write an unsolicited response with every record, to send when the device joins a network
+    /// so caches learn about it straight away
+    pub fn announcement<'r>(&self, response: &'r mut [u8]) -> Option<&'r [u8]> {
+        let services = self.services.len().min(MAX_SERVICES);
+        let mut records = Records::default();
+        Record::all(services).for_each(|record| records.insert(record));
+
+        let mut writer = Writer {
+            buffer: response,
+            length: 0,
+        };
+        writer.bytes(&[0, 0, 0b1000_0100, 0])?;
+        writer.bytes(&[0; 8])?;
+        let answer_count = self.write_records(&mut writer, records, false);
+        let length = writer.length;
+        response[6..8].copy_from_slice(&answer_count.to_be_bytes());
+        Some(&response[..length])
+    }
+
+    /// Whether the known answer at `offset` in `query` is `record`, with at least half its TTL
+    /// left. Only pointers are checked, as those are what browsers send.
+    fn is_known(&self, query: &[u8], offset: usize, record: Record) -> bool {
+        let Some(name) = query.get(offset..).and_then(name_length) else {
+            return false;
+        };
+        let fields = offset + name;
+        let is_pointer = read_u16(query, fields) == Some(TYPE_PTR);
+        let class = read_u16(query, fields + 2).map(|class| class & !CLASS_TOP_BIT);
+        let ttl = query
+            .get(fields + 4..fields + 8)
+            .map(|ttl| u32::from_be_bytes([ttl[0], ttl[1], ttl[2], ttl[3]]));
+        if !(is_pointer && class == Some(CLASS_IN) && ttl.is_some_and(|ttl| ttl >= OTHER_TTL / 2)) {
+            return false;
+        }
+        let target = fields + RECORD_FIELDS_SIZE;
+        match record {
+            Record::ServiceType(i) => {
+                let service_type = self.services[i].service_type;
+                name_matches(query, offset, &[SERVICE_TYPES, LOCAL])
+                    && name_matches(query, target, &[service_type, LOCAL])
+            }
+            Record::Pointer(i) => {
+                let service_type = self.services[i].service_type;
+                name_matches(query, offset, &[service_type, LOCAL])
+                    && name_matches(query, target, &[self.hostname, service_type, LOCAL])
+            }
+            _ => false,
+        }
+    }
+
+    /// Write each of `records` that fits, returning how many were written
+    fn write_records(&self, writer: &mut Writer, records: Records, legacy: bool) -> u16 {
+        let mut count = 0;
+        for record in records.iter(self.services.len().min(MAX_SERVICES)) {
+            let start = writer.length;
+            if self.write_record(writer, record, legacy).is_some() {
+                count += 1;
+            } else {
+                // leave out what doesn't fit, rather than sending part of a record
+                writer.length = start;
+            }
+        }
+        count
+    }
+
+    fn write_record(&self, writer: &mut Writer, record: Record, legacy: bool) -> Option<()> {
+        let hostname = [self.hostname, LOCAL];
+        let (record_type, unique, ttl) = match record {
+            Record::Address => (TYPE_A, true, HOST_TTL),
+            Record::ServiceType(_) | Record::Pointer(_) => (TYPE_PTR, false, OTHER_TTL),
+            Record::Server(_) => (TYPE_SRV, true, HOST_TTL),
+            Record::Text(_) => (TYPE_TXT, true, OTHER_TTL),
+        };
+        match record {
+            Record::Address => writer.name(&hostname)?,
+            Record::ServiceType(_) => writer.name(&[SERVICE_TYPES, LOCAL])?,
+            Record::Pointer(i) => writer.name(&[self.services[i].service_type, LOCAL])?,
+            Record::Server(i) | Record::Text(i) => {
+                writer.name(&[self.hostname, self.services[i].service_type, LOCAL])?
+            }
+        }
+        // legacy resolvers don't know about the cache flush bit, and shouldn't cache for long
+        let class = if unique && !legacy {
+            CLASS_IN | CLASS_TOP_BIT
+        } else {
+            CLASS_IN
+        };
+        let ttl = if legacy { ttl.min(LEGACY_TTL) } else { ttl };
+        writer.bytes(&record_type.to_be_bytes())?;
+        writer.bytes(&class.to_be_bytes())?;
+        writer.bytes(&ttl.to_be_bytes())?;
+
+        let length_offset = writer.length;
+        writer.bytes(&[0, 0])?;
+        match record {
+            Record::Address => writer.bytes(&self.address.0)?,
+            Record::ServiceType(i) => writer.name(&[self.services[i].service_type, LOCAL])?,
+            Record::Pointer(i) => {
+                writer.name(&[self.hostname, self.services[i].service_type, LOCAL])?
+            }
+            Record::Server(i) => {
+                // priority, weight, then the port
+                writer.bytes(&[0, 0, 0, 0])?;
+                writer.bytes(&self.services[i].port.to_be_bytes())?;
+                writer.name(&hostname)?
+            }
+            Record::Text(i) => {
+                let txt = self.services[i].txt;
+                // a TXT record can't be empty, so no strings is a single empty one
+                if txt.is_empty() {
+                    writer.bytes(&[0])?;
+                }
+                for entry in txt {
+                    let length = u8::try_from(entry.len()).ok()?;
+                    writer.bytes(&[length])?;
+                    writer.bytes(entry.as_bytes())?;
+                }
+            }
+        }
+        let data_length = (writer.length - length_offset - 2) as u16;
+        writer.buffer[length_offset..length_offset + 2].copy_from_slice(&data_length.to_be_bytes());
+        Some(())
+    }
+}
@@ -0,0 +1,150 @@
+//! Query builders and response checks shared by the tests, which each use only some of them
+#![allow(dead_code)]
+
+use dns_packet::Responder;
+use smoltcp::wire::Ipv4Address;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_HTTPS: u16 = 65;
+pub const CLASS_IN: u16 = 1;
+
+pub const ADDRESS: Ipv4Address = Ipv4Address([169, 254, 1, 1]);
+pub const NAMES: &[&str] = &["picohttp", "picohttp.piconet.local"];
+
+pub fn responder(wildcard: Option<Ipv4Address>) -> Responder<'static> {
+    Responder {
+        names: NAMES,
+        address: ADDRESS,
+        wildcard,
+        ttl: 60,
+    }
+}
+
+/// `name` as a sequence of labels
+pub fn encode_name(name: &str) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        encoded.push(label.len() as u8);
+        encoded.extend(label.as_bytes());
+    }
+    encoded.push(0);
+    encoded
+}
+
+pub fn header(id: u16, question_count: u16, additional_count: u16) -> Vec<u8> {
+    let mut header = Vec::new();
+    header.extend(id.to_be_bytes());
+    // a standard query, recursion desired
+    header.extend([0x01, 0x00]);
+    header.extend(question_count.to_be_bytes());
+    header.extend([0, 0, 0, 0]);
+    header.extend(additional_count.to_be_bytes());
+    header
+}
+
+pub fn question(name: &str, question_type: u16) -> Vec<u8> {
+    let mut question = encode_name(name);
+    question.extend(question_type.to_be_bytes());
+    question.extend(CLASS_IN.to_be_bytes());
+    question
+}
+
+/// An OPT record for EDNS, with a payload size and version
+pub fn opt(payload_size: u16, version: u8) -> Vec<u8> {
+    let mut record = vec![0];
+    record.extend(41u16.to_be_bytes());
+    record.extend(payload_size.to_be_bytes());
+    record.extend([0, version, 0, 0, 0, 0]);
+    record
+}
+
+/// A query with one question for each of `questions`
+pub fn query(id: u16, questions: &[(&str, u16)], edns: Option<(u16, u8)>) -> Vec<u8> {
+    let mut query = header(id, questions.len() as u16, edns.is_some() as u16);
+    for (name, question_type) in questions {
+        query.extend(question(name, *question_type));
+    }
+    if let Some((payload_size, version)) = edns {
+        query.extend(opt(payload_size, version));
+    }
+    query
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Answer {
+    /// where the answer's name points to
+    pub name: u16,
+    pub ttl: u32,
+    pub address: [u8; 4],
+}
+
+/// The parts of a response the tests look at
+#[derive(Debug)]
+pub struct Response {
+    pub id: u16,
+    pub authoritative: bool,
+    pub truncated: bool,
+    pub recursion_desired: bool,
+    pub rcode: u8,
+    pub question_count: u16,
+    pub answers: Vec<Answer>,
+    /// the payload size and extended RCODE in the OPT record
+    pub opt: Option<(u16, u8)>,
+}
+
+/// Parse a response, checking every record is where the counts say
+pub fn parse(response: &[u8], query: &[u8]) -> Response {
+    let u16_at = |offset: usize| u16::from_be_bytes([response[offset], response[offset + 1]]);
+    assert!(response[2] & 0x80 != 0, "not a response");
+    assert_eq!(u16_at(8), 0, "authority records");
+
+    let question_count = u16_at(4);
+    let answer_count = u16_at(6);
+    let additional_count = u16_at(10);
+    // the questions are echoed
+    let mut offset = 12;
+    for _ in 0..question_count {
+        while response[offset] != 0 && response[offset] < 0xc0 {
+            offset += 1 + response[offset] as usize;
+        }
+        offset += if response[offset] == 0 { 1 } else { 2 } + 4;
+    }
+    assert_eq!(response[..2], query[..2]);
+    assert_eq!(response[12..offset], query[12..offset]);
+
+    let mut answers = Vec::new();
+    for _ in 0..answer_count {
+        assert_eq!(response[offset] & 0xc0, 0xc0, "answer names are pointers");
+        assert_eq!(u16_at(offset + 2), TYPE_A);
+        assert_eq!(u16_at(offset + 4), CLASS_IN);
+        assert_eq!(u16_at(offset + 10), 4);
+        answers.push(Answer {
+            name: u16_at(offset) & 0x3fff,
+            ttl: u32::from_be_bytes(response[offset + 6..offset + 10].try_into().unwrap()),
+            address: response[offset + 12..offset + 16].try_into().unwrap(),
+        });
+        offset += 16;
+    }
+
+    let mut opt = None;
+    for _ in 0..additional_count {
+        assert_eq!(response[offset], 0, "OPT records are for the root");
+        assert_eq!(u16_at(offset + 1), 41);
+        assert_eq!(u16_at(offset + 9), 0, "OPT options");
+        opt = Some((u16_at(offset + 3), response[offset + 5]));
+        offset += 11;
+    }
+    assert_eq!(offset, response.len(), "trailing bytes");
+
+    Response {
+        id: u16_at(0),
+        authoritative: response[2] & 0x04 != 0,
+        truncated: response[2] & 0x02 != 0,
+        recursion_desired: response[2] & 0x01 != 0,
+        rcode: response[3] & 0x0f,
+        question_count,
+        answers,
+        opt,
+    }
+}
@@ -0,0 +1,160 @@
+//! Throws random and mangled packets at the parser and responders, which must never panic or hang,
+//! and must only ever write well formed responses. Set `DNS_FUZZ_ITERATIONS` to run for longer.
+
+mod common;
+
+use common::*;
+use dns_packet::mdns::{MdnsResponder, Service};
+use dns_packet::DnsPacket;
+use smoltcp::wire::Ipv4Address;
+
+/// xorshift64, so failures can be reproduced from the seed
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn byte(&mut self) -> u8 {
+        self.next() as u8
+    }
+}
+
+fn iterations() -> usize {
+    std::env::var("DNS_FUZZ_ITERATIONS")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(20_000)
+}
+
+/// Parse and answer `packet` with response buffers of a few sizes
+fn check(packet: &[u8]) {
+    if let Some(parsed) = DnsPacket::new_checked(packet) {
+        for i in 0..parsed.header().question_count().min(16) {
+            if let Some(question) = parsed.question(i) {
+                question.matches("picohttp.piconet.local");
+                question.question_type();
+                question.question_class();
+            }
+        }
+    }
+
+    for wildcard in [None, Some(Ipv4Address::new(198, 51, 100, 1))] {
+        for size in [12, 100, 512, 1024] {
+            let mut buffer = vec![0; size];
+            let Some(response) = responder(wildcard).respond(packet, &mut buffer) else {
+                continue;
+            };
+            let response = response.to_vec();
+            assert!(response.len() <= size);
+            parse(&response, packet);
+        }
+    }
+
+    let mdns = MdnsResponder {
+        hostname: "pico-matrix",
+        address: ADDRESS,
+        services: &[Service {
+            service_type: "_http._tcp",
+            port: 80,
+            txt: &["version=0.1.0"],
+        }],
+    };
+    for legacy in [false, true] {
+        for size in [12, 100, 1500] {
+            let mut buffer = vec![0; size];
+            if let Some(response) = mdns.respond(packet, legacy, &mut buffer) {
+                assert!(response.len() <= size);
+                assert_eq!(response[2], 0x84);
+            }
+        }
+    }
+}
+
+/// Valid queries to start mangling from
+fn seeds() -> Vec<Vec<u8>> {
+    let mut compressed = header(7, 2, 0);
+    compressed.extend(question("picohttp.piconet.local", TYPE_A));
+    compressed.extend([3, b'w', b'w', b'w', 0xc0, 12 + 9, 0, 1, 0, 1]);
+    vec![
+        query(1, &[("picohttp", TYPE_A)], None),
+        query(2, &[("picohttp.piconet.local", TYPE_AAAA)], Some((1232, 0))),
+        query(
+            3,
+            &[("example.com", TYPE_A), ("picohttp", TYPE_HTTPS)],
+            Some((4096, 1)),
+        ),
+        query(4, &[("a.b.c.d.e.f", 255); 8], None),
+        compressed,
+        query(
+            5,
+            &[("_http._tcp.local", 12), ("pico-matrix.local", 255)],
+            None,
+        ),
+    ]
+}
+
+#[test]
+fn random_bytes() {
+    let mut rng = Rng(0x5eed_0001);
+    for _ in 0..iterations() {
+        let len = rng.below(600);
+        let packet: Vec<u8> = (0..len).map(|_| rng.byte()).collect();
+        check(&packet);
+    }
+}
+
+#[test]
+fn random_headers() {
+    // random bytes rarely look like a query, so make them
+    let mut rng = Rng(0x5eed_0002);
+    for _ in 0..iterations() {
+        let len = 12 + rng.below(300);
+        let mut packet: Vec<u8> = (0..len).map(|_| rng.byte() % 70).collect();
+        packet[2] = rng.byte() & 0x07;
+        packet[4] = 0;
+        packet[5] = rng.below(10) as u8;
+        packet[6..10].fill(0);
+        packet[10] = 0;
+        packet[11] = rng.below(3) as u8;
+        check(&packet);
+    }
+}
+
+#[test]
+fn mangled_queries() {
+    let seeds = seeds();
+    let mut rng = Rng(0x5eed_0003);
+    for _ in 0..iterations() {
+        let mut packet = seeds[rng.below(seeds.len())].clone();
+        for _ in 0..1 + rng.below(4) {
+            match rng.below(4) {
+                0 => {
+                    let i = rng.below(packet.len());
+                    packet[i] = rng.byte();
+                }
+                1 => {
+                    let i = rng.below(packet.len());
+                    packet[i] ^= 1 << rng.below(8);
+                }
+                2 => packet.truncate(rng.below(packet.len() + 1)),
+                _ => {
+                    let i = rng.below(packet.len() + 1);
+                    packet.insert(i, [0, 0xc0, 63, rng.byte()][rng.below(4)]);
+                }
+            }
+            if packet.is_empty() {
+                break;
+            }
+        }
+        check(&packet);
+    }
+}
@@ -0,0 +1,391 @@
+mod common;
+
+use common::*;
+use dns_packet::mdns::{MdnsResponder, Service};
+
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CACHE_FLUSH: u16 = 0x8000;
+
+const HTTP_TXT: &[&str] = &["size=16x16", "version=0.1.0"];
+const SERVICES: &[Service] = &[
+    Service {
+        service_type: "_http._tcp",
+        port: 80,
+        txt: HTTP_TXT,
+    },
+    Service {
+        service_type: "_pico-matrix._tcp",
+        port: 80,
+        txt: &[],
+    },
+];
+
+fn responder() -> MdnsResponder<'static> {
+    MdnsResponder {
+        hostname: "pico-matrix",
+        address: ADDRESS,
+        services: SERVICES,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Data {
+    Address([u8; 4]),
+    Pointer(String),
+    Server { port: u16, target: String },
+    Text(Vec<String>),
+}
+
+#[derive(Debug, PartialEq)]
+struct Record {
+    name: String,
+    class: u16,
+    ttl: u32,
+    data: Data,
+}
+
+#[derive(Debug)]
+struct Response {
+    id: u16,
+    questions: Vec<String>,
+    answers: Vec<Record>,
+    additional: Vec<Record>,
+}
+
+/// The name at `offset`, and where it ends
+fn read_name(packet: &[u8], mut offset: usize) -> (String, usize) {
+    let mut labels = Vec::new();
+    let mut end = None;
+    loop {
+        let size = packet[offset] as usize;
+        if size == 0 {
+            break;
+        }
+        if size >= 0xc0 {
+            end.get_or_insert(offset + 2);
+            offset = ((size & 0x3f) << 8) | packet[offset + 1] as usize;
+            continue;
+        }
+        labels.push(String::from_utf8(packet[offset + 1..offset + 1 + size].to_vec()).unwrap());
+        offset += 1 + size;
+    }
+    (labels.join("."), end.unwrap_or(offset + 1))
+}
+
+fn parse(response: &[u8]) -> Response {
+    let u16_at = |offset: usize| u16::from_be_bytes([response[offset], response[offset + 1]]);
+    assert_eq!(response[2], 0x84, "an authoritative response");
+    assert_eq!(response[3], 0, "no error");
+    assert_eq!(u16_at(8), 0, "authority records");
+
+    let mut offset = 12;
+    let mut questions = Vec::new();
+    for _ in 0..u16_at(4) {
+        let (name, end) = read_name(response, offset);
+        questions.push(name);
+        offset = end + 4;
+    }
+    let mut read_records = |count| {
+        let mut records = Vec::new();
+        for _ in 0..count {
+            let (name, fields) = read_name(response, offset);
+            let data_start = fields + 10;
+            let data_end = data_start + u16_at(fields + 8) as usize;
+            let data = &response[data_start..data_end];
+            let data = match u16_at(fields) {
+                TYPE_A => Data::Address(data.try_into().unwrap()),
+                TYPE_PTR => Data::Pointer(read_name(response, data_start).0),
+                TYPE_SRV => {
+                    assert_eq!(data[..4], [0; 4], "priority and weight");
+                    Data::Server {
+                        port: u16_at(data_start + 4),
+                        target: read_name(response, data_start + 6).0,
+                    }
+                }
+                TYPE_TXT => {
+                    let mut strings = Vec::new();
+                    let mut i = 0;
+                    while i < data.len() {
+                        let size = data[i] as usize;
+                        strings
+                            .push(String::from_utf8(data[i + 1..i + 1 + size].to_vec()).unwrap());
+                        i += 1 + size;
+                    }
+                    Data::Text(strings)
+                }
+                other => panic!("unexpected record type {other}"),
+            };
+            records.push(Record {
+                name,
+                class: u16_at(fields + 2),
+                ttl: u32::from_be_bytes(response[fields + 4..fields + 8].try_into().unwrap()),
+                data,
+            });
+            offset = data_end;
+        }
+        records
+    };
+    let answers = read_records(u16_at(6));
+    let additional = read_records(u16_at(10));
+    assert_eq!(offset, response.len(), "trailing bytes");
+    Response {
+        id: u16_at(0),
+        questions,
+        answers,
+        additional,
+    }
+}
+
+fn respond(query: &[u8]) -> Option<Response> {
+    let mut buffer = [0; 1500];
+    let response = responder().respond(query, false, &mut buffer)?;
+    Some(parse(response))
+}
+
+fn address_record(class: u16, ttl: u32) -> Record {
+    Record {
+        name: "pico-matrix.local".into(),
+        class,
+        ttl,
+        data: Data::Address(ADDRESS.0),
+    }
+}
+
+fn http_records() -> [Record; 2] {
+    [
+        Record {
+            name: "pico-matrix._http._tcp.local".into(),
+            class: CLASS_IN | CACHE_FLUSH,
+            ttl: 120,
+            data: Data::Server {
+                port: 80,
+                target: "pico-matrix.local".into(),
+            },
+        },
+        Record {
+            name: "pico-matrix._http._tcp.local".into(),
+            class: CLASS_IN | CACHE_FLUSH,
+            ttl: 4500,
+            data: Data::Text(vec!["size=16x16".into(), "version=0.1.0".into()]),
+        },
+    ]
+}
+
+/// A PTR record for `name`, pointing to `target`
+fn pointer(name: &str, target: &str, ttl: u32) -> Vec<u8> {
+    let mut record = encode_name(name);
+    record.extend(TYPE_PTR.to_be_bytes());
+    record.extend(CLASS_IN.to_be_bytes());
+    record.extend(ttl.to_be_bytes());
+    let target = encode_name(target);
+    record.extend((target.len() as u16).to_be_bytes());
+    record.extend(target);
+    record
+}
+
+/// A multicast query, which has a zero ID, and the known answers in `known`
+fn browse(name: &str, known: &[Vec<u8>]) -> Vec<u8> {
+    let mut query = header(0, 1, 0);
+    query[6..8].copy_from_slice(&(known.len() as u16).to_be_bytes());
+    query.extend(question(name, TYPE_PTR));
+    known.iter().for_each(|record| query.extend(record));
+    query
+}
+
+#[test]
+fn answers_for_the_hostname() {
+    for name in ["pico-matrix.local", "Pico-Matrix.LOCAL."] {
+        for question_type in [TYPE_A, TYPE_ANY] {
+            let response = respond(&query(0, &[(name, question_type)], None)).unwrap();
+            assert_eq!(response.id, 0);
+            assert!(response.questions.is_empty());
+            assert_eq!(
+                response.answers,
+                [address_record(CLASS_IN | CACHE_FLUSH, 120)]
+            );
+            assert!(response.additional.is_empty());
+        }
+    }
+}
+
+#[test]
+fn stays_quiet_about_other_names() {
+    for name in [
+        "pico-matrix",
+        "other.local",
+        "pico-matrix.local.evil",
+        "local",
+    ] {
+        assert!(respond(&query(0, &[(name, TYPE_A)], None)).is_none());
+    }
+    // there's no IPv6 address to give
+    assert!(respond(&query(0, &[("pico-matrix.local", TYPE_AAAA)], None)).is_none());
+    // nor any text for the hostname itself
+    assert!(respond(&query(0, &[("pico-matrix.local", TYPE_TXT)], None)).is_none());
+}
+
+#[test]
+fn lists_service_types() {
+    let response = respond(&browse("_services._dns-sd._udp.local", &[])).unwrap();
+    let targets: Vec<_> = response
+        .answers
+        .iter()
+        .map(|record| (record.class, &record.data))
+        .collect();
+    assert_eq!(
+        targets,
+        [
+            (CLASS_IN, &Data::Pointer("_http._tcp.local".into())),
+            (CLASS_IN, &Data::Pointer("_pico-matrix._tcp.local".into())),
+        ]
+    );
+    assert!(response.additional.is_empty());
+}
+
+#[test]
+fn browsing_gives_the_instance_and_how_to_reach_it() {
+    let response = respond(&browse("_http._tcp.local", &[])).unwrap();
+    assert_eq!(
+        response.answers,
+        [Record {
+            name: "_http._tcp.local".into(),
+            class: CLASS_IN,
+            ttl: 4500,
+            data: Data::Pointer("pico-matrix._http._tcp.local".into()),
+        }]
+    );
+    let [server, text] = http_records();
+    assert_eq!(
+        response.additional,
+        [address_record(CLASS_IN | CACHE_FLUSH, 120), server, text]
+    );
+
+    // a service with no text still has a TXT record, with one empty string
+    let response = respond(&browse("_pico-matrix._tcp.local", &[])).unwrap();
+    assert_eq!(response.additional[2].data, Data::Text(vec![String::new()]));
+}
+
+#[test]
+fn resolves_instances() {
+    let instance = "pico-matrix._http._tcp.local";
+    let [server, text] = http_records();
+
+    let response = respond(&query(0, &[(instance, TYPE_SRV)], None)).unwrap();
+    assert_eq!(response.answers, [server]);
+    assert_eq!(
+        response.additional,
+        [address_record(CLASS_IN | CACHE_FLUSH, 120)]
+    );
+
+    let response = respond(&query(0, &[(instance, TYPE_TXT)], None)).unwrap();
+    assert_eq!(response.answers, [text]);
+    assert!(response.additional.is_empty());
+
+    // records answered aren't repeated as additional records
+    let response = respond(&query(
+        0,
+        &[(instance, TYPE_ANY), ("pico-matrix.local", TYPE_A)],
+        None,
+    ))
+    .unwrap();
+    assert_eq!(response.answers.len(), 3);
+    assert!(response.additional.is_empty());
+}
+
+#[test]
+fn leaves_out_known_answers() {
+    let known = pointer("_http._tcp.local", "pico-matrix._http._tcp.local", 4500);
+    assert!(respond(&browse("_http._tcp.local", &[known])).is_none());
+
+    // unless the querier's copy is past half its life
+    let stale = pointer("_http._tcp.local", "pico-matrix._http._tcp.local", 2000);
+    assert_eq!(
+        respond(&browse("_http._tcp.local", &[stale]))
+            .unwrap()
+            .answers
+            .len(),
+        1
+    );
+
+    // or it's for another instance
+    let other = pointer("_http._tcp.local", "printer._http._tcp.local", 4500);
+    assert_eq!(
+        respond(&browse("_http._tcp.local", &[other]))
+            .unwrap()
+            .answers
+            .len(),
+        1
+    );
+
+    let known = pointer("_services._dns-sd._udp.local", "_http._tcp.local", 4500);
+    let response = respond(&browse("_services._dns-sd._udp.local", &[known])).unwrap();
+    assert_eq!(
+        response.answers[0].data,
+        Data::Pointer("_pico-matrix._tcp.local".into())
+    );
+    assert_eq!(response.answers.len(), 1);
+}
+
+#[test]
+fn answers_legacy_queries_like_a_resolver() {
+    let query = query(0x1234, &[("pico-matrix.local", TYPE_A)], None);
+    let mut buffer = [0; 1500];
+    let response = responder().respond(&query, true, &mut buffer).unwrap();
+    let response = parse(response);
+    assert_eq!(response.id, 0x1234);
+    assert_eq!(response.questions, ["pico-matrix.local"]);
+    // without the cache flush bit, and only cached briefly
+    assert_eq!(response.answers, [address_record(CLASS_IN, 10)]);
+}
+
+#[test]
+fn announces_every_record() {
+    let mut buffer = [0; 1500];
+    let announcement = parse(responder().announcement(&mut buffer).unwrap());
+    assert_eq!(announcement.id, 0);
+    assert!(announcement.questions.is_empty());
+    assert!(announcement.additional.is_empty());
+    let names: Vec<_> = announcement
+        .answers
+        .iter()
+        .map(|record| record.name.as_str())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "pico-matrix.local",
+            "_services._dns-sd._udp.local",
+            "_http._tcp.local",
+            "pico-matrix._http._tcp.local",
+            "pico-matrix._http._tcp.local",
+            "_services._dns-sd._udp.local",
+            "_pico-matrix._tcp.local",
+            "pico-matrix._pico-matrix._tcp.local",
+            "pico-matrix._pico-matrix._tcp.local",
+        ]
+    );
+}
+
+#[test]
+fn ignores_what_is_not_a_query() {
+    let mut buffer = [0; 1500];
+    let responder = responder();
+
+    let mut response = query(0, &[("pico-matrix.local", TYPE_A)], None);
+    response[2] |= 0x80;
+    assert!(responder.respond(&response, false, &mut buffer).is_none());
+    assert!(responder.respond(&[0; 11], false, &mut buffer).is_none());
+
+    let mut cut = query(0, &[("pico-matrix.local", TYPE_A)], None);
+    cut.pop();
+    assert!(responder.respond(&cut, false, &mut buffer).is_none());
+
+    // records that don't fit are left out rather than cut short
+    let mut small = [0; 12 + 23 + 10];
+    assert!(responder
+        .respond(&browse("_http._tcp.local", &[]), false, &mut small)
+        .is_none());
+}
@@ -0,0 +1,260 @@
+mod common;
+
+use common::*;
+use dns_packet::{DnsHeader, DnsPacket, DnsQuestion};
+use smoltcp::wire::Ipv4Address;
+
+fn respond(query: &[u8], wildcard: Option<Ipv4Address>) -> Response {
+    let mut buffer = [0; 1024];
+    let response = responder(wildcard).respond(query, &mut buffer).unwrap();
+    parse(response, query)
+}
+
+#[test]
+fn header_fields() {
+    let query = query(0x1234, &[("picohttp", TYPE_A)], Some((1232, 0)));
+    let header = DnsHeader::new_checked(&query).unwrap();
+    assert!(header.is_query());
+    assert!(header.is_standard_query());
+    assert!(header.recursion_desired());
+    assert_eq!(header.question_count(), 1);
+    assert_eq!(header.record_count(), 0);
+    assert_eq!(header.additional_count(), 1);
+    assert!(DnsHeader::new_checked(&query[..11]).is_none());
+}
+
+#[test]
+fn questions_are_indexed_from_zero() {
+    let query = query(
+        1,
+        &[
+            ("a.example", TYPE_A),
+            ("bb", TYPE_AAAA),
+            ("c.d.e", TYPE_HTTPS),
+        ],
+        None,
+    );
+    let packet = DnsPacket::new_checked(&query).unwrap();
+    let types: Vec<_> = (0..3)
+        .map(|i| packet.question(i).unwrap().question_type())
+        .collect();
+    assert_eq!(types, [TYPE_A, TYPE_AAAA, TYPE_HTTPS]);
+    assert_eq!(packet.question(0).unwrap().offset(), 12);
+    assert_eq!(packet.question(1).unwrap().offset(), 12 + 11 + 4);
+    assert!(packet.question(1).unwrap().matches("bb"));
+    assert_eq!(packet.question(2).unwrap().question_class(), CLASS_IN);
+    assert!(packet.question(3).is_none());
+
+    // a question cut short isn't returned
+    let cut = &query[..query.len() - 1];
+    let packet = DnsPacket::new_checked(cut).unwrap();
+    assert!(packet.question(1).is_some());
+    assert!(packet.question(2).is_none());
+    assert!(DnsPacket::new_checked(&query[..4]).is_none());
+}
+
+/// Whether a query for `name` matches `expected`
+fn matches(name: &str, expected: &str) -> bool {
+    let query = query(1, &[(name, TYPE_A)], None);
+    DnsQuestion::new_checked(&query, 12)
+        .unwrap()
+        .matches(expected)
+}
+
+#[test]
+fn names_match_exactly() {
+    assert!(matches("picohttp", "picohttp"));
+    assert!(matches("picohttp.piconet.local", "picohttp.piconet.local"));
+    assert!(!matches("picohttp.evil.com", "picohttp"));
+    assert!(!matches("picohttp", "picohttp.piconet.local"));
+    assert!(!matches("picohttp.piconet", "picohttp.piconet.local"));
+    assert!(!matches("picohttpx", "picohttp"));
+    assert!(!matches("evil.picohttp", "picohttp"));
+    assert!(matches("", ""));
+    assert!(!matches("", "picohttp"));
+}
+
+#[test]
+fn names_match_ignoring_case_and_a_trailing_dot() {
+    assert!(matches("PicoHTTP.PicoNet.Local", "picohttp.piconet.local"));
+    assert!(matches("picohttp", "PICOHTTP"));
+    assert!(matches("picohttp", "picohttp."));
+}
+
+#[test]
+fn names_follow_compression_pointers() {
+    let mut query = header(1, 2, 0);
+    query.extend(question("picohttp.piconet.local", TYPE_A));
+    // "www" then a pointer to "piconet.local" in the first question
+    let second = query.len();
+    query.extend([3, b'w', b'w', b'w', 0xc0, 12 + 9]);
+    query.extend(TYPE_A.to_be_bytes());
+    query.extend(CLASS_IN.to_be_bytes());
+
+    let packet = DnsPacket::new_checked(&query).unwrap();
+    let question = packet.question(1).unwrap();
+    assert_eq!(question.offset(), second);
+    assert_eq!(question.question_type(), TYPE_A);
+    assert!(question.matches("www.piconet.local"));
+    assert!(!question.matches("www"));
+    assert!(!question.matches("www.piconet"));
+}
+
+#[test]
+fn bad_names_never_match() {
+    let name_query = |name: &[u8]| {
+        let mut query = header(1, 1, 0);
+        query.extend(name);
+        query.extend(TYPE_A.to_be_bytes());
+        query.extend(CLASS_IN.to_be_bytes());
+        query
+    };
+
+    // a pointer to itself
+    let query = name_query(&[0xc0, 12]);
+    assert!(!DnsQuestion::new_checked(&query, 12)
+        .unwrap()
+        .matches("picohttp"));
+
+    // a label that points to itself through another
+    let query = name_query(&[3, b'a', b'b', b'c', 0xc0, 12]);
+    assert!(!DnsQuestion::new_checked(&query, 12)
+        .unwrap()
+        .matches("abc.abc"));
+
+    // a pointer past the end
+    let query = name_query(&[0xc0, 0xff]);
+    assert!(!DnsQuestion::new_checked(&query, 12).unwrap().matches(""));
+
+    // labels can't be longer than 63 bytes
+    let query = name_query(&[0x40, b'a', 0]);
+    assert!(DnsQuestion::new_checked(&query, 12).is_none());
+}
+
+#[test]
+fn answers_a_queries_for_our_names() {
+    for name in NAMES {
+        let query = query(0xbeef, &[(name, TYPE_A)], None);
+        let response = respond(&query, None);
+        assert_eq!(response.id, 0xbeef);
+        assert!(response.authoritative);
+        assert!(response.recursion_desired);
+        assert!(!response.truncated);
+        assert_eq!(response.rcode, 0);
+        assert_eq!(
+            response.answers,
+            [Answer {
+                name: 12,
+                ttl: 60,
+                address: ADDRESS.0
+            }]
+        );
+        assert_eq!(response.opt, None);
+    }
+}
+
+#[test]
+fn answers_other_types_with_no_records() {
+    for question_type in [TYPE_AAAA, TYPE_HTTPS, 16] {
+        let query = query(1, &[("picohttp", question_type)], None);
+        let response = respond(&query, None);
+        assert_eq!(response.rcode, 0);
+        assert!(response.answers.is_empty());
+        assert_eq!(response.question_count, 1);
+    }
+}
+
+#[test]
+fn unknown_names_do_not_exist_without_a_wildcard() {
+    let query = query(1, &[("picohttp.evil.com", TYPE_A)], None);
+    let response = respond(&query, None);
+    assert_eq!(response.rcode, 3);
+    assert!(response.answers.is_empty());
+
+    let wildcard = Ipv4Address::new(198, 51, 100, 1);
+    let response = respond(&query, Some(wildcard));
+    assert_eq!(response.rcode, 0);
+    assert_eq!(response.answers[0].address, wildcard.0);
+}
+
+#[test]
+fn answers_every_question() {
+    let query = query(
+        1,
+        &[
+            ("picohttp", TYPE_A),
+            ("example.com", TYPE_A),
+            ("picohttp.piconet.local", TYPE_AAAA),
+            ("PICOHTTP.piconet.local", TYPE_A),
+        ],
+        None,
+    );
+    let response = respond(&query, None);
+    // one of the names exists, so this isn't NXDOMAIN
+    assert_eq!(response.rcode, 0);
+    let packet = DnsPacket::new_checked(&query).unwrap();
+    let names: Vec<_> = response.answers.iter().map(|a| a.name as usize).collect();
+    assert_eq!(
+        names,
+        [
+            packet.question(0).unwrap().offset(),
+            packet.question(3).unwrap().offset()
+        ]
+    );
+}
+
+#[test]
+fn echoes_edns() {
+    let query = query(1, &[("picohttp", TYPE_A)], Some((1232, 0)));
+    let response = respond(&query, None);
+    assert_eq!(response.opt, Some((1024, 0)));
+    assert_eq!(response.answers.len(), 1);
+
+    // unsupported versions get BADVERS and no answers
+    let query = common::query(1, &[("picohttp", TYPE_A)], Some((1232, 1)));
+    let response = respond(&query, None);
+    assert_eq!(response.opt, Some((1024, 1)));
+    assert!(response.answers.is_empty());
+    assert_eq!(response.rcode, 0);
+}
+
+#[test]
+fn truncates_answers_that_do_not_fit() {
+    let questions = [("picohttp", TYPE_A); 8];
+    let query = query(1, &questions, None);
+    let mut buffer = [0; 12 + 8 * 14 + 3 * 16];
+    let response = responder(None).respond(&query, &mut buffer).unwrap();
+    let response = parse(response, &query);
+    assert!(response.truncated);
+    assert_eq!(response.answers.len(), 3);
+}
+
+#[test]
+fn refuses_what_it_cannot_answer() {
+    let mut buffer = [0; 512];
+    let responder = responder(None);
+
+    // responses and short packets are ignored
+    let mut response = query(1, &[("picohttp", TYPE_A)], None);
+    response[2] |= 0x80;
+    assert_eq!(responder.respond(&response, &mut buffer), None);
+    assert_eq!(responder.respond(&[0; 11], &mut buffer), None);
+
+    // other opcodes aren't implemented
+    let mut status = query(1, &[], None);
+    status[2] = 2 << 3;
+    let response = responder.respond(&status, &mut buffer).unwrap().to_vec();
+    assert_eq!(parse(&response, &status).rcode, 4);
+
+    // malformed queries, no questions or too many questions are format errors
+    let mut cut = query(1, &[("picohttp", TYPE_A)], None);
+    cut.pop();
+    let no_questions = query(1, &[], None);
+    let too_many = query(1, &[("picohttp", TYPE_A); 9], None);
+    for query in [cut, no_questions, too_many] {
+        let response = responder.respond(&query, &mut buffer).unwrap().to_vec();
+        let response = parse(&response, &query);
+        assert_eq!(response.rcode, 1);
+        assert_eq!(response.question_count, 0);
+    }
+}
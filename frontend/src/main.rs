@@ -1,12 +1,28 @@
-use futures_util::{SinkExt, StreamExt};
+use futures_util::StreamExt;
 use gloo_console::log;
 use gloo_net::http::Request;
 use gloo_net::websocket::{futures::WebSocket, Message};
 use gloo_timers::future::TimeoutFuture;
+use gloo_utils::window;
+use matrix_state::scene::SceneMessage;
+use matrix_state::{MatrixSnapshot, ServerMessage};
 use wasm_bindgen_futures::spawn_local;
 use yew::prelude::*;
 use yew_router::{BrowserRouter, Routable, Switch};
 
+/// What the matrix sends over the WebSocket, postcard encoded
+type Event = ServerMessage<MatrixSnapshot<SceneMessage>>;
+
+/// The WebSocket on whichever server served the page, using wss: if the page came over https:
+fn socket_url() -> Option<String> {
+    let location = window().location();
+    let scheme = match location.protocol().ok()?.as_str() {
+        "https:" => "wss:",
+        _ => "ws:",
+    };
+    Some(format!("{}//{}/ws/ws", scheme, location.host().ok()?))
+}
+
 #[derive(Clone, Routable, PartialEq)]
 enum Route {
     #[at("/")]
@@ -31,13 +47,32 @@ fn App() -> Html {
 #[function_component(Main)]
 fn main() -> Html {
     use_effect_with((), move |_| {
-        match WebSocket::open("ws://127.0.0.1:8080/ws/ws") {
+        let Some(url) = socket_url() else {
+            log!("Couldn't work out the websocket's address from the page's location");
+            return;
+        };
+        match WebSocket::open(&url) {
             Ok(ws) => {
-                let (mut write, mut read) = ws.split();
+                let (_write, mut read) = ws.split();
                 spawn_local(async move {
-                    while let Some(m) = read.next().await {
-                        log!(format!("Got message: {:?}", m));
-                        write.send(Message::Text("Hi".into())).await.unwrap();
+                    while let Some(message) = read.next().await {
+                        match message {
+                            Ok(Message::Bytes(data)) => {
+                                match postcard::from_bytes::<Event>(&data) {
+                                    Ok(ServerMessage::State(snapshot)) => {
+                                        log!(format!("The matrix is showing {:?}", snapshot))
+                                    }
+                                    Ok(ServerMessage::Error(e)) => {
+                                        log!(format!("The matrix couldn't apply a message: {}", e))
+                                    }
+                                    Err(e) => log!(format!("Couldn't decode a message: {:?}", e)),
+                                }
+                            }
+                            Ok(Message::Text(_)) => {
+                                log!("Ignoring a text message, the matrix sends binary")
+                            }
+                            Err(e) => log!(format!("Websocket error: {:?}", e)),
+                        }
                     }
                     log!("Bye bye socket");
                 })
@@ -47,7 +82,47 @@ fn main() -> Html {
             }
         }
     });
-    html! {"Hello"}
+    html! {
+        <>
+            {"Hello"}
+            <CaptivePortalOff/>
+            <WifiSettings/>
+            <AccessPointSettings/>
+            <FactoryReset/>
+        </>
+    }
+}
+
+/// Once the UI has been reached, the captive portal can be switched off so phones close their
+/// sign-in popup and stay on the matrix's network
+#[function_component(CaptivePortalOff)]
+fn captive_portal_off() -> Html {
+    html! {
+        <form method="post" action="/api/captive-portal">
+            <input type="hidden" name="enabled" value="false"/>
+            <button type="submit">{"Done signing in"}</button>
+        </form>
+    }
+}
+
+/// Sends the credentials for a network for the matrix to join. The matrix restarts and tries
+/// to join it, falling back to its own access point if it can't. Leave the SSID empty to go
+/// back to the access point.
+#[function_component(WifiSettings)]
+fn wifi_settings() -> Html {
+    html! {
+        <form method="post" action="/api/wifi">
+            <fieldset>
+                <legend>{"WiFi network"}</legend>
+                <label>{"SSID "}<input type="text" name="ssid" maxlength="32"/></label>
+                <label>
+                    {"Passphrase "}
+                    <input type="password" name="passphrase" maxlength="63"/>
+                </label>
+                <button type="submit">{"Join"}</button>
+            </fieldset>
+        </form>
+    }
 }
 
 #[function_component(HelloServer)]
@@ -96,6 +171,39 @@ fn hello_server() -> Html {
     }
 }
 
+/// Sets up the network the matrix runs when it isn't joining one. Leave the SSID and passphrase
+/// empty for the matrix's own defaults.
+#[function_component(AccessPointSettings)]
+fn access_point_settings() -> Html {
+    html! {
+        <form method="post" action="/api/access-point">
+            <fieldset>
+                <legend>{"Access point"}</legend>
+                <label>{"SSID "}<input type="text" name="ssid" maxlength="32"/></label>
+                <label>
+                    {"Passphrase "}
+                    <input type="password" name="passphrase" minlength="8" maxlength="63"/>
+                </label>
+                <label>
+                    {"Channel "}
+                    <input type="number" name="channel" min="1" max="13" value="5"/>
+                </label>
+                <button type="submit">{"Save"}</button>
+            </fieldset>
+        </form>
+    }
+}
+
+/// Puts every setting back to its default and restarts the matrix
+#[function_component(FactoryReset)]
+fn factory_reset() -> Html {
+    html! {
+        <form method="post" action="/api/config/reset">
+            <button type="submit">{"Factory reset"}</button>
+        </form>
+    }
+}
+
 fn main() {
     yew::Renderer::<App>::new().render();
 }
@@ -0,0 +1,150 @@
+//! The JSON API the firmware and the simulator both serve. Requests are turned into
+//! `MatrixStateMessage`s, so scripts drive the matrix the same way the WebSocket does.
+
+use heapless::Vec;
+use serde::de::{
+    self, DeserializeSeed, Deserializer, EnumAccess, IntoDeserializer, VariantAccess, Visitor,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{Layout, PixelRun, MAX_RUN_LEN};
+
+/// The body of `GET` and `PUT /api/brightness`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Brightness {
+    /// from 0 to 1
+    pub brightness: f32,
+}
+
+/// The body of `GET /api/info`
+#[derive(Debug, Clone, Serialize)]
+pub struct Info<'a> {
+    pub rows: usize,
+    pub cols: usize,
+    pub layout: Layout,
+    pub version: &'a str,
+    /// seconds since startup
+    pub uptime: u64,
+    /// bytes of RAM not used by statics or the stack, if that's known
+    pub free_memory: Option<usize>,
+}
+
+/// The body of `POST /api/frame`. The firmware reads each request into a 2KB buffer along with
+/// its headers, so it takes a little over 250 pixels at a time, and bigger frames are sent in
+/// parts with `start` counting on from the last part.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Frame<'a> {
+    /// a `rrggbb` hex colour for each pixel, counting along the rows from the top left
+    pub pixels: &'a str,
+    /// the pixel the first colour is for, when the frame is sent in parts
+    #[serde(default)]
+    pub start: u16,
+}
+
+impl Frame<'_> {
+    /// The frame as runs of pixels to send as `MatrixStateMessage::DrawPixels`, or `None` if the
+    /// pixels aren't all hex colours
+    pub fn runs(&self) -> Option<impl Iterator<Item = PixelRun> + '_> {
+        let hex = self.pixels.as_bytes();
+        let valid = hex.len().is_multiple_of(6)
+            && self.start as usize + hex.len() / 6 <= u16::MAX as usize
+            && hex.iter().all(u8::is_ascii_hexdigit);
+        if !valid {
+            return None;
+        }
+        let byte = |digits: &[u8]| {
+            // the digits were checked above
+            let digit = |d: u8| (d as char).to_digit(16).unwrap_or_default() as u8;
+            digit(digits[0]) << 4 | digit(digits[1])
+        };
+        Some(
+            hex.chunks(6 * MAX_RUN_LEN)
+                .enumerate()
+                .map(move |(i, chunk)| PixelRun {
+                    start: self.start + (i * MAX_RUN_LEN) as u16,
+                    pixels: chunk
+                        .chunks(6)
+                        .map(|c| (byte(&c[0..2]), byte(&c[2..4]), byte(&c[4..6])))
+                        .collect::<Vec<_, MAX_RUN_LEN>>(),
+                }),
+        )
+    }
+}
+
+/// Deserialize the variant `name` of the enum `T`, with what's in the variant from `contents`.
+/// `POST /api/scene/{name}` uses this to take the scene from the path and its message from the
+/// body.
+pub fn from_variant<'de, T, D>(name: &str, contents: D) -> Result<T, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(Variant { name, contents })
+}
+
+struct Variant<'a, D> {
+    name: &'a str,
+    contents: D,
+}
+
+impl<'de, D: Deserializer<'de>> Deserializer<'de> for Variant<'_, D> {
+    type Error = D::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("only enums can be made from a variant"))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_enum(self)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map struct identifier
+        ignored_any
+    }
+}
+
+impl<'de, D: Deserializer<'de>> EnumAccess<'de> for Variant<'_, D> {
+    type Error = D::Error;
+    type Variant = Contents<D>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Contents<D>), D::Error> {
+        let name: de::value::StrDeserializer<D::Error> = self.name.into_deserializer();
+        Ok((seed.deserialize(name)?, Contents(self.contents)))
+    }
+}
+
+struct Contents<D>(D);
+
+impl<'de, D: Deserializer<'de>> VariantAccess<'de> for Contents<D> {
+    type Error = D::Error;
+
+    fn unit_variant(self) -> Result<(), D::Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, D::Error> {
+        seed.deserialize(self.0)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, D::Error> {
+        self.0.deserialize_tuple(len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, D::Error> {
+        self.0.deserialize_struct("", fields, visitor)
+    }
+}
@@ -0,0 +1,101 @@
+use crate::RGB8;
+
+/// A lookup table mapping a perceptual channel value to the value sent to the LEDs
+#[derive(Debug, PartialEq, Eq)]
+pub struct GammaTable(pub [u8; 256]);
+
+impl GammaTable {
+    /// The identity table, no gamma correction is applied
+    pub const LINEAR: GammaTable = GammaTable::linear();
+
+    /// A gamma of 2.8, which is a good fit for WS2812B LEDs
+    pub const WS2812B: GammaTable = GammaTable([
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, //
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, //
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, //
+        2, 3, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4, 4, 5, 5, 5, //
+        5, 6, 6, 6, 6, 7, 7, 7, 7, 8, 8, 8, 9, 9, 9, 10, //
+        10, 10, 11, 11, 11, 12, 12, 13, 13, 13, 14, 14, 15, 15, 16, 16, //
+        17, 17, 18, 18, 19, 19, 20, 20, 21, 21, 22, 22, 23, 24, 24, 25, //
+        25, 26, 27, 27, 28, 29, 29, 30, 31, 32, 32, 33, 34, 35, 35, 36, //
+        37, 38, 39, 39, 40, 41, 42, 43, 44, 45, 46, 47, 48, 49, 50, 50, //
+        51, 52, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63, 64, 66, 67, 68, //
+        69, 70, 72, 73, 74, 75, 77, 78, 79, 81, 82, 83, 85, 86, 87, 89, //
+        90, 92, 93, 95, 96, 98, 99, 101, 102, 104, 105, 107, 109, 110, 112, 114, //
+        115, 117, 119, 120, 122, 124, 126, 127, 129, 131, 133, 135, 137, 138, 140, 142, //
+        144, 146, 148, 150, 152, 154, 156, 158, 160, 162, 164, 167, 169, 171, 173, 175, //
+        177, 180, 182, 184, 186, 189, 191, 193, 196, 198, 200, 203, 205, 208, 210, 213, //
+        215, 218, 220, 223, 225, 228, 231, 233, 236, 239, 241, 244, 247, 249, 252, 255, //
+    ]);
+
+    pub const fn new(table: [u8; 256]) -> Self {
+        Self(table)
+    }
+
+    const fn linear() -> Self {
+        let mut table = [0u8; 256];
+        let mut i = 0;
+        while i < 256 {
+            table[i] = i as u8;
+            i += 1;
+        }
+        Self(table)
+    }
+
+    pub fn lookup(&self, value: u8) -> u8 {
+        self.0[value as usize]
+    }
+}
+
+/// The brightness and gamma correction that a display applies to every pixel it outputs.
+/// Brightness scales each channel first, and the gamma table then maps the result to the LED
+/// drive level, so the dimmed display looks as bright as the setting says.
+#[derive(Debug, Copy, Clone)]
+pub struct ColourCorrection {
+    brightness: u8,
+    gamma: &'static GammaTable,
+}
+
+impl Default for ColourCorrection {
+    fn default() -> Self {
+        Self::new(1.0, &GammaTable::WS2812B)
+    }
+}
+
+impl ColourCorrection {
+    /// Create a correction from a brightness in [0, 1]. Values outside that range are clamped,
+    /// and NaN is treated as 0.
+    pub fn new(brightness: f32, gamma: &'static GammaTable) -> Self {
+        Self {
+            brightness: (clamp_brightness(brightness) * 255.0 + 0.5) as u8,
+            gamma,
+        }
+    }
+
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    pub fn apply_channel(&self, value: u8) -> u8 {
+        let scaled = (value as u16 * self.brightness as u16 + 127) / 255;
+        self.gamma.lookup(scaled as u8)
+    }
+
+    pub fn apply(&self, colour: RGB8) -> RGB8 {
+        RGB8 {
+            padding: colour.padding,
+            b: self.apply_channel(colour.b),
+            r: self.apply_channel(colour.r),
+            g: self.apply_channel(colour.g),
+        }
+    }
+}
+
+/// clamp a brightness into [0, 1], mapping NaN to 0
+pub(crate) fn clamp_brightness(brightness: f32) -> f32 {
+    if brightness.is_nan() {
+        0.0
+    } else {
+        brightness.clamp(0.0, 1.0)
+    }
+}
@@ -0,0 +1,377 @@
+//! Drawing on a `MatrixDisplay`, for effects and anything else that draws shapes rather than
+//! setting each pixel itself. Points are (row, col) from the top left like the display's own
+//! positions, but signed, so shapes can hang off the edges. Anything outside the display is
+//! clipped.
+
+use heapless::Vec;
+
+use crate::effects::MAX_PIXELS;
+use crate::{MatrixDisplay, UpdateError, RGB8};
+
+#[cfg(feature = "embedded-graphics")]
+mod canvas;
+
+#[cfg(feature = "embedded-graphics")]
+pub use canvas::Canvas;
+
+/// A (row, col) position, which may be off the display
+pub type Point = (i32, i32);
+
+/// Runs still to be filled by `flood_fill`. Any that don't fit are found again by rescanning.
+const FLOOD_STACK_SIZE: usize = 64;
+
+/// A position worked out from a `Point`, in a wider type so that it can't overflow
+type WidePoint = (i64, i64);
+
+/// Drawing operations, for every `MatrixDisplay`
+pub trait Draw: MatrixDisplay + Sized {
+    /// Set the pixel at `point`, if it's on the display
+    fn plot(&mut self, (row, col): Point, colour: RGB8) {
+        plot_wide(self, (row.into(), col.into()), colour);
+    }
+
+    fn fill(&mut self, colour: RGB8) {
+        let (rows, cols) = self.size();
+        fill_clipped(self, 0..rows, 0..cols, colour);
+    }
+
+    /// Turn every pixel off
+    fn clear(&mut self) {
+        self.fill(RGB8::default());
+    }
+
+    /// A straight line between two points, including both ends
+    fn line(&mut self, from: Point, to: Point, colour: RGB8) {
+        // Bresenham's algorithm, stepping along whichever of the rows or columns the line
+        // crosses more of and moving across whenever that keeps it closest to the true line.
+        // The coordinates are swapped so the steps are always along the first.
+        let (from, to): (WidePoint, WidePoint) =
+            ((from.0.into(), from.1.into()), (to.0.into(), to.1.into()));
+        let steep = (to.0 - from.0).abs() >= (to.1 - from.1).abs();
+        let swap = |(a, b): WidePoint| if steep { (a, b) } else { (b, a) };
+        let (from, to) = (swap(from), swap(to));
+        let (rows, cols) = self.size();
+        let len = swap((rows as i64, cols as i64));
+        let (d_major, d_minor) = ((to.0 - from.0).abs(), (to.1 - from.1).abs());
+        let step = ((to.0 - from.0).signum(), (to.1 - from.1).signum());
+
+        // the ends can be a long way off the display, so skip straight to the first step on it
+        let (Some(major_offset), Some(minor_offset)) = (
+            steps_onto(from.0, step.0, len.0),
+            steps_onto(from.1, step.1, len.1),
+        ) else {
+            return;
+        };
+        let Some((mut i, across, mut error)) =
+            line_start(d_major, d_minor, major_offset, minor_offset)
+        else {
+            return;
+        };
+        let (mut major, mut minor) = (from.0 + step.0 * i, from.1 + step.1 * across);
+        // then step along until the line leaves the display or ends
+        while i <= d_major && (0..len.0).contains(&major) && (0..len.1).contains(&minor) {
+            plot_wide(self, swap((major, minor)), colour);
+            if error > 0 {
+                minor += step.1;
+                error -= 2 * d_major;
+            }
+            error += 2 * d_minor;
+            major += step.0;
+            i += 1;
+        }
+    }
+
+    /// The outline of the rectangle with corners `from` and `to`
+    fn rect(&mut self, from: Point, to: Point, colour: RGB8) {
+        self.line(from, (from.0, to.1), colour);
+        self.line((from.0, to.1), to, colour);
+        self.line(to, (to.0, from.1), colour);
+        self.line((to.0, from.1), from, colour);
+    }
+
+    /// The rectangle with corners `from` and `to`, filled in
+    fn fill_rect(&mut self, from: Point, to: Point, colour: RGB8) {
+        let (rows, cols) = self.size();
+        fill_clipped(
+            self,
+            clip_range(from.0.into(), to.0.into(), rows),
+            clip_range(from.1.into(), to.1.into(), cols),
+            colour,
+        );
+    }
+
+    /// The outline of a circle, `radius` pixels from its centre to its edge
+    fn circle(&mut self, centre: Point, radius: u16, colour: RGB8) {
+        let centre: WidePoint = (centre.0.into(), centre.1.into());
+        for (row, col) in circle_octant(radius) {
+            for (row, col) in [(row, col), (col, row)] {
+                let (row, col) = (row as i64, col as i64);
+                plot_wide(self, (centre.0 - row, centre.1 - col), colour);
+                plot_wide(self, (centre.0 - row, centre.1 + col), colour);
+                plot_wide(self, (centre.0 + row, centre.1 - col), colour);
+                plot_wide(self, (centre.0 + row, centre.1 + col), colour);
+            }
+        }
+    }
+
+    /// A circle filled in, the same size as the one `circle` draws
+    fn fill_circle(&mut self, centre: Point, radius: u16, colour: RGB8) {
+        let (rows, cols) = self.size();
+        let centre: WidePoint = (centre.0.into(), centre.1.into());
+        for (row, col) in circle_octant(radius) {
+            for (row, col) in [(row, col), (col, row)] {
+                let (row, col) = (row as i64, col as i64);
+                let span = clip_range(centre.1 - col, centre.1 + col, cols);
+                for row in [centre.0 - row, centre.0 + row] {
+                    fill_clipped(self, clip_range(row, row, rows), span.clone(), colour);
+                }
+            }
+        }
+    }
+
+    /// Fill the area around `start` that is the same colour as it, spreading between pixels
+    /// that share an edge. Displays with more than `MAX_PIXELS` pixels aren't filled.
+    fn flood_fill(&mut self, start: (usize, usize), colour: RGB8) -> Result<(), UpdateError> {
+        let (rows, cols) = self.size();
+        if rows * cols > MAX_PIXELS {
+            return Err(UpdateError::UnsupportedSize { rows, cols });
+        }
+        let Some(&target) = self.get(start.0, start.1) else {
+            return Ok(());
+        };
+        if target == colour {
+            return Ok(());
+        }
+        // which pixels have been filled, counting along the rows from the top left, to tell
+        // them apart from pixels that were already the colour when rescanning
+        let mut filled = [0u32; MAX_PIXELS / 32];
+        let mut stack = Vec::<(usize, usize), FLOOD_STACK_SIZE>::new();
+        let _ = stack.push(start);
+        let mut overflowed = false;
+        loop {
+            while let Some((row, col)) = stack.pop() {
+                if self.get(row, col) != Some(&target) {
+                    continue;
+                }
+                let matches = |display: &Self, col: usize| display.get(row, col) == Some(&target);
+                let left = (0..col)
+                    .rev()
+                    .find(|&c| !matches(self, c))
+                    .map_or(0, |c| c + 1);
+                let right = (col..cols).find(|&c| !matches(self, c)).unwrap_or(cols);
+                for col in left..right {
+                    filled[(row * cols + col) / 32] |= 1 << ((row * cols + col) % 32);
+                }
+                fill_clipped(self, row..row + 1, left..right, colour);
+                // a seed for each run of the colour above and below
+                for next in [row.wrapping_sub(1), row + 1] {
+                    let mut in_run = false;
+                    for col in left..right {
+                        let matches = self.get(next, col) == Some(&target);
+                        if matches && !in_run {
+                            overflowed |= stack.push((next, col)).is_err();
+                        }
+                        in_run = matches;
+                    }
+                }
+            }
+            if !overflowed {
+                return Ok(());
+            }
+            overflowed = false;
+            // look for the seeds that were dropped, next to the pixels that were filled
+            'rescan: for index in 0..rows * cols {
+                if filled[index / 32] & (1 << (index % 32)) == 0 {
+                    continue;
+                }
+                let (row, col) = (index / cols, index % cols);
+                for (next_row, next_col) in [
+                    (row.wrapping_sub(1), col),
+                    (row + 1, col),
+                    (row, col.wrapping_sub(1)),
+                    (row, col + 1),
+                ] {
+                    if self.get(next_row, next_col) == Some(&target)
+                        && stack.push((next_row, next_col)).is_err()
+                    {
+                        overflowed = true;
+                        break 'rescan;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Copy an image onto the display with its top left at `at`, leaving out the parts that
+    /// don't fit. The image is `cols` pixels wide, stored a row at a time.
+    fn blit(&mut self, pixels: &[RGB8], cols: usize, at: Point) {
+        if cols == 0 {
+            return;
+        }
+        for (row, pixels) in pixels.chunks(cols).enumerate() {
+            for (col, &pixel) in pixels.iter().enumerate() {
+                plot_wide(
+                    self,
+                    (at.0 as i64 + row as i64, at.1 as i64 + col as i64),
+                    pixel,
+                );
+            }
+        }
+    }
+
+    /// Move everything on the display down `rows` and right `cols`, or up and left for
+    /// negative amounts. The pixels left behind are set to `fill`.
+    fn shift(&mut self, rows: i32, cols: i32, fill: RGB8) {
+        let (height, width) = self.size();
+        // read each pixel before anything is moved over it
+        let order = |len: usize, forwards: bool| {
+            (0..len).map(move |i| if forwards { i } else { len - 1 - i })
+        };
+        for row in order(height, rows <= 0) {
+            for col in order(width, cols <= 0) {
+                let from = (row as i64 - rows as i64, col as i64 - cols as i64);
+                let colour = match (usize::try_from(from.0), usize::try_from(from.1)) {
+                    (Ok(r), Ok(c)) => self.get(r, c).copied().unwrap_or(fill),
+                    _ => fill,
+                };
+                if let Some(pixel) = self.get_mut(row, col) {
+                    *pixel = colour;
+                }
+            }
+        }
+    }
+
+    /// Like `shift`, but the pixels moved off one edge come back on at the other
+    fn scroll(&mut self, rows: i32, cols: i32) {
+        let (height, width) = self.size();
+        if height == 0 || width == 0 {
+            return;
+        }
+        // rotating is reversing the whole, then each of the two parts
+        let rows = rows.rem_euclid(height as i32) as usize;
+        let cols = cols.rem_euclid(width as i32) as usize;
+        for row in 0..height {
+            for (start, end) in [(0, width), (0, cols), (cols, width)] {
+                reverse(self, start..end, |c| (row, c));
+            }
+        }
+        for col in 0..width {
+            for (start, end) in [(0, height), (0, rows), (rows, height)] {
+                reverse(self, start..end, |r| (r, col));
+            }
+        }
+    }
+}
+
+impl<D: MatrixDisplay> Draw for D {}
+
+/// Set the pixel at `point`, if it's on the display
+fn plot_wide<D: MatrixDisplay>(display: &mut D, (row, col): WidePoint, colour: RGB8) {
+    if let (Ok(row), Ok(col)) = (usize::try_from(row), usize::try_from(col)) {
+        if let Some(pixel) = display.get_mut(row, col) {
+            *pixel = colour;
+        }
+    }
+}
+
+/// The positions from `a` to `b`, or `b` to `a`, that are less than `len`
+fn clip_range(a: i64, b: i64, len: usize) -> core::ops::Range<usize> {
+    let start = usize::try_from(a.min(b).max(0)).unwrap_or(len);
+    let end = usize::try_from(a.max(b)).map_or(0, |end| end.saturating_add(1).min(len));
+    start..end
+}
+
+/// How many steps of `step` it takes to get from `from` into `0..len`, or `None` if it never
+/// gets there
+fn steps_onto(from: i64, step: i64, len: i64) -> Option<i64> {
+    match step {
+        _ if (0..len).contains(&from) => Some(0),
+        1 if from < 0 => Some(-from),
+        -1 if from >= len => Some(from - len + 1),
+        _ => None,
+    }
+}
+
+/// Where a line `d_major` steps long and `d_minor` across starts on the display: the number of
+/// steps along, the number across, and the error term for the next step, for the first step that
+/// is at least `major_offset` along and `minor_offset` across. This is worked out once for each
+/// line, in a wider type as the ends can be as far apart as any two points.
+fn line_start(
+    d_major: i64,
+    d_minor: i64,
+    major_offset: i64,
+    minor_offset: i64,
+) -> Option<(i64, i64, i64)> {
+    let (a, b) = (d_major as i128, d_minor as i128);
+    // the line moves across at the steps where the true line passes half way between pixels
+    let first_across = match minor_offset as i128 {
+        0 => 0,
+        _ if b == 0 => return None,
+        offset => ((2 * offset - 1) * a + 1 + 2 * b - 1) / (2 * b),
+    };
+    let i = (major_offset as i128).max(first_across);
+    if i > a {
+        return None;
+    }
+    let across = match a {
+        0 => 0,
+        _ => (2 * i * b + a - 1) / (2 * a),
+    };
+    let error = 2 * (i + 1) * b - (2 * across + 1) * a;
+    Some((i as i64, across as i64, error as i64))
+}
+
+fn fill_clipped<D: MatrixDisplay>(
+    display: &mut D,
+    rows: core::ops::Range<usize>,
+    cols: core::ops::Range<usize>,
+    colour: RGB8,
+) {
+    for row in rows {
+        for col in cols.clone() {
+            if let Some(pixel) = display.get_mut(row, col) {
+                *pixel = colour;
+            }
+        }
+    }
+}
+
+/// Reverse the pixels at `position(i)` for each `i` in `range`
+fn reverse<D: MatrixDisplay>(
+    display: &mut D,
+    range: core::ops::Range<usize>,
+    position: impl Fn(usize) -> (usize, usize),
+) {
+    let (mut start, mut end) = (range.start, range.end);
+    while start + 1 < end {
+        end -= 1;
+        let (a, b) = (position(start), position(end));
+        if let (Some(&first), Some(&second)) = (display.get(a.0, a.1), display.get(b.0, b.1)) {
+            *display.get_mut(a.0, a.1).unwrap() = second;
+            *display.get_mut(b.0, b.1).unwrap() = first;
+        }
+        start += 1;
+    }
+}
+
+/// The points of one eighth of a circle around the origin, going from (0, radius) until the
+/// row and column meet. The rest of the circle is these with the coordinates swapped or negated.
+fn circle_octant(radius: u16) -> impl Iterator<Item = (i32, i32)> {
+    let radius = radius as i32;
+    // the midpoint algorithm, which moves in a column whenever the next row would be outside
+    let (mut row, mut col, mut error) = (0, radius, 1 - radius);
+    core::iter::from_fn(move || {
+        if row > col {
+            return None;
+        }
+        let point = (row, col);
+        row += 1;
+        if error < 0 {
+            error += 2 * row + 1;
+        } else {
+            col -= 1;
+            error += 2 * (row - col) + 1;
+        }
+        Some(point)
+    })
+}
@@ -0,0 +1,65 @@
+use core::convert::Infallible;
+
+use embedded_graphics_core::pixelcolor::{Rgb888, RgbColor};
+use embedded_graphics_core::prelude::{DrawTarget, OriginDimensions, Pixel, Size};
+use embedded_graphics_core::primitives::Rectangle;
+
+use super::Draw;
+use crate::{MatrixDisplay, RGB8};
+
+/// Lets embedded-graphics draw on a display, so its fonts and shapes can be used on the matrix.
+/// Its x runs along the columns and its y down the rows.
+pub struct Canvas<'a, D> {
+    display: &'a mut D,
+}
+
+impl<'a, D: MatrixDisplay> Canvas<'a, D> {
+    pub fn new(display: &'a mut D) -> Self {
+        Self { display }
+    }
+}
+
+impl From<Rgb888> for RGB8 {
+    fn from(colour: Rgb888) -> Self {
+        RGB8::new(colour.r(), colour.g(), colour.b())
+    }
+}
+
+impl<D: MatrixDisplay> OriginDimensions for Canvas<'_, D> {
+    fn size(&self) -> Size {
+        let (rows, cols) = self.display.size();
+        Size::new(cols as u32, rows as u32)
+    }
+}
+
+impl<D: MatrixDisplay> DrawTarget for Canvas<'_, D> {
+    type Color = Rgb888;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, colour) in pixels {
+            self.display.plot((point.y, point.x), colour.into());
+        }
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, colour: Self::Color) -> Result<(), Self::Error> {
+        if let Some(bottom_right) = area.bottom_right() {
+            let top_left = area.top_left;
+            self.display.fill_rect(
+                (top_left.y, top_left.x),
+                (bottom_right.y, bottom_right.x),
+                colour.into(),
+            );
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, colour: Self::Color) -> Result<(), Self::Error> {
+        self.display.fill(colour.into());
+        Ok(())
+    }
+}
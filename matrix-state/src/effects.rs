@@ -0,0 +1,199 @@
+//! Effects for the matrix to show, which `create_matrix_state!` combines into scenes. Each one
+//! takes a message with its parameters and sets its own frame time. They only use integer maths
+//! and fixed-size buffers, so they run the same on the RP2040 as in the simulator, and their
+//! randomness comes from a seeded `Rng` so runs can be repeated.
+
+use serde::{Deserialize, Serialize};
+
+use crate::RGB8;
+
+mod fire;
+mod life;
+mod plasma;
+mod rain;
+mod rainbow;
+mod solid;
+mod twinkle;
+
+pub use fire::{Fire, FireMessage};
+pub use life::{Life, LifeMessage};
+pub use plasma::{Plasma, PlasmaMessage};
+pub use rain::{Rain, RainMessage};
+pub use rainbow::{Rainbow, RainbowMessage};
+pub use solid::Solid;
+pub use twinkle::{Twinkle, TwinkleMessage};
+
+/// The most pixels the effects that keep something for each pixel have room for. Pixels past
+/// this are left dark.
+pub const MAX_PIXELS: usize = 1024;
+
+/// The seed effects use unless they're given one
+const DEFAULT_SEED: u32 = 0x2545_f491;
+
+/// A small xorshift random number generator
+#[derive(Debug, Clone)]
+pub struct Rng(u32);
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self::new(DEFAULT_SEED)
+    }
+}
+
+impl Rng {
+    pub const fn new(seed: u32) -> Self {
+        // xorshift never leaves zero
+        Self(if seed == 0 { DEFAULT_SEED } else { seed })
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        x
+    }
+
+    /// A number from 0 up to, but not including, `n`
+    pub fn below(&mut self, n: u32) -> u32 {
+        ((self.next_u32() as u64 * n as u64) >> 32) as u32
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        (self.next_u32() >> 24) as u8
+    }
+
+    /// True `chance` times in 256
+    pub fn chance(&mut self, chance: u8) -> bool {
+        self.next_u8() < chance
+    }
+}
+
+/// The colours an effect picks from, indexed from 0 to 255
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Palette {
+    /// every hue, wrapping back round to red
+    #[default]
+    Rainbow,
+    /// black through red and yellow to white
+    Heat,
+    /// deep blue through cyan to white
+    Ocean,
+    /// dark green through green to yellow
+    Forest,
+    /// black up to one colour, given as (r, g, b)
+    Single((u8, u8, u8)),
+}
+
+impl Palette {
+    /// The colour at `index` along the palette
+    pub fn colour(&self, index: u8) -> RGB8 {
+        match *self {
+            Palette::Rainbow => hue(index),
+            Palette::Heat => gradient(
+                &[(0, 0, 0), (255, 0, 0), (255, 255, 0), (255, 255, 255)],
+                index,
+            ),
+            Palette::Ocean => gradient(
+                &[(0, 0, 40), (0, 40, 255), (0, 255, 255), (255, 255, 255)],
+                index,
+            ),
+            Palette::Forest => gradient(
+                &[(0, 20, 0), (0, 128, 0), (100, 255, 0), (255, 255, 60)],
+                index,
+            ),
+            Palette::Single((r, g, b)) => scale(RGB8::new(r, g, b), index),
+        }
+    }
+}
+
+/// A point on the colour wheel, with red at 0, green at 85 and blue at 170
+pub fn hue(h: u8) -> RGB8 {
+    match h {
+        0..=84 => RGB8::new(255 - h * 3, h * 3, 0),
+        85..=169 => {
+            let h = h - 85;
+            RGB8::new(0, 255 - h * 3, h * 3)
+        }
+        _ => {
+            let h = h - 170;
+            RGB8::new(h * 3, 0, 255 - h * 3)
+        }
+    }
+}
+
+/// Blend evenly between four colours
+fn gradient(stops: &[(u8, u8, u8); 4], index: u8) -> RGB8 {
+    let segment = (index / 85).min(2);
+    // from 0 to 255 along the segment
+    let t = (index - segment * 85) as u16 * 3;
+    let (from, to) = (stops[segment as usize], stops[segment as usize + 1]);
+    let lerp = |a: u8, b: u8| ((a as u16 * (255 - t) + b as u16 * t) / 255) as u8;
+    RGB8::new(lerp(from.0, to.0), lerp(from.1, to.1), lerp(from.2, to.2))
+}
+
+/// Dim a colour to `amount` / 255 of its brightness
+pub fn scale(colour: RGB8, amount: u8) -> RGB8 {
+    let scale = |c: u8| ((c as u16 * (amount as u16 + 1)) >> 8) as u8;
+    RGB8::new(scale(colour.r), scale(colour.g), scale(colour.b))
+}
+
+/// A quarter of a sine wave with an amplitude of 127, in 64 steps
+const QUARTER_SINE: [u8; 65] = [
+    0, 3, 6, 9, 12, 16, 19, 22, 25, 28, 31, 34, 37, 40, 43, 46, 49, 51, 54, 57, 60, 63, 65, 68, 71,
+    73, 76, 78, 81, 83, 85, 88, 90, 92, 94, 96, 98, 100, 102, 104, 106, 107, 109, 111, 112, 113,
+    115, 116, 117, 118, 120, 121, 122, 122, 123, 124, 125, 125, 126, 126, 126, 127, 127, 127, 127,
+];
+
+/// A sine wave with a period of 256, going from 1 to 255 around 128
+pub fn sin8(x: u8) -> u8 {
+    let i = (x & 63) as usize;
+    match x >> 6 {
+        0 => 128 + QUARTER_SINE[i],
+        1 => 128 + QUARTER_SINE[64 - i],
+        2 => 128 - QUARTER_SINE[i],
+        _ => 128 - QUARTER_SINE[64 - i],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{hue, sin8, Palette, Rng};
+
+    #[test]
+    fn sine_wave() {
+        assert_eq!(
+            [sin8(0), sin8(64), sin8(128), sin8(192)],
+            [128, 255, 128, 1]
+        );
+        assert_eq!(sin8(32), 128 + 90);
+        assert_eq!(sin8(96), 128 + 90);
+        assert_eq!(sin8(160), 128 - 90);
+        // rising for the first quarter
+        assert!((0..64).all(|x| sin8(x) <= sin8(x + 1)));
+    }
+
+    #[test]
+    fn palette_ends() {
+        let rgb = |c: crate::RGB8| (c.r, c.g, c.b);
+        assert_eq!(rgb(hue(0)), (255, 0, 0));
+        assert_eq!(rgb(hue(85)), (0, 255, 0));
+        assert_eq!(rgb(hue(170)), (0, 0, 255));
+        assert_eq!(rgb(Palette::Heat.colour(0)), (0, 0, 0));
+        assert_eq!(rgb(Palette::Heat.colour(255)), (255, 255, 255));
+        assert_eq!(
+            rgb(Palette::Single((200, 100, 0)).colour(255)),
+            (200, 100, 0)
+        );
+        assert_eq!(rgb(Palette::Single((200, 100, 0)).colour(0)), (0, 0, 0));
+    }
+
+    #[test]
+    fn rng_is_seeded() {
+        let (mut a, mut b) = (Rng::new(7), Rng::new(7));
+        assert!((0..100).all(|_| a.next_u32() == b.next_u32()));
+        assert_ne!(Rng::new(0).next_u32(), 0);
+        assert!((0..1000).all(|_| a.below(10) < 10));
+    }
+}
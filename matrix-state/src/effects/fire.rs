@@ -0,0 +1,130 @@
+use serde::{Deserialize, Serialize};
+
+use super::{Palette, Rng, MAX_PIXELS};
+use crate::{FrameTime, MatrixDisplay, Snapshot, UpdateError, Updateable};
+
+/// Flames rising from the bottom row. Each pixel has a heat, which drifts upwards and cools,
+/// and random sparks near the bottom keep the fire going.
+#[derive(Debug)]
+pub struct Fire {
+    parameters: FireMessage,
+    /// the heat of each pixel, counting along the rows from the top left
+    heat: [u8; MAX_PIXELS],
+    rng: Rng,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FireMessage {
+    /// how quickly the flames cool as they rise, so how tall they are
+    pub cooling: u8,
+    /// the chance out of 256 of a new spark in each column every frame
+    pub sparking: u8,
+    pub palette: Palette,
+}
+
+impl Default for FireMessage {
+    fn default() -> Self {
+        Self {
+            cooling: 55,
+            sparking: 120,
+            palette: Palette::Heat,
+        }
+    }
+}
+
+impl Default for Fire {
+    fn default() -> Self {
+        Self::with_seed(0)
+    }
+}
+
+impl Fire {
+    pub fn with_seed(seed: u32) -> Self {
+        Self {
+            parameters: FireMessage::default(),
+            heat: [0; MAX_PIXELS],
+            rng: Rng::new(seed),
+        }
+    }
+
+    fn step(&mut self, rows: usize, cols: usize) {
+        let FireMessage {
+            cooling, sparking, ..
+        } = self.parameters;
+        let max_cooling = cooling as u32 * 10 / rows as u32 + 2;
+        for col in 0..cols {
+            let index = |row: usize| row * cols + col;
+            for row in 0..rows {
+                let cooled = self.rng.below(max_cooling) as u8;
+                self.heat[index(row)] = self.heat[index(row)].saturating_sub(cooled);
+            }
+            // heat drifts up, spreading out a little
+            for row in 0..rows.saturating_sub(2) {
+                let (below, further) = (self.heat[index(row + 1)], self.heat[index(row + 2)]);
+                self.heat[index(row)] = ((below as u16 + 2 * further as u16) / 3) as u8;
+            }
+            if self.rng.chance(sparking) {
+                let row = rows - 1 - self.rng.below(rows.min(3) as u32) as usize;
+                let spark = 160 + self.rng.below(96) as u8;
+                self.heat[index(row)] = self.heat[index(row)].saturating_add(spark);
+            }
+        }
+    }
+}
+
+impl Updateable for Fire {
+    type Message = FireMessage;
+
+    fn update<D: MatrixDisplay>(&mut self, message: Option<Self::Message>, display: &mut D) {
+        let _ = self.try_update(message, display);
+    }
+
+    fn try_update<D: MatrixDisplay>(
+        &mut self,
+        message: Option<Self::Message>,
+        display: &mut D,
+    ) -> Result<(), UpdateError> {
+        let (all_rows, cols) = display.size();
+        // only as many rows as there's room for, counting up from the bottom
+        let rows = all_rows.min(MAX_PIXELS / cols.max(1));
+        let top = all_rows - rows;
+        match message {
+            Some(parameters) => self.parameters = parameters,
+            None if rows > 0 => self.step(rows, cols),
+            None => {}
+        }
+        for ((row, col), pixel) in display.iter_mut() {
+            let heat = if row >= top {
+                self.heat[(row - top) * cols + col]
+            } else {
+                0
+            };
+            // the hottest colours are kept for the brightest sparks
+            *pixel = self
+                .parameters
+                .palette
+                .colour(((heat as u16 * 240) / 255) as u8);
+        }
+        if rows < all_rows {
+            return Err(UpdateError::UnsupportedSize {
+                rows: all_rows,
+                cols,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Snapshot for Fire {
+    type Snapshot = FireMessage;
+
+    fn snapshot(&self) -> FireMessage {
+        self.parameters.clone()
+    }
+}
+
+impl FrameTime for Fire {
+    fn frame_time(&self) -> u64 {
+        30
+    }
+}
@@ -0,0 +1,218 @@
+use serde::{Deserialize, Serialize};
+
+use super::{scale, Rng, MAX_PIXELS};
+use crate::{FrameTime, MatrixDisplay, Snapshot, UpdateError, Updateable, RGB8};
+
+const WORDS: usize = MAX_PIXELS / 32;
+
+/// Conway's Game of Life, on a grid that wraps around at the edges. It starts again from a
+/// random grid when everything dies, or it settles into something that doesn't change.
+#[derive(Debug)]
+pub struct Life {
+    parameters: LifeMessage,
+    /// a bit for each pixel, counting along the rows from the top left
+    cells: Cells,
+    /// the generation before, to spot things flipping between two states
+    previous: Cells,
+    /// the size the grid was seeded for
+    size: (usize, usize),
+    rng: Rng,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LifeMessage {
+    /// generations a second, at least one
+    pub speed: u8,
+    /// the chance out of 256 of each cell starting alive
+    pub density: u8,
+    /// the colour of live cells, as (r, g, b)
+    pub colour: (u8, u8, u8),
+}
+
+impl Default for LifeMessage {
+    fn default() -> Self {
+        Self {
+            speed: 8,
+            density: 80,
+            colour: (255, 160, 0),
+        }
+    }
+}
+
+type Cells = [u32; WORDS];
+
+fn alive(cells: &Cells, index: usize) -> bool {
+    index < MAX_PIXELS && cells[index / 32] & (1 << (index % 32)) != 0
+}
+
+impl Default for Life {
+    fn default() -> Self {
+        Self::with_seed(0)
+    }
+}
+
+impl Life {
+    pub fn with_seed(seed: u32) -> Self {
+        Self {
+            parameters: LifeMessage::default(),
+            cells: [0; WORDS],
+            previous: [0; WORDS],
+            size: (0, 0),
+            rng: Rng::new(seed),
+        }
+    }
+
+    fn seed(&mut self, rows: usize, cols: usize) {
+        self.size = (rows, cols);
+        self.previous = [0; WORDS];
+        self.cells = [0; WORDS];
+        for index in 0..(rows * cols).min(MAX_PIXELS) {
+            if self.rng.chance(self.parameters.density) {
+                self.cells[index / 32] |= 1 << (index % 32);
+            }
+        }
+    }
+
+    /// The next generation, by the usual rules: live cells with two or three neighbours
+    /// survive, and dead cells with three come alive
+    fn next_generation(&self) -> Cells {
+        let (rows, cols) = self.size;
+        let mut next = [0; WORDS];
+        for row in 0..rows {
+            for col in 0..cols {
+                let index = row * cols + col;
+                if index >= MAX_PIXELS {
+                    return next;
+                }
+                let mut neighbours = 0;
+                for (dr, dc) in [(rows - 1, cols - 1), (rows - 1, 0), (rows - 1, 1)]
+                    .into_iter()
+                    .chain([(0, cols - 1), (0, 1)])
+                    .chain([(1, cols - 1), (1, 0), (1, 1)])
+                {
+                    let neighbour = (row + dr) % rows * cols + (col + dc) % cols;
+                    neighbours += alive(&self.cells, neighbour) as u8;
+                }
+                if neighbours == 3 || (neighbours == 2 && alive(&self.cells, index)) {
+                    next[index / 32] |= 1 << (index % 32);
+                }
+            }
+        }
+        next
+    }
+
+    fn step(&mut self) {
+        let next = self.next_generation();
+        if next == self.cells || next == self.previous || next == [0; WORDS] {
+            let (rows, cols) = self.size;
+            self.seed(rows, cols);
+        } else {
+            self.previous = core::mem::replace(&mut self.cells, next);
+        }
+    }
+}
+
+impl Updateable for Life {
+    type Message = LifeMessage;
+
+    fn update<D: MatrixDisplay>(&mut self, message: Option<Self::Message>, display: &mut D) {
+        let _ = self.try_update(message, display);
+    }
+
+    fn try_update<D: MatrixDisplay>(
+        &mut self,
+        message: Option<Self::Message>,
+        display: &mut D,
+    ) -> Result<(), UpdateError> {
+        if message
+            .as_ref()
+            .is_some_and(|parameters| parameters.speed == 0)
+        {
+            return Err(UpdateError::out_of_range("speed", 1, u8::MAX as u32));
+        }
+        let (rows, cols) = display.size();
+        if let Some(parameters) = message {
+            self.parameters = parameters;
+            self.seed(rows, cols);
+        } else if self.size != (rows, cols) {
+            self.seed(rows, cols);
+        } else if rows > 0 && cols > 0 {
+            self.step();
+        }
+        let (r, g, b) = self.parameters.colour;
+        for ((row, col), pixel) in display.iter_mut() {
+            *pixel = if alive(&self.cells, row * cols + col) {
+                RGB8::new(r, g, b)
+            } else {
+                // cells that have died leave a fading mark
+                scale(*pixel, 64)
+            };
+        }
+        if rows * cols > MAX_PIXELS {
+            return Err(UpdateError::UnsupportedSize { rows, cols });
+        }
+        Ok(())
+    }
+}
+
+impl Snapshot for Life {
+    type Snapshot = LifeMessage;
+
+    fn snapshot(&self) -> LifeMessage {
+        self.parameters.clone()
+    }
+}
+
+impl FrameTime for Life {
+    fn frame_time(&self) -> u64 {
+        1000 / self.parameters.speed as u64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use heapless::Vec;
+
+    use super::{alive, Life};
+
+    fn life_with(rows: usize, cols: usize, live: &[(usize, usize)]) -> Life {
+        let mut life = Life {
+            size: (rows, cols),
+            ..Life::default()
+        };
+        for &(row, col) in live {
+            let index = row * cols + col;
+            life.cells[index / 32] |= 1 << (index % 32);
+        }
+        life
+    }
+
+    fn live_cells(life: &Life) -> Vec<(usize, usize), 32> {
+        let (rows, cols) = life.size;
+        (0..rows * cols)
+            .filter(|&i| alive(&life.cells, i))
+            .map(|i| (i / cols, i % cols))
+            .collect()
+    }
+
+    #[test]
+    fn blinker_flips() {
+        let mut life = life_with(5, 5, &[(2, 1), (2, 2), (2, 3)]);
+        life.step();
+        assert_eq!(live_cells(&life), [(1, 2), (2, 2), (3, 2)]);
+        // flipping back would repeat the generation before, so it starts again instead
+        life.step();
+        assert_ne!(live_cells(&life), [(2, 1), (2, 2), (2, 3)]);
+    }
+
+    #[test]
+    fn gliders_wrap_around() {
+        let glider = [(0, 1), (1, 2), (2, 0), (2, 1), (2, 2)];
+        let mut life = life_with(6, 6, &glider);
+        // a glider moves one cell down and right every four generations
+        for _ in 0..24 {
+            life.step();
+        }
+        assert_eq!(live_cells(&life), glider);
+    }
+}
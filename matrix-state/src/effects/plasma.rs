@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+
+use super::{sin8, Palette};
+use crate::{FrameTime, MatrixDisplay, Snapshot, Updateable};
+
+/// Slowly shifting blobs of colour, from sine waves running across, down and diagonally
+#[derive(Debug, Default)]
+pub struct Plasma {
+    parameters: PlasmaMessage,
+    time: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlasmaMessage {
+    /// how quickly the waves move
+    pub speed: u8,
+    /// how tightly packed the blobs are
+    pub scale: u8,
+    pub palette: Palette,
+}
+
+impl Default for PlasmaMessage {
+    fn default() -> Self {
+        Self {
+            speed: 3,
+            scale: 16,
+            palette: Palette::Rainbow,
+        }
+    }
+}
+
+impl Updateable for Plasma {
+    type Message = PlasmaMessage;
+
+    fn update<D: MatrixDisplay>(&mut self, message: Option<Self::Message>, display: &mut D) {
+        match message {
+            Some(parameters) => self.parameters = parameters,
+            None => self.time = self.time.wrapping_add(self.parameters.speed as u32),
+        }
+        let PlasmaMessage { scale, palette, .. } = self.parameters;
+        // each wave moves at its own speed, so the pattern doesn't repeat quickly
+        let t = self.time;
+        let (t1, t2, t3) = (t as u8, (t / 2) as u8, (t / 3) as u8);
+        for ((row, col), pixel) in display.iter_mut() {
+            let x = (col * scale as usize) as u8;
+            let y = (row * scale as usize) as u8;
+            let across = sin8(x.wrapping_add(t1)) as u16;
+            let down = sin8(y.wrapping_sub(t2)) as u16;
+            let diagonal = sin8((x / 2).wrapping_add(y / 2).wrapping_add(t3)) as u16;
+            let swirl = sin8(sin8(x.wrapping_add(t2)).wrapping_add(y)) as u16;
+            let value = ((across + down + diagonal + swirl) / 4) as u8;
+            *pixel = palette.colour(value.wrapping_add((t / 8) as u8));
+        }
+    }
+}
+
+impl Snapshot for Plasma {
+    type Snapshot = PlasmaMessage;
+
+    fn snapshot(&self) -> PlasmaMessage {
+        self.parameters.clone()
+    }
+}
+
+impl FrameTime for Plasma {
+    fn frame_time(&self) -> u64 {
+        30
+    }
+}
@@ -0,0 +1,136 @@
+use heapless::Vec;
+use serde::{Deserialize, Serialize};
+
+use super::{scale, Rng};
+use crate::{Draw, FrameTime, MatrixDisplay, Snapshot, UpdateError, Updateable, RGB8};
+
+/// The most drops falling at once
+const MAX_DROPS: usize = 64;
+const FRAME_TIME: u64 = 40;
+
+/// Drops falling down the columns, leaving trails that fade behind them
+#[derive(Debug, Default)]
+pub struct Rain {
+    parameters: RainMessage,
+    drops: Vec<Drop, MAX_DROPS>,
+    rng: Rng,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RainMessage {
+    /// how many rows a second the drops fall, on average, at least one
+    pub speed: u8,
+    /// the chance out of 256 of a new drop every frame
+    pub density: u8,
+    /// the colour of the trails, as (r, g, b)
+    pub colour: (u8, u8, u8),
+}
+
+impl Default for RainMessage {
+    fn default() -> Self {
+        Self {
+            speed: 12,
+            density: 96,
+            colour: (0, 255, 64),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Drop {
+    col: usize,
+    /// in 256ths of a row
+    position: u32,
+    /// in 256ths of a row each frame
+    speed: u32,
+}
+
+impl Rain {
+    pub fn with_seed(seed: u32) -> Self {
+        Self {
+            parameters: RainMessage::default(),
+            drops: Vec::new(),
+            rng: Rng::new(seed),
+        }
+    }
+}
+
+impl Updateable for Rain {
+    type Message = RainMessage;
+
+    fn update<D: MatrixDisplay>(&mut self, message: Option<Self::Message>, display: &mut D) {
+        let _ = self.try_update(message, display);
+    }
+
+    fn try_update<D: MatrixDisplay>(
+        &mut self,
+        message: Option<Self::Message>,
+        display: &mut D,
+    ) -> Result<(), UpdateError> {
+        if let Some(parameters) = message {
+            // drops that don't move would never leave room for new ones
+            if parameters.speed == 0 {
+                return Err(UpdateError::out_of_range("speed", 1, u8::MAX as u32));
+            }
+            self.parameters = parameters;
+            self.drops.clear();
+            display.clear();
+            return Ok(());
+        }
+        let (rows, cols) = display.size();
+        let RainMessage {
+            speed,
+            density,
+            colour: (r, g, b),
+        } = self.parameters;
+        let trail = RGB8::new(r, g, b);
+        // the head of each drop is the trail's colour, halfway to white
+        let head = RGB8::new(r / 2 + 128, g / 2 + 128, b / 2 + 128);
+
+        for (_, pixel) in display.iter_mut() {
+            *pixel = scale(*pixel, 200);
+        }
+        if cols > 0 && self.rng.chance(density) {
+            // each drop falls at between half and one and a half times the speed
+            let average = speed as u32 * 256 * FRAME_TIME as u32 / 1000;
+            let _ = self.drops.push(Drop {
+                col: self.rng.below(cols as u32) as usize,
+                position: 0,
+                speed: average / 2 + self.rng.below(average + 1),
+            });
+        }
+        self.drops.retain_mut(|drop| {
+            let from = (drop.position >> 8) as usize;
+            drop.position += drop.speed;
+            let to = (drop.position >> 8) as usize;
+            // the rows passed over become part of the trail, so fast drops don't leave gaps
+            for row in from..to.min(rows) {
+                if let Some(pixel) = display.get_mut(row, drop.col) {
+                    *pixel = trail;
+                }
+            }
+            match display.get_mut(to, drop.col) {
+                Some(pixel) => {
+                    *pixel = head;
+                    true
+                }
+                None => false,
+            }
+        });
+        Ok(())
+    }
+}
+
+impl Snapshot for Rain {
+    type Snapshot = RainMessage;
+
+    fn snapshot(&self) -> RainMessage {
+        self.parameters.clone()
+    }
+}
+
+impl FrameTime for Rain {
+    fn frame_time(&self) -> u64 {
+        FRAME_TIME
+    }
+}
@@ -0,0 +1,62 @@
+use serde::{Deserialize, Serialize};
+
+use super::Palette;
+use crate::{FrameTime, MatrixDisplay, Snapshot, Updateable};
+
+/// Sweep a palette diagonally across the matrix
+#[derive(Debug, Default)]
+pub struct Rainbow {
+    parameters: RainbowMessage,
+    phase: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RainbowMessage {
+    /// how far along the palette the sweep moves each frame
+    pub speed: u8,
+    /// how far along the palette each step across or down the matrix is
+    pub spread: u8,
+    pub palette: Palette,
+}
+
+impl Default for RainbowMessage {
+    fn default() -> Self {
+        Self {
+            speed: 2,
+            spread: 8,
+            palette: Palette::Rainbow,
+        }
+    }
+}
+
+impl Updateable for Rainbow {
+    type Message = RainbowMessage;
+
+    fn update<D: MatrixDisplay>(&mut self, message: Option<Self::Message>, display: &mut D) {
+        match message {
+            Some(parameters) => self.parameters = parameters,
+            None => self.phase = self.phase.wrapping_add(self.parameters.speed),
+        }
+        let RainbowMessage {
+            spread, palette, ..
+        } = self.parameters;
+        for ((row, col), pixel) in display.iter_mut() {
+            let offset = ((row + col) * spread as usize) as u8;
+            *pixel = palette.colour(self.phase.wrapping_add(offset));
+        }
+    }
+}
+
+impl Snapshot for Rainbow {
+    type Snapshot = RainbowMessage;
+
+    fn snapshot(&self) -> RainbowMessage {
+        self.parameters.clone()
+    }
+}
+
+impl FrameTime for Rainbow {
+    fn frame_time(&self) -> u64 {
+        20
+    }
+}
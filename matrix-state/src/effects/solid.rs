@@ -0,0 +1,32 @@
+use crate::{Draw, FrameTime, MatrixDisplay, Snapshot, Updateable, RGB8};
+
+/// Fill the whole matrix with one colour, given as (r, g, b)
+#[derive(Debug, Default)]
+pub struct Solid {
+    colour: RGB8,
+}
+
+impl Updateable for Solid {
+    type Message = (u8, u8, u8);
+
+    fn update<D: MatrixDisplay>(&mut self, message: Option<Self::Message>, display: &mut D) {
+        if let Some((r, g, b)) = message {
+            self.colour = RGB8::new(r, g, b);
+        }
+        display.fill(self.colour);
+    }
+}
+
+impl Snapshot for Solid {
+    type Snapshot = (u8, u8, u8);
+
+    fn snapshot(&self) -> (u8, u8, u8) {
+        (self.colour.r, self.colour.g, self.colour.b)
+    }
+}
+
+impl FrameTime for Solid {
+    fn frame_time(&self) -> u64 {
+        1000
+    }
+}
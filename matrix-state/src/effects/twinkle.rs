@@ -0,0 +1,78 @@
+use serde::{Deserialize, Serialize};
+
+use super::{scale, Palette, Rng};
+use crate::{FrameTime, MatrixDisplay, Snapshot, Updateable};
+
+/// Pixels light up at random and fade away. The fading is done on the pixels read back from
+/// the display, so nothing is kept for each pixel.
+#[derive(Debug, Default)]
+pub struct Twinkle {
+    parameters: TwinkleMessage,
+    rng: Rng,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TwinkleMessage {
+    /// the chance out of 4096 of each pixel lighting up every frame
+    pub density: u8,
+    /// how much of their brightness lit pixels lose each frame, out of 256
+    pub fade: u8,
+    pub palette: Palette,
+}
+
+impl Default for TwinkleMessage {
+    fn default() -> Self {
+        Self {
+            density: 16,
+            fade: 24,
+            palette: Palette::Rainbow,
+        }
+    }
+}
+
+impl Twinkle {
+    pub fn with_seed(seed: u32) -> Self {
+        Self {
+            parameters: TwinkleMessage::default(),
+            rng: Rng::new(seed),
+        }
+    }
+}
+
+impl Updateable for Twinkle {
+    type Message = TwinkleMessage;
+
+    fn update<D: MatrixDisplay>(&mut self, message: Option<Self::Message>, display: &mut D) {
+        if let Some(parameters) = message {
+            self.parameters = parameters;
+            // what was shown before fades out on the next frames
+            return;
+        }
+        let TwinkleMessage {
+            density,
+            fade,
+            palette,
+        } = self.parameters;
+        for (_, pixel) in display.iter_mut() {
+            if self.rng.below(4096) < density as u32 {
+                *pixel = palette.colour(self.rng.next_u8());
+            } else {
+                *pixel = scale(*pixel, 255 - fade);
+            }
+        }
+    }
+}
+
+impl Snapshot for Twinkle {
+    type Snapshot = TwinkleMessage;
+
+    fn snapshot(&self) -> TwinkleMessage {
+        self.parameters.clone()
+    }
+}
+
+impl FrameTime for Twinkle {
+    fn frame_time(&self) -> u64 {
+        40
+    }
+}
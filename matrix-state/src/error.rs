@@ -0,0 +1,66 @@
+use core::fmt;
+
+use heapless::String;
+use serde::{Deserialize, Serialize};
+
+const MAX_NAME_LEN: usize = 24;
+
+/// The name of a parameter or variant in an `UpdateError`, which is owned so that clients can
+/// decode the errors they're sent
+pub type Name = String<MAX_NAME_LEN>;
+
+/// Why a state couldn't apply a message, from `Updateable::try_update`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UpdateError {
+    /// A parameter was outside the range the state can use, so the message was ignored
+    OutOfRange { parameter: Name, min: u32, max: u32 },
+    /// A message for one variant was sent while another was active, so it was ignored
+    WrongVariant { active: Name, message: Name },
+    /// The display is too big for the state, which only draws the part of it that fits
+    UnsupportedSize { rows: usize, cols: usize },
+}
+
+impl UpdateError {
+    pub fn out_of_range(parameter: &str, min: u32, max: u32) -> Self {
+        UpdateError::OutOfRange {
+            parameter: name(parameter),
+            min,
+            max,
+        }
+    }
+
+    pub fn wrong_variant(active: &str, message: &str) -> Self {
+        UpdateError::WrongVariant {
+            active: name(active),
+            message: name(message),
+        }
+    }
+}
+
+/// `name`, cut short at a character boundary if it doesn't fit
+fn name(name: &str) -> Name {
+    let mut end = name.len().min(MAX_NAME_LEN);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    String::try_from(&name[..end]).unwrap_or_default()
+}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpdateError::OutOfRange {
+                parameter,
+                min,
+                max,
+            } => write!(f, "{parameter} must be from {min} to {max}"),
+            UpdateError::WrongVariant { active, message } => write!(
+                f,
+                "a message for {message} was sent while {active} is showing"
+            ),
+            UpdateError::UnsupportedSize { rows, cols } => {
+                write!(f, "a {cols}x{rows} display is too big")
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use crate::{ColourCorrection, MatrixDisplay, RGB8};
+
+/// An in-memory display, for rendering without any hardware
+#[derive(Debug, Clone)]
+pub struct FrameBuffer<const ROWS: usize, const COLS: usize> {
+    pixels: [[RGB8; COLS]; ROWS],
+    correction: ColourCorrection,
+}
+
+impl<const ROWS: usize, const COLS: usize> Default for FrameBuffer<ROWS, COLS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const ROWS: usize, const COLS: usize> FrameBuffer<ROWS, COLS> {
+    pub fn new() -> Self {
+        Self {
+            pixels: [[RGB8::default(); COLS]; ROWS],
+            correction: ColourCorrection::default(),
+        }
+    }
+
+    /// The pixels as drawn, before any correction
+    pub fn pixels(&self) -> &[[RGB8; COLS]; ROWS] {
+        &self.pixels
+    }
+
+    pub fn correction(&self) -> ColourCorrection {
+        self.correction
+    }
+
+    /// The pixel at (row, col) as it would be sent to the LEDs
+    pub fn corrected(&self, row: usize, col: usize) -> Option<RGB8> {
+        self.get(row, col).map(|p| self.correction.apply(*p))
+    }
+}
+
+impl<const ROWS: usize, const COLS: usize> MatrixDisplay for FrameBuffer<ROWS, COLS> {
+    fn get_mut(&mut self, row: usize, col: usize) -> Option<&mut RGB8> {
+        self.pixels.get_mut(row)?.get_mut(col)
+    }
+
+    fn get(&self, row: usize, col: usize) -> Option<&RGB8> {
+        self.pixels.get(row)?.get(col)
+    }
+
+    fn size(&self) -> (usize, usize) {
+        (ROWS, COLS)
+    }
+
+    fn set_correction(&mut self, correction: ColourCorrection) {
+        self.correction = correction;
+    }
+}
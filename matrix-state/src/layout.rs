@@ -0,0 +1,213 @@
+use serde::{Deserialize, Serialize};
+
+/// The order the LEDs are chained in along each row of a panel (or of tiles)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Wiring {
+    /// every row runs left to right
+    Progressive,
+    /// rows alternate between left to right and right to left
+    Serpentine,
+}
+
+/// How far a panel is turned clockwise from the orientation it is wired in
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Rotation {
+    R0,
+    R90,
+    R180,
+    R270,
+}
+
+/// Maps logical (row, col) coordinates onto the index of the LED along the strip.
+/// The matrix is a grid of `tile_rows` x `tile_cols` identical panels, each wired as
+/// `panel_rows` x `panel_cols` before being rotated and flipped into place.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Layout {
+    panel_rows: usize,
+    panel_cols: usize,
+    wiring: Wiring,
+    rotation: Rotation,
+    flip_horizontal: bool,
+    flip_vertical: bool,
+    tile_rows: usize,
+    tile_cols: usize,
+    tile_wiring: Wiring,
+}
+
+impl Layout {
+    /// A single progressively wired panel, with no rotation or flips
+    pub const fn new(rows: usize, cols: usize) -> Self {
+        Self {
+            panel_rows: rows,
+            panel_cols: cols,
+            wiring: Wiring::Progressive,
+            rotation: Rotation::R0,
+            flip_horizontal: false,
+            flip_vertical: false,
+            tile_rows: 1,
+            tile_cols: 1,
+            tile_wiring: Wiring::Progressive,
+        }
+    }
+
+    pub const fn with_wiring(mut self, wiring: Wiring) -> Self {
+        self.wiring = wiring;
+        self
+    }
+
+    pub const fn with_rotation(mut self, rotation: Rotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Mirror the columns of each panel
+    pub const fn with_flip_horizontal(mut self, flip: bool) -> Self {
+        self.flip_horizontal = flip;
+        self
+    }
+
+    /// Mirror the rows of each panel
+    pub const fn with_flip_vertical(mut self, flip: bool) -> Self {
+        self.flip_vertical = flip;
+        self
+    }
+
+    /// Chain `rows` x `cols` copies of the panel, in the order given by `wiring`
+    pub const fn with_tiles(mut self, rows: usize, cols: usize, wiring: Wiring) -> Self {
+        self.tile_rows = rows;
+        self.tile_cols = cols;
+        self.tile_wiring = wiring;
+        self
+    }
+
+    /// The size of one panel once it has been rotated into place
+    const fn tile_size(&self) -> (usize, usize) {
+        match self.rotation {
+            Rotation::R0 | Rotation::R180 => (self.panel_rows, self.panel_cols),
+            Rotation::R90 | Rotation::R270 => (self.panel_cols, self.panel_rows),
+        }
+    }
+
+    /// The logical (rows, cols) of the whole matrix
+    pub const fn size(&self) -> (usize, usize) {
+        let (rows, cols) = self.tile_size();
+        (rows * self.tile_rows, cols * self.tile_cols)
+    }
+
+    /// The number of LEDs in the strip
+    pub const fn len(&self) -> usize {
+        self.panel_rows * self.panel_cols * self.tile_rows * self.tile_cols
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    const fn wired_index(wiring: Wiring, row: usize, col: usize, cols: usize) -> usize {
+        match wiring {
+            Wiring::Serpentine if row % 2 == 1 => row * cols + (cols - 1 - col),
+            _ => row * cols + col,
+        }
+    }
+
+    /// The index along the strip of the LED at the logical (row, col)
+    pub const fn index(&self, row: usize, col: usize) -> Option<usize> {
+        let (rows, cols) = self.tile_size();
+        if row >= rows * self.tile_rows || col >= cols * self.tile_cols {
+            return None;
+        }
+        let tile = Self::wired_index(self.tile_wiring, row / rows, col / cols, self.tile_cols);
+        let (mut r, mut c) = (row % rows, col % cols);
+        if self.flip_horizontal {
+            c = cols - 1 - c;
+        }
+        if self.flip_vertical {
+            r = rows - 1 - r;
+        }
+        let (pr, pc) = match self.rotation {
+            Rotation::R0 => (r, c),
+            Rotation::R90 => (self.panel_rows - 1 - c, r),
+            Rotation::R180 => (self.panel_rows - 1 - r, self.panel_cols - 1 - c),
+            Rotation::R270 => (c, self.panel_cols - 1 - r),
+        };
+        Some(
+            tile * self.panel_rows * self.panel_cols
+                + Self::wired_index(self.wiring, pr, pc, self.panel_cols),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Layout, Rotation, Wiring};
+
+    fn indices<const N: usize>(layout: &Layout) -> [usize; N] {
+        let (rows, cols) = layout.size();
+        let mut out = [0; N];
+        for r in 0..rows {
+            for c in 0..cols {
+                out[r * cols + c] = layout.index(r, c).unwrap();
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn test_progressive_and_serpentine() {
+        let layout = Layout::new(2, 3);
+        assert_eq!(indices::<6>(&layout), [0, 1, 2, 3, 4, 5]);
+        assert_eq!(layout.index(2, 0), None);
+        assert_eq!(layout.index(0, 3), None);
+        let layout = layout.with_wiring(Wiring::Serpentine);
+        assert_eq!(indices::<6>(&layout), [0, 1, 2, 5, 4, 3]);
+    }
+
+    #[test]
+    fn test_rotation() {
+        // wired as 2 rows of 3
+        let layout = Layout::new(2, 3);
+        assert_eq!(layout.with_rotation(Rotation::R90).size(), (3, 2));
+        assert_eq!(
+            indices::<6>(&layout.with_rotation(Rotation::R90)),
+            [3, 0, 4, 1, 5, 2]
+        );
+        assert_eq!(
+            indices::<6>(&layout.with_rotation(Rotation::R180)),
+            [5, 4, 3, 2, 1, 0]
+        );
+        assert_eq!(
+            indices::<6>(&layout.with_rotation(Rotation::R270)),
+            [2, 5, 1, 4, 0, 3]
+        );
+    }
+
+    #[test]
+    fn test_flips() {
+        let layout = Layout::new(2, 3);
+        assert_eq!(
+            indices::<6>(&layout.with_flip_horizontal(true)),
+            [2, 1, 0, 5, 4, 3]
+        );
+        assert_eq!(
+            indices::<6>(&layout.with_flip_vertical(true)),
+            [3, 4, 5, 0, 1, 2]
+        );
+    }
+
+    #[test]
+    fn test_tiles() {
+        let layout = Layout::new(2, 2)
+            .with_wiring(Wiring::Serpentine)
+            .with_tiles(2, 2, Wiring::Serpentine);
+        assert_eq!(layout.size(), (4, 4));
+        assert_eq!(layout.len(), 16);
+        #[rustfmt::skip]
+        let expected = [
+            0, 1, 4, 5,
+            3, 2, 7, 6,
+            12, 13, 8, 9,
+            15, 14, 11, 10,
+        ];
+        assert_eq!(indices::<16>(&layout), expected);
+    }
+}
@@ -1,7 +1,27 @@
 #![no_std]
 
+use heapless::Vec;
 use serde::{Deserialize, Serialize};
 
+pub mod api;
+mod correction;
+mod draw;
+pub mod effects;
+mod error;
+mod framebuffer;
+mod layout;
+mod pacing;
+pub mod scene;
+
+pub use correction::{ColourCorrection, GammaTable};
+#[cfg(feature = "embedded-graphics")]
+pub use draw::Canvas;
+pub use draw::{Draw, Point};
+pub use error::UpdateError;
+pub use framebuffer::FrameBuffer;
+pub use layout::{Layout, Rotation, Wiring};
+pub use pacing::{FramePacer, Overrun};
+
 #[derive(Default, Debug, Copy, Clone)]
 #[repr(C, align(4))]
 pub struct RGB8 {
@@ -11,25 +31,120 @@ pub struct RGB8 {
     pub g: u8,
 }
 
+impl RGB8 {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self {
+            padding: 0,
+            b,
+            r,
+            g,
+        }
+    }
+}
+
+/// Colours are the same when their channels are, whatever is in the padding
+impl PartialEq for RGB8 {
+    fn eq(&self, other: &Self) -> bool {
+        (self.r, self.g, self.b) == (other.r, other.g, other.b)
+    }
+}
+
+impl Eq for RGB8 {}
+
 pub trait FrameTime {
+    /// The time between frames, in milliseconds
     fn frame_time(&self) -> u64;
 }
 
+/// States that can describe what they're showing, so clients can be kept in sync with them
+pub trait Snapshot {
+    type Snapshot;
+
+    fn snapshot(&self) -> Self::Snapshot;
+}
+
 pub trait Updateable {
     type Message;
 
     fn update<D: MatrixDisplay>(&mut self, message: Option<Self::Message>, display: &mut D);
+
+    /// Like `update`, but reporting a message that couldn't be applied. States that can reject
+    /// a message override this, and the default accepts everything.
+    fn try_update<D: MatrixDisplay>(
+        &mut self,
+        message: Option<Self::Message>,
+        display: &mut D,
+    ) -> Result<(), UpdateError> {
+        self.update(message, display);
+        Ok(())
+    }
 }
 
 pub struct MatrixState<ImageState> {
     im: ImageState,
     brightness: f32,
+    gamma: &'static GammaTable,
+    /// set when pixels are drawn directly, so the scene doesn't draw over them until it's next
+    /// sent a message
+    paused: bool,
 }
 
-#[derive(Serialize, Deserialize)]
+impl<ImageState> MatrixState<ImageState> {
+    /// Create a new state at full brightness, using the WS2812B gamma table
+    pub fn new(im: ImageState) -> Self {
+        Self {
+            im,
+            brightness: 1.0,
+            gamma: &GammaTable::WS2812B,
+            paused: false,
+        }
+    }
+
+    pub fn with_gamma(mut self, gamma: &'static GammaTable) -> Self {
+        self.gamma = gamma;
+        self
+    }
+
+    pub fn brightness(&self) -> f32 {
+        self.brightness
+    }
+
+    /// Set the brightness, clamping it to [0, 1]
+    pub fn set_brightness(&mut self, brightness: f32) {
+        self.brightness = correction::clamp_brightness(brightness);
+    }
+
+    /// The correction that displays should apply to the pixels they output
+    pub fn correction(&self) -> ColourCorrection {
+        ColourCorrection::new(self.brightness, self.gamma)
+    }
+}
+
+impl<ImageState: FrameTime> FrameTime for MatrixState<ImageState> {
+    fn frame_time(&self) -> u64 {
+        self.im.frame_time()
+    }
+}
+
+/// The most pixels in one `PixelRun`, so messages stay small enough to queue
+pub const MAX_RUN_LEN: usize = 64;
+
+/// Colours for consecutive pixels, as (r, g, b), counting along the rows from the top left
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PixelRun {
+    /// the number of pixels before the first one in the run
+    pub start: u16,
+    pub pixels: Vec<(u8, u8, u8), MAX_RUN_LEN>,
+}
+
+// there's no allocator to box the pixels with, so `MAX_RUN_LEN` keeps them small instead
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum MatrixStateMessage<ImageStateMessage> {
     UpdateBrightness(f32),
     UpdateImage(ImageStateMessage),
+    /// Draw the pixels over the scene, which is paused until its next message
+    DrawPixels(PixelRun),
 }
 
 impl<ImageState, ImageStateMessage> Updateable for MatrixState<ImageState>
@@ -39,11 +154,85 @@ where
     type Message = MatrixStateMessage<ImageStateMessage>;
 
     fn update<D: MatrixDisplay>(&mut self, message: Option<Self::Message>, display: &mut D) {
-        match message {
-            Some(MatrixStateMessage::UpdateBrightness(b)) => (),
-            Some(MatrixStateMessage::UpdateImage(im)) => self.im.update(Some(im), display),
-            None => self.im.update(None, display),
+        let _ = self.try_update(message, display);
+    }
+
+    fn try_update<D: MatrixDisplay>(
+        &mut self,
+        message: Option<Self::Message>,
+        display: &mut D,
+    ) -> Result<(), UpdateError> {
+        let result = match message {
+            Some(MatrixStateMessage::UpdateBrightness(b)) => {
+                self.set_brightness(b);
+                Ok(())
+            }
+            Some(MatrixStateMessage::UpdateImage(im)) => {
+                let result = self.im.try_update(Some(im), display);
+                // a rejected message leaves drawn pixels in place
+                self.paused &= result.is_err();
+                result
+            }
+            Some(MatrixStateMessage::DrawPixels(run)) => {
+                self.paused = true;
+                draw_run(&run, display);
+                Ok(())
+            }
+            None if self.paused => Ok(()),
+            None => self.im.try_update(None, display),
         };
+        display.set_correction(self.correction());
+        result
+    }
+}
+
+/// What a `MatrixState` is showing
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MatrixSnapshot<ImageSnapshot> {
+    pub brightness: f32,
+    /// whether the scene is paused under pixels that were drawn directly
+    pub paused: bool,
+    pub image: ImageSnapshot,
+}
+
+impl<ImageState: Snapshot> Snapshot for MatrixState<ImageState> {
+    type Snapshot = MatrixSnapshot<ImageState::Snapshot>;
+
+    fn snapshot(&self) -> Self::Snapshot {
+        MatrixSnapshot {
+            brightness: self.brightness,
+            paused: self.paused,
+            image: self.im.snapshot(),
+        }
+    }
+}
+
+/// A message from the matrix to its WebSocket clients, postcard encoded like the messages they
+/// send it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ServerMessage<StateSnapshot> {
+    /// A message sent to the matrix couldn't be applied
+    Error(UpdateError),
+    /// What the matrix is showing, sent when a client connects and after every change
+    State(StateSnapshot),
+}
+
+fn draw_run<D: MatrixDisplay>(run: &PixelRun, display: &mut D) {
+    let (_, cols) = display.size();
+    if cols == 0 {
+        return;
+    }
+    for (i, &(r, g, b)) in run.pixels.iter().enumerate() {
+        let index = run.start as usize + i;
+        // pixels past the end of the matrix are left out
+        if let Some(pixel) = display.get_mut(index / cols, index % cols) {
+            *pixel = RGB8 {
+                padding: 0,
+                b,
+                r,
+                g,
+            };
+        }
     }
 }
 
@@ -51,6 +240,9 @@ pub trait MatrixDisplay {
     fn get_mut(&mut self, row: usize, col: usize) -> Option<&mut RGB8>;
     fn get(&self, row: usize, col: usize) -> Option<&RGB8>;
     fn size(&self) -> (usize, usize);
+    /// Set the brightness and gamma correction applied to every pixel when it is output.
+    /// The pixels stored in the display are left uncorrected, so effects can read them back.
+    fn set_correction(&mut self, correction: ColourCorrection);
 
     fn iter_coords_helper(rows: usize, cols: usize) -> impl Iterator<Item = (usize, usize)> {
         (0..rows).flat_map(move |r| (0..cols).map(move |c| (r, c)))
@@ -66,32 +258,96 @@ pub trait MatrixDisplay {
     }
 }
 
+/// A message for a state made by `create_matrix_state!`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum VariantMessage<M> {
+    /// Pass the message on to the active variant, which it has to be for
+    Update(M),
+    /// Switch to the variant the message is for, starting it with the message as its parameters
+    Switch(M),
+}
+
+/// Combine states into an enum `$name` which shows one of them at a time, along with an enum
+/// `$message_type_name` of their messages. Its `Updateable` message is a `VariantMessage`, which
+/// either updates the active variant or switches to another, and a message for a variant that
+/// isn't active is an `UpdateError::WrongVariant`. Every state has to implement `Default`, which
+/// switching starts them from, and `Snapshot` with its message as the snapshot, so the snapshot of
+/// `$name` is the `$message_type_name` that would switch to what it's showing.
 #[macro_export]
 macro_rules! create_matrix_state {
     ($name: ident; $message_type_name: ident; $($i: ident),*) => {
-	use $crate::{FrameTime, Updateable, MatrixDisplay};
+	use $crate::{FrameTime, Updateable, MatrixDisplay, Snapshot, UpdateError, VariantMessage};
 	use serde::{Serialize, Deserialize};
 	pub enum $name {
 	    $($i($i)),*
 	}
 
-	#[derive(Serialize, Deserialize)]
+	#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 	pub enum $message_type_name {
 	    $($i(<$i as Updateable>::Message)),*
 	}
 
+	impl $message_type_name {
+	    /// The name of the variant the message is for
+	    pub fn variant_name(&self) -> &'static str {
+		match self {
+		    $(Self::$i(_) => stringify!($i)),*
+		}
+	    }
+	}
+
+	impl $name {
+	    /// The names of the variants, in the order they were given
+	    pub const VARIANTS: &'static [&'static str] = &[$(stringify!($i)),*];
+
+	    /// The name of the active variant
+	    pub fn variant_name(&self) -> &'static str {
+		match self {
+		    $($name::$i(_) => stringify!($i)),*
+		}
+	    }
+
+	    /// Switch to the variant the message is for, starting it from its default with the
+	    /// message as its parameters. If the variant rejects them, the active one is kept.
+	    pub fn switch<D: MatrixDisplay>(
+		&mut self,
+		message: $message_type_name,
+		display: &mut D,
+	    ) -> Result<(), UpdateError> {
+		*self = match message {
+		    $($message_type_name::$i(message) => {
+			let mut inner = <$i>::default();
+			inner.try_update(Some(message), display)?;
+			$name::$i(inner)
+		    })*
+		};
+		Ok(())
+	    }
+	}
+
 	impl Updateable for $name {
-	    type Message = $message_type_name;
+	    type Message = VariantMessage<$message_type_name>;
 
 	    fn update<D: MatrixDisplay>(&mut self, message: Option<Self::Message>, display: &mut D) {
-		match self {
-		    $($name::$i(inner) => {
-			let message = match message {
-			    Some(Self::Message::$i(inner)) => Some(inner),
-			    _ => None
-			};
-			inner.update(message, display);
+		let _ = self.try_update(message, display);
+	    }
+
+	    fn try_update<D: MatrixDisplay>(
+		&mut self,
+		message: Option<Self::Message>,
+		display: &mut D,
+	    ) -> Result<(), UpdateError> {
+		#[allow(unreachable_patterns)]
+		match (self, message) {
+		    (this, Some(VariantMessage::Switch(message))) => this.switch(message, display),
+		    $(($name::$i(inner), Some(VariantMessage::Update($message_type_name::$i(message)))) => {
+			inner.try_update(Some(message), display)
 		    })*
+		    $(($name::$i(inner), None) => inner.try_update(None, display),)*
+		    (active, Some(VariantMessage::Update(message))) => Err(UpdateError::wrong_variant(
+			active.variant_name(),
+			message.variant_name(),
+		    )),
 		}
 	    }
 	}
@@ -103,12 +359,27 @@ macro_rules! create_matrix_state {
 		}
 	    }
 	}
+
+	impl Snapshot for $name {
+	    type Snapshot = $message_type_name;
+
+	    fn snapshot(&self) -> Self::Snapshot {
+		match self {
+		    $($name::$i(inner) => $message_type_name::$i(inner.snapshot())),*
+		}
+	    }
+	}
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::{FrameTime, Updateable};
+    use crate::{
+        ColourCorrection, FrameBuffer, GammaTable, MatrixDisplay, MatrixState, MatrixStateMessage,
+        Updateable, RGB8,
+    };
+
+    #[allow(clippy::assertions_on_constants)]
     #[test]
     fn is_true() {
         assert!(true)
@@ -116,14 +387,15 @@ mod test {
 
     #[test]
     fn test_create_matrix_state() {
+        #[derive(Default)]
         struct Hi;
         impl Updateable for Hi {
             type Message = u32;
 
             fn update<D: crate::MatrixDisplay>(
                 &mut self,
-                message: Option<Self::Message>,
-                display: &mut D,
+                _message: Option<Self::Message>,
+                _display: &mut D,
             ) {
                 todo!()
             }
@@ -133,14 +405,22 @@ mod test {
                 3
             }
         }
+        impl Snapshot for Hi {
+            type Snapshot = u32;
+
+            fn snapshot(&self) -> u32 {
+                5
+            }
+        }
+        #[derive(Default)]
         struct There;
         impl Updateable for There {
             type Message = f64;
 
             fn update<D: crate::MatrixDisplay>(
                 &mut self,
-                message: Option<Self::Message>,
-                display: &mut D,
+                _message: Option<Self::Message>,
+                _display: &mut D,
             ) {
                 todo!()
             }
@@ -150,7 +430,85 @@ mod test {
                 2
             }
         }
+        impl Snapshot for There {
+            type Snapshot = f64;
+
+            fn snapshot(&self) -> f64 {
+                0.5
+            }
+        }
         create_matrix_state!(Hello; HelloMessage; Hi, There);
         assert_eq!(Hello::Hi(Hi).frame_time(), 3);
+        assert_eq!(Hello::There(There).frame_time(), 2);
+        assert_eq!(Hello::VARIANTS, ["Hi", "There"]);
+        assert_eq!(Hello::There(There).variant_name(), "There");
+        assert_eq!(HelloMessage::Hi(4).variant_name(), "Hi");
+        assert_eq!(Hello::Hi(Hi).snapshot(), HelloMessage::Hi(5));
+    }
+
+    #[test]
+    fn test_gamma_tables() {
+        for i in 0..=255 {
+            assert_eq!(GammaTable::LINEAR.lookup(i), i);
+        }
+        assert_eq!(GammaTable::WS2812B.lookup(0), 0);
+        assert_eq!(GammaTable::WS2812B.lookup(255), 255);
+        assert!(GammaTable::WS2812B.0.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn test_brightness_is_clamped_and_applied() {
+        struct Fill;
+        impl Updateable for Fill {
+            type Message = ();
+
+            fn update<D: MatrixDisplay>(&mut self, _message: Option<()>, display: &mut D) {
+                for (_, pixel) in display.iter_mut() {
+                    *pixel = RGB8 {
+                        padding: 0,
+                        b: 255,
+                        r: 128,
+                        g: 0,
+                    };
+                }
+            }
+        }
+
+        let mut display = FrameBuffer::<2, 2>::new();
+        let mut state = MatrixState::new(Fill).with_gamma(&GammaTable::LINEAR);
+
+        state.update(
+            Some(MatrixStateMessage::UpdateBrightness(2.0)),
+            &mut display,
+        );
+        assert_eq!(state.brightness(), 1.0);
+        state.update(
+            Some(MatrixStateMessage::UpdateBrightness(f32::NAN)),
+            &mut display,
+        );
+        assert_eq!(state.brightness(), 0.0);
+
+        state.update(
+            Some(MatrixStateMessage::UpdateBrightness(0.5)),
+            &mut display,
+        );
+        state.update(None, &mut display);
+        // the stored pixels are untouched, the correction is applied on output
+        let pixel = *display.get(1, 1).unwrap();
+        assert_eq!((pixel.r, pixel.g, pixel.b), (128, 0, 255));
+        let corrected = display.corrected(1, 1).unwrap();
+        assert_eq!((corrected.r, corrected.g, corrected.b), (64, 0, 128));
+
+        // brightness scales the linear value before the gamma lookup
+        let half = ColourCorrection::new(0.5, &GammaTable::WS2812B);
+        let white = half.apply(RGB8::new(255, 255, 255));
+        assert_eq!((white.r, white.g, white.b), (37, 37, 37));
+        assert_eq!(half.apply_channel(0), 0);
+        let full = ColourCorrection::new(1.0, &GammaTable::WS2812B);
+        assert!((0..=255).all(|v| full.apply_channel(v) == GammaTable::WS2812B.lookup(v)));
+        assert_eq!(
+            ColourCorrection::new(0.0, &GammaTable::WS2812B).apply_channel(255),
+            0
+        );
     }
 }
@@ -0,0 +1,77 @@
+/// What to do with the frames that were missed when a frame overruns
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Overrun {
+    /// run the missed updates back to back (up to `max_frames` of them) and only show the last,
+    /// so animations keep their speed
+    CatchUp { max_frames: u32 },
+    /// skip the missed frames, so the animation slows down but no time is spent catching up
+    Drop,
+}
+
+/// Keeps track of when the next frame is due. Times are in milliseconds, the same units as
+/// `FrameTime::frame_time`, and frames stay on the grid set by the first one.
+#[derive(Debug, Clone)]
+pub struct FramePacer {
+    next_frame: u64,
+    overrun: Overrun,
+}
+
+impl FramePacer {
+    /// Create a pacer with the first frame due at `now`
+    pub fn new(now: u64, overrun: Overrun) -> Self {
+        Self {
+            next_frame: now,
+            overrun,
+        }
+    }
+
+    /// The time the next frame is due
+    pub fn next_frame(&self) -> u64 {
+        self.next_frame
+    }
+
+    /// Schedule the next frame and return how many updates should be run before it is shown.
+    /// Returns 0 if the next frame isn't due yet.
+    pub fn tick(&mut self, now: u64, frame_time: u64) -> u32 {
+        if now < self.next_frame {
+            return 0;
+        }
+        let frame_time = frame_time.max(1);
+        let due = (now - self.next_frame) / frame_time + 1;
+        self.next_frame += due * frame_time;
+        match self.overrun {
+            Overrun::CatchUp { max_frames } => due.min(max_frames.max(1) as u64) as u32,
+            Overrun::Drop => 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FramePacer, Overrun};
+
+    #[test]
+    fn test_on_time() {
+        let mut pacer = FramePacer::new(100, Overrun::Drop);
+        assert_eq!(pacer.tick(99, 10), 0);
+        assert_eq!(pacer.tick(100, 10), 1);
+        assert_eq!(pacer.next_frame(), 110);
+        assert_eq!(pacer.tick(105, 10), 0);
+        assert_eq!(pacer.tick(112, 10), 1);
+        // late frames don't push the grid back
+        assert_eq!(pacer.next_frame(), 120);
+    }
+
+    #[test]
+    fn test_overruns() {
+        let mut pacer = FramePacer::new(0, Overrun::CatchUp { max_frames: 3 });
+        assert_eq!(pacer.tick(25, 10), 3);
+        assert_eq!(pacer.next_frame(), 30);
+        assert_eq!(pacer.tick(100, 10), 3);
+        assert_eq!(pacer.next_frame(), 110);
+
+        let mut pacer = FramePacer::new(0, Overrun::Drop);
+        assert_eq!(pacer.tick(25, 10), 1);
+        assert_eq!(pacer.next_frame(), 30);
+    }
+}
@@ -0,0 +1,11 @@
+//! The scenes the matrix can show. The firmware and the simulator both use these types, so
+//! they speak the same protocol.
+
+pub use crate::effects::{Fire, Life, Plasma, Rain, Rainbow, Solid, Twinkle};
+
+// the order is part of the saved boot scene's format, so new scenes go on the end
+crate::create_matrix_state!(Scene; SceneMessage; Solid, Rainbow, Plasma, Fire, Twinkle, Rain, Life);
+
+/// The most bytes a `ServerMessage` about a `Scene` takes postcard encoded, which is an error
+/// with the longest names, as the snapshots are all a few bytes
+pub const MAX_SERVER_MESSAGE_LEN: usize = 64;
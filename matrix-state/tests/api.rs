@@ -0,0 +1,108 @@
+use matrix_state::api::{from_variant, Brightness, Frame, Info};
+use matrix_state::scene::SceneMessage;
+use matrix_state::{Layout, MatrixStateMessage, PixelRun, MAX_RUN_LEN};
+
+type Message = MatrixStateMessage<SceneMessage>;
+
+/// The scene message for `name`, from a JSON body
+fn scene(name: &str, body: &str) -> Result<SceneMessage, serde_json::Error> {
+    let mut body = serde_json::Deserializer::from_str(body);
+    let message = from_variant(name, &mut body)?;
+    body.end()?;
+    Ok(message)
+}
+
+#[test]
+fn builds_variants_from_a_name_and_body() {
+    assert_eq!(
+        scene("Solid", "[255, 0, 64]").unwrap(),
+        SceneMessage::Solid((255, 0, 64))
+    );
+    // unknown scenes, bad parameters and trailing data are all errors
+    assert!(scene("Plasma", "[255, 0, 64]").is_err());
+    assert!(scene("solid", "[255, 0, 64]").is_err());
+    assert!(scene("Solid", "[256, 0, 64]").is_err());
+    assert!(scene("Solid", "[255, 0]").is_err());
+    assert!(scene("Solid", "[255, 0, 64] x").is_err());
+
+    // and from other formats, like the postcard the WebSocket uses
+    let bytes = [1, 2, 3];
+    let mut body = postcard::Deserializer::from_bytes(&bytes);
+    let message: SceneMessage = from_variant("Solid", &mut body).unwrap();
+    assert_eq!(message, SceneMessage::Solid((1, 2, 3)));
+
+    // newtype and struct variants work too
+    let mut body = serde_json::Deserializer::from_str("0.25");
+    let message: Message = from_variant("UpdateBrightness", &mut body).unwrap();
+    assert_eq!(message, MatrixStateMessage::UpdateBrightness(0.25));
+    let mut body = serde_json::Deserializer::from_str(r#"{"start": 3, "pixels": [[1, 2, 3]]}"#);
+    let message: Message = from_variant("DrawPixels", &mut body).unwrap();
+    assert_eq!(
+        message,
+        MatrixStateMessage::DrawPixels(PixelRun {
+            start: 3,
+            pixels: [(1, 2, 3)].into_iter().collect(),
+        })
+    );
+}
+
+#[test]
+fn splits_frames_into_runs() {
+    let pixels = "ff0000".repeat(MAX_RUN_LEN) + "00FF80" + "0a0b0c";
+    let body = format!(r#"{{"pixels": "{pixels}"}}"#);
+    let frame: Frame = serde_json::from_str(&body).unwrap();
+    let runs: Vec<_> = frame.runs().unwrap().collect();
+    assert_eq!(runs.len(), 2);
+    assert_eq!(runs[0].start, 0);
+    assert_eq!(runs[0].pixels.len(), MAX_RUN_LEN);
+    assert!(runs[0].pixels.iter().all(|&p| p == (255, 0, 0)));
+    assert_eq!(runs[1].start, MAX_RUN_LEN as u16);
+    assert_eq!(runs[1].pixels, [(0, 255, 128), (10, 11, 12)]);
+
+    assert_eq!(
+        Frame {
+            pixels: "",
+            start: 0
+        }
+        .runs()
+        .unwrap()
+        .count(),
+        0
+    );
+    for pixels in ["ff00", "ff00001", "gg0000"] {
+        assert!(Frame { pixels, start: 0 }.runs().is_none(), "{pixels}");
+    }
+}
+
+#[test]
+fn frames_can_be_sent_in_parts() {
+    let frame: Frame = serde_json::from_str(r#"{"pixels": "0a0b0c", "start": 300}"#).unwrap();
+    let runs: Vec<_> = frame.runs().unwrap().collect();
+    assert_eq!(runs[0].start, 300);
+    assert_eq!(runs[0].pixels, [(10, 11, 12)]);
+
+    let past_the_end = Frame {
+        pixels: "000000",
+        start: u16::MAX,
+    };
+    assert!(past_the_end.runs().is_none());
+}
+
+#[test]
+fn bodies_are_plain_json() {
+    let brightness: Brightness = serde_json::from_str(r#"{"brightness": 0.5}"#).unwrap();
+    assert_eq!(brightness.brightness, 0.5);
+
+    let info = Info {
+        rows: 8,
+        cols: 32,
+        layout: Layout::new(8, 32),
+        version: "0.1.0",
+        uptime: 12,
+        free_memory: None,
+    };
+    let json = serde_json::to_value(info).unwrap();
+    assert_eq!(json["cols"], 32);
+    assert_eq!(json["version"], "0.1.0");
+    assert_eq!(json["free_memory"], serde_json::Value::Null);
+}
@@ -0,0 +1,144 @@
+//! A harness for golden-frame tests. A state is driven through a script of messages and ticks
+//! against an in-memory display, and the frames after each step are compared with the ones
+//! checked in under `tests/golden`. Run with `UPDATE_GOLDEN=1` to write new golden files.
+
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use matrix_state::{FrameBuffer, MatrixDisplay, Updateable};
+
+#[allow(dead_code)]
+pub enum Step<M> {
+    /// send a message to the state
+    Message(M),
+    /// tick the state this many times
+    Tick(u32),
+}
+
+/// Run the script, returning the frame shown after each step
+#[allow(dead_code)]
+pub fn run_script<S: Updateable, const ROWS: usize, const COLS: usize>(
+    state: &mut S,
+    script: impl IntoIterator<Item = Step<S::Message>>,
+) -> Vec<FrameBuffer<ROWS, COLS>> {
+    let mut display = FrameBuffer::new();
+    script
+        .into_iter()
+        .map(|step| {
+            match step {
+                Step::Message(message) => state.update(Some(message), &mut display),
+                Step::Tick(n) => {
+                    for _ in 0..n {
+                        state.update(None, &mut display);
+                    }
+                }
+            }
+            display.clone()
+        })
+        .collect()
+}
+
+/// Each frame is a header giving the brightness, followed by a row of hex colours per line
+fn encode<const ROWS: usize, const COLS: usize>(frames: &[FrameBuffer<ROWS, COLS>]) -> String {
+    let mut out = String::new();
+    for (i, frame) in frames.iter().enumerate() {
+        writeln!(
+            out,
+            "# frame {i} brightness {}",
+            frame.correction().brightness()
+        )
+        .unwrap();
+        for row in frame.pixels() {
+            let row: Vec<_> = row
+                .iter()
+                .map(|p| format!("{:02x}{:02x}{:02x}", p.r, p.g, p.b))
+                .collect();
+            writeln!(out, "{}", row.join(" ")).unwrap();
+        }
+    }
+    out
+}
+
+/// Describe how two encoded frame sets differ, marking changed pixels with `X`
+fn diff(expected: &str, actual: &str) -> String {
+    let mut out = String::new();
+    let (mut expected, mut actual) = (expected.lines(), actual.lines());
+    let mut header = String::new();
+    let mut row = 0;
+    loop {
+        match (expected.next(), actual.next()) {
+            (None, None) => break,
+            (Some(e), Some(a)) if e.starts_with('#') || a.starts_with('#') => {
+                if e != a {
+                    writeln!(out, "expected `{e}`, got `{a}`").unwrap();
+                }
+                header = a.to_string();
+                row = 0;
+            }
+            (Some(e), Some(a)) => {
+                if e != a {
+                    let marks: String = e
+                        .split(' ')
+                        .zip(a.split(' '))
+                        .map(|(e, a)| if e == a { '.' } else { 'X' })
+                        .collect();
+                    writeln!(out, "{header}, row {row}: {marks}").unwrap();
+                    writeln!(out, "    expected: {e}").unwrap();
+                    writeln!(out, "    actual:   {a}").unwrap();
+                }
+                row += 1;
+            }
+            (e, a) => {
+                writeln!(
+                    out,
+                    "different number of lines: expected `{e:?}`, got `{a:?}`"
+                )
+                .unwrap();
+                break;
+            }
+        }
+    }
+    out
+}
+
+/// Compare the frames with the golden file `tests/golden/<name>.txt`
+pub fn assert_golden<const ROWS: usize, const COLS: usize>(
+    name: &str,
+    frames: &[FrameBuffer<ROWS, COLS>],
+) {
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "golden", name]
+        .iter()
+        .collect::<PathBuf>()
+        .with_extension("txt");
+    let actual = encode(frames);
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!(
+            "missing golden file {}, run with UPDATE_GOLDEN=1 to create it",
+            path.display()
+        )
+    });
+    if expected != actual {
+        panic!(
+            "frames don't match {} (run with UPDATE_GOLDEN=1 to accept them):\n{}",
+            path.display(),
+            diff(&expected, &actual)
+        );
+    }
+}
+
+/// Check that every pixel of a frame is the same
+#[allow(dead_code)]
+pub fn is_uniform<const ROWS: usize, const COLS: usize>(frame: &FrameBuffer<ROWS, COLS>) -> bool {
+    let first = *frame.get(0, 0).unwrap();
+    frame
+        .pixels()
+        .iter()
+        .flatten()
+        .all(|p| (p.r, p.g, p.b) == (first.r, first.g, first.b))
+}
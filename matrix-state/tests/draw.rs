@@ -0,0 +1,226 @@
+mod common;
+
+use std::collections::VecDeque;
+
+use common::assert_golden;
+use matrix_state::{Draw, FrameBuffer, MatrixDisplay, UpdateError, RGB8};
+
+const RED: RGB8 = RGB8::new(255, 0, 0);
+const GREEN: RGB8 = RGB8::new(0, 255, 0);
+const BLUE: RGB8 = RGB8::new(0, 0, 255);
+
+/// A frame where each pixel's red is its row and its green is its column
+fn numbered<const ROWS: usize, const COLS: usize>() -> FrameBuffer<ROWS, COLS> {
+    let mut frame = FrameBuffer::new();
+    for row in 0..ROWS {
+        for col in 0..COLS {
+            *frame.get_mut(row, col).unwrap() = RGB8::new(row as u8, col as u8, 0);
+        }
+    }
+    frame
+}
+
+#[test]
+fn shapes() {
+    let mut frame = FrameBuffer::<8, 12>::new();
+    let mut frames = vec![];
+    let mut draw = |draw: &dyn Fn(&mut FrameBuffer<8, 12>)| {
+        frame.clear();
+        draw(&mut frame);
+        frames.push(frame.clone());
+    };
+    draw(&|f| {
+        f.line((0, 0), (7, 11), RED);
+        // steep, backwards, and running off the top
+        f.line((7, 3), (-2, 1), GREEN);
+        f.line((4, 11), (4, 6), BLUE);
+    });
+    draw(&|f| {
+        f.fill_rect((1, 1), (3, 4), RED);
+        // corners either way round, and hanging off the edges
+        f.rect((9, 13), (5, 7), GREEN);
+        f.fill_rect((-3, 10), (1, 20), BLUE);
+    });
+    draw(&|f| {
+        f.circle((3, 3), 3, RED);
+        f.fill_circle((4, 9), 2, GREEN);
+        f.circle((8, 12), 2, BLUE);
+    });
+    draw(&|f| {
+        f.fill(BLUE);
+        let sprite = [RED, GREEN, GREEN, RED, RED, GREEN];
+        f.blit(&sprite, 3, (1, 1));
+        f.blit(&sprite, 3, (-1, 10));
+    });
+    assert_golden("shapes", &frames);
+}
+
+#[test]
+fn shapes_far_off_the_display() {
+    // the ends are as far apart as points can be, and only the part on the display is drawn
+    let mut frame = FrameBuffer::<4, 4>::new();
+    frame.line((i32::MIN, i32::MIN), (i32::MAX, i32::MAX), RED);
+    frame.line((2, i32::MAX), (2, i32::MIN), GREEN);
+    for row in 0..4 {
+        for col in 0..4 {
+            let expected = match (row, col) {
+                (2, _) => GREEN,
+                _ if row == col => RED,
+                _ => RGB8::default(),
+            };
+            assert_eq!(frame.get(row, col), Some(&expected), "({row}, {col})");
+        }
+    }
+
+    // and circles around centres at the edges of the range miss it
+    let drawn = frame.clone();
+    frame.circle((i32::MAX, i32::MIN), u16::MAX, BLUE);
+    frame.fill_circle((i32::MIN, i32::MAX), u16::MAX, BLUE);
+    assert_eq!(frame.pixels(), drawn.pixels());
+}
+
+#[test]
+fn lines_starting_off_the_display_match_whole_lines() {
+    // each line drawn on a small display, and on a big one with the small one in its middle
+    let ends = [
+        (-15, -9),
+        (-9, 20),
+        (2, -14),
+        (20, 3),
+        (19, 21),
+        (-13, 18),
+        (1, 2),
+    ];
+    for from in ends {
+        for to in ends {
+            let mut small = FrameBuffer::<5, 6>::new();
+            let mut big = FrameBuffer::<40, 40>::new();
+            small.line(from, to, RED);
+            big.line((from.0 + 16, from.1 + 16), (to.0 + 16, to.1 + 16), RED);
+            for row in 0..5 {
+                for col in 0..6 {
+                    assert_eq!(
+                        small.get(row, col),
+                        big.get(row + 16, col + 16),
+                        "{from:?} to {to:?} at ({row}, {col})"
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn flood_fill_stays_inside_the_outline() {
+    let mut frame = FrameBuffer::<6, 6>::new();
+    frame.rect((1, 1), (4, 4), RED);
+    assert_eq!(frame.flood_fill((2, 2), GREEN), Ok(()));
+    assert_eq!(frame.get(0, 0), Some(&RGB8::default()));
+    assert_eq!(frame.get(1, 1), Some(&RED));
+    assert!((2..4).all(|row| (2..4).all(|col| frame.get(row, col) == Some(&GREEN))));
+
+    // outside, around the outline
+    assert_eq!(frame.flood_fill((5, 5), BLUE), Ok(()));
+    assert!((0..6).all(|i| frame.get(0, i) == Some(&BLUE) && frame.get(i, 0) == Some(&BLUE)));
+    assert_eq!(frame.get(2, 2), Some(&GREEN));
+
+    // starting off the display does nothing
+    assert_eq!(frame.flood_fill((6, 0), RED), Ok(()));
+    assert_eq!(
+        FrameBuffer::<40, 40>::new().flood_fill((0, 0), RED),
+        Err(UpdateError::UnsupportedSize { rows: 40, cols: 40 })
+    );
+}
+
+#[test]
+fn flood_fill_finds_every_branch() {
+    // open rows with a comb of single pixel gaps between them, which has more branches than
+    // the fill keeps track of at once, and a wall that shuts off the bottom right
+    let mut frame = FrameBuffer::<32, 32>::new();
+    for row in (1..32).step_by(2) {
+        for col in (1..32).step_by(2) {
+            *frame.get_mut(row, col).unwrap() = RED;
+        }
+    }
+    frame.line((20, 31), (31, 20), RED);
+    frame.line((21, 31), (31, 21), RED);
+
+    // what a simple breadth first fill makes of it
+    let mut expected = frame.clone();
+    let mut queue = VecDeque::from([(0, 0)]);
+    while let Some((row, col)) = queue.pop_front() {
+        match expected.get_mut(row, col) {
+            Some(pixel) if *pixel == RGB8::default() => *pixel = GREEN,
+            _ => continue,
+        }
+        queue.extend([
+            (row.wrapping_sub(1), col),
+            (row + 1, col),
+            (row, col.wrapping_sub(1)),
+            (row, col + 1),
+        ]);
+    }
+
+    assert_eq!(frame.flood_fill((0, 0), GREEN), Ok(()));
+    assert_eq!(frame.pixels(), expected.pixels());
+    assert_eq!(frame.get(30, 30), Some(&RGB8::default()));
+}
+
+#[test]
+fn shifting_and_scrolling() {
+    let mut frame = numbered::<3, 4>();
+    frame.shift(1, -2, BLUE);
+    assert_eq!(frame.get(0, 0), Some(&BLUE));
+    assert_eq!(frame.get(1, 0), Some(&RGB8::new(0, 2, 0)));
+    assert_eq!(frame.get(2, 1), Some(&RGB8::new(1, 3, 0)));
+    assert_eq!(frame.get(2, 2), Some(&BLUE));
+
+    let mut frame = numbered::<3, 4>();
+    frame.shift(-1, 1, BLUE);
+    assert_eq!(frame.get(0, 1), Some(&RGB8::new(1, 0, 0)));
+    assert_eq!(frame.get(1, 3), Some(&RGB8::new(2, 2, 0)));
+    assert_eq!(frame.get(2, 1), Some(&BLUE));
+    assert_eq!(frame.get(0, 0), Some(&BLUE));
+
+    // scrolling wraps round, however far it goes
+    let mut frame = numbered::<3, 4>();
+    frame.scroll(1, -1);
+    assert_eq!(frame.get(0, 0), Some(&RGB8::new(2, 1, 0)));
+    assert_eq!(frame.get(0, 3), Some(&RGB8::new(2, 0, 0)));
+    assert_eq!(frame.get(1, 0), Some(&RGB8::new(0, 1, 0)));
+    frame.scroll(-7, 9);
+    assert_eq!(frame.pixels(), numbered::<3, 4>().pixels());
+}
+
+#[cfg(feature = "embedded-graphics")]
+#[test]
+fn canvas() {
+    use embedded_graphics::mono_font::{ascii::FONT_4X6, MonoTextStyle};
+    use embedded_graphics::pixelcolor::Rgb888;
+    use embedded_graphics::prelude::*;
+    use embedded_graphics::primitives::{Circle, PrimitiveStyle, Rectangle};
+    use embedded_graphics::text::{Baseline, Text};
+    use matrix_state::Canvas;
+
+    let mut frame = FrameBuffer::<8, 12>::new();
+    let mut canvas = Canvas::new(&mut frame);
+    assert_eq!(canvas.size(), Size::new(12, 8));
+    canvas.clear(Rgb888::new(0, 0, 64)).unwrap();
+    Rectangle::new(Point::new(9, 5), Size::new(5, 5))
+        .into_styled(PrimitiveStyle::with_fill(Rgb888::YELLOW))
+        .draw(&mut canvas)
+        .unwrap();
+    Circle::new(Point::new(6, -2), 5)
+        .into_styled(PrimitiveStyle::with_stroke(Rgb888::GREEN, 1))
+        .draw(&mut canvas)
+        .unwrap();
+    Text::with_baseline(
+        "Hi",
+        Point::new(1, 1),
+        MonoTextStyle::new(&FONT_4X6, Rgb888::RED),
+        Baseline::Top,
+    )
+    .draw(&mut canvas)
+    .unwrap();
+    assert_golden("canvas", &[frame]);
+}
@@ -0,0 +1,197 @@
+mod common;
+
+use common::{assert_golden, run_script, Step};
+use matrix_state::effects::{
+    Fire, FireMessage, Life, LifeMessage, Palette, Plasma, PlasmaMessage, Rain, RainMessage,
+    Rainbow, RainbowMessage, Twinkle, TwinkleMessage,
+};
+use matrix_state::scene::Scene;
+use matrix_state::{FrameBuffer, FrameTime, MatrixDisplay, UpdateError, Updateable};
+
+#[test]
+fn rainbow() {
+    let frames = run_script::<_, 4, 6>(
+        &mut Rainbow::default(),
+        [
+            Step::Tick(1),
+            Step::Tick(10),
+            Step::Message(RainbowMessage {
+                speed: 20,
+                spread: 40,
+                palette: Palette::Ocean,
+            }),
+            Step::Tick(1),
+        ],
+    );
+    assert_golden("rainbow", &frames);
+}
+
+#[test]
+fn plasma() {
+    let frames = run_script::<_, 6, 6>(
+        &mut Plasma::default(),
+        [
+            Step::Tick(1),
+            Step::Tick(20),
+            Step::Message(PlasmaMessage {
+                speed: 9,
+                scale: 40,
+                palette: Palette::Forest,
+            }),
+            Step::Tick(5),
+        ],
+    );
+    assert_golden("plasma", &frames);
+}
+
+#[test]
+fn fire() {
+    let frames = run_script::<_, 8, 5>(
+        &mut Fire::default(),
+        [
+            Step::Tick(1),
+            Step::Tick(20),
+            Step::Message(FireMessage {
+                cooling: 120,
+                sparking: 200,
+                palette: Palette::Ocean,
+            }),
+            Step::Tick(20),
+        ],
+    );
+    // the flames come up from the bottom
+    let heat = |frame: &FrameBuffer<8, 5>, row: usize| {
+        frame.pixels()[row]
+            .iter()
+            .map(|p| p.r as u32 + p.g as u32 + p.b as u32)
+            .sum::<u32>()
+    };
+    assert!(heat(&frames[1], 7) > heat(&frames[1], 0));
+    assert_golden("fire", &frames);
+}
+
+#[test]
+fn twinkle() {
+    let frames = run_script::<_, 6, 6>(
+        &mut Twinkle::default(),
+        [
+            Step::Message(TwinkleMessage {
+                density: 200,
+                fade: 64,
+                palette: Palette::Rainbow,
+            }),
+            Step::Tick(1),
+            Step::Tick(1),
+            Step::Message(TwinkleMessage {
+                density: 0,
+                fade: 128,
+                palette: Palette::Rainbow,
+            }),
+            // with no new sparkles, everything fades out
+            Step::Tick(10),
+        ],
+    );
+    assert!(frames[4]
+        .pixels()
+        .iter()
+        .flatten()
+        .all(|p| (p.r, p.g, p.b) == (0, 0, 0)));
+    assert_golden("twinkle", &frames);
+}
+
+#[test]
+fn rain() {
+    let frames = run_script::<_, 8, 6>(
+        &mut Rain::default(),
+        [
+            Step::Tick(5),
+            Step::Tick(5),
+            Step::Message(RainMessage {
+                speed: 40,
+                density: 255,
+                colour: (255, 0, 0),
+            }),
+            Step::Tick(3),
+            Step::Tick(3),
+        ],
+    );
+    assert_golden("rain", &frames);
+}
+
+#[test]
+fn life() {
+    let mut life = Life::default();
+    let frames = run_script::<_, 6, 6>(
+        &mut life,
+        [
+            Step::Tick(1),
+            Step::Tick(1),
+            Step::Tick(1),
+            Step::Message(LifeMessage {
+                speed: 2,
+                density: 128,
+                colour: (0, 0, 255),
+            }),
+            Step::Tick(1),
+        ],
+    );
+    assert_eq!(life.frame_time(), 500);
+    assert_golden("life", &frames);
+}
+
+#[test]
+fn seeds_repeat_and_differ() {
+    let run = |seed| {
+        let mut display = FrameBuffer::<8, 8>::new();
+        let mut fire = Fire::with_seed(seed);
+        for _ in 0..10 {
+            fire.update(None, &mut display);
+        }
+        display
+    };
+    let pixels =
+        |display: FrameBuffer<8, 8>| display.pixels().map(|row| row.map(|p| (p.r, p.g, p.b)));
+    assert_eq!(pixels(run(1)), pixels(run(1)));
+    assert_ne!(pixels(run(1)), pixels(run(2)));
+}
+
+#[test]
+fn effects_fit_any_size() {
+    fn exercise<D: MatrixDisplay>(display: &mut D) {
+        for mut scene in [
+            Scene::Rainbow(Rainbow::default()),
+            Scene::Plasma(Plasma::default()),
+            Scene::Fire(Fire::default()),
+            Scene::Twinkle(Twinkle::default()),
+            Scene::Rain(Rain::default()),
+            Scene::Life(Life::default()),
+        ] {
+            for _ in 0..5 {
+                scene.update(None, display);
+            }
+            assert!(scene.frame_time() > 0);
+        }
+    }
+    // including ones too big for the effects that keep something for each pixel
+    exercise(&mut FrameBuffer::<0, 0>::new());
+    exercise(&mut FrameBuffer::<1, 1>::new());
+    exercise(&mut FrameBuffer::<1, 40>::new());
+    exercise(&mut FrameBuffer::<40, 1>::new());
+    exercise(&mut FrameBuffer::<40, 40>::new());
+}
+
+#[test]
+fn displays_too_big_are_reported() {
+    let mut display = FrameBuffer::<40, 40>::new();
+    let too_big = Err(UpdateError::UnsupportedSize { rows: 40, cols: 40 });
+    let mut fire = Fire::default();
+    for _ in 0..5 {
+        assert_eq!(fire.try_update(None, &mut display), too_big);
+    }
+    // the part that fits is still drawn, with the flames at the bottom
+    assert!(display.pixels()[39].iter().any(|p| p.r > 0));
+    assert_eq!(Life::default().try_update(None, &mut display), too_big);
+    // the effects that don't keep anything for each pixel fit any size
+    assert_eq!(Twinkle::default().try_update(None, &mut display), Ok(()));
+    assert_eq!(Rain::default().try_update(None, &mut display), Ok(()));
+}
@@ -0,0 +1,275 @@
+mod common;
+
+use common::{assert_golden, is_uniform, run_script, Step};
+use matrix_state::effects::{
+    FireMessage, LifeMessage, Palette, PlasmaMessage, RainMessage, Rainbow, RainbowMessage,
+    TwinkleMessage,
+};
+use matrix_state::scene::{Scene, SceneMessage, Solid, MAX_SERVER_MESSAGE_LEN};
+use matrix_state::{
+    FrameBuffer, MatrixSnapshot, MatrixState, MatrixStateMessage, PixelRun, ServerMessage,
+    Snapshot, UpdateError, Updateable, VariantMessage,
+};
+
+#[test]
+fn solid_scene() {
+    let mut state = MatrixState::new(Scene::Solid(Solid::default()));
+    let frames = run_script::<_, 4, 4>(
+        &mut state,
+        [
+            Step::Tick(1),
+            Step::Message(MatrixStateMessage::UpdateImage(VariantMessage::Update(
+                SceneMessage::Solid((255, 128, 0)),
+            ))),
+            Step::Tick(3),
+            Step::Message(MatrixStateMessage::UpdateBrightness(0.5)),
+            Step::Message(MatrixStateMessage::UpdateImage(VariantMessage::Update(
+                SceneMessage::Solid((0, 0, 64)),
+            ))),
+        ],
+    );
+    assert!(frames.iter().all(is_uniform));
+    assert_golden("solid_scene", &frames);
+}
+
+#[test]
+fn drawn_pixels_pause_the_scene() {
+    let mut state = MatrixState::new(Scene::Solid(Solid::default()));
+    let run = |start, pixels: &[(u8, u8, u8)]| {
+        MatrixStateMessage::DrawPixels(PixelRun {
+            start,
+            pixels: pixels.iter().copied().collect(),
+        })
+    };
+    let frames = run_script::<_, 3, 3>(
+        &mut state,
+        [
+            Step::Message(MatrixStateMessage::UpdateImage(VariantMessage::Update(
+                SceneMessage::Solid((0, 0, 255)),
+            ))),
+            // the second run carries on along the next row, and runs off the end
+            Step::Message(run(1, &[(255, 0, 0), (0, 255, 0)])),
+            Step::Message(run(
+                5,
+                &[
+                    (255, 255, 255),
+                    (1, 2, 3),
+                    (4, 5, 6),
+                    (7, 8, 9),
+                    (10, 11, 12),
+                ],
+            )),
+            Step::Tick(3),
+            Step::Message(MatrixStateMessage::UpdateBrightness(0.5)),
+            Step::Tick(1),
+            // until the scene's next message
+            Step::Message(MatrixStateMessage::UpdateImage(VariantMessage::Update(
+                SceneMessage::Solid((0, 64, 0)),
+            ))),
+        ],
+    );
+    assert_golden("drawn_pixels", &frames);
+}
+
+#[test]
+fn switching_scenes() {
+    let mut state = MatrixState::new(Scene::Solid(Solid::default()));
+    let update = |message| MatrixStateMessage::UpdateImage(VariantMessage::Update(message));
+    let switch = |message| MatrixStateMessage::UpdateImage(VariantMessage::Switch(message));
+    let frames = run_script::<_, 3, 4>(
+        &mut state,
+        [
+            Step::Message(update(SceneMessage::Solid((255, 0, 0)))),
+            // messages for a scene that isn't showing are left out
+            Step::Message(update(SceneMessage::Rainbow(RainbowMessage::default()))),
+            Step::Message(switch(SceneMessage::Rainbow(RainbowMessage {
+                speed: 16,
+                ..RainbowMessage::default()
+            }))),
+            Step::Tick(2),
+            Step::Message(update(SceneMessage::Rainbow(RainbowMessage {
+                speed: 0,
+                spread: 64,
+                ..RainbowMessage::default()
+            }))),
+            Step::Tick(2),
+            Step::Message(switch(SceneMessage::Solid((0, 0, 255)))),
+        ],
+    );
+    assert!(is_uniform(&frames[1]) && is_uniform(&frames[6]));
+    assert_golden("switching_scenes", &frames);
+}
+
+#[test]
+fn rejected_messages_are_errors() {
+    let mut display = FrameBuffer::<2, 2>::new();
+    let mut scene = Scene::Rainbow(Rainbow::default());
+    assert_eq!(scene.variant_name(), "Rainbow");
+    let wrong_variant = UpdateError::wrong_variant("Rainbow", "Solid");
+    assert_eq!(
+        scene.try_update(
+            Some(VariantMessage::Update(SceneMessage::Solid((1, 2, 3)))),
+            &mut display
+        ),
+        Err(wrong_variant.clone())
+    );
+    assert_eq!(
+        scene.try_update(
+            Some(VariantMessage::Update(SceneMessage::Rainbow(
+                RainbowMessage::default()
+            ))),
+            &mut display
+        ),
+        Ok(())
+    );
+
+    // a scene that rejects its parameters isn't switched to
+    let stopped = LifeMessage {
+        speed: 0,
+        ..LifeMessage::default()
+    };
+    assert_eq!(
+        scene.switch(SceneMessage::Life(stopped), &mut display),
+        Err(UpdateError::out_of_range("speed", 1, 255))
+    );
+    assert_eq!(scene.variant_name(), "Rainbow");
+    assert_eq!(
+        scene.switch(SceneMessage::Solid((1, 2, 3)), &mut display),
+        Ok(())
+    );
+    assert_eq!(scene.variant_name(), "Solid");
+    assert_eq!(scene.try_update(None, &mut display), Ok(()));
+    assert_eq!(
+        Scene::VARIANTS,
+        ["Solid", "Rainbow", "Plasma", "Fire", "Twinkle", "Rain", "Life"]
+    );
+
+    // the state passes them on, so they can be sent back to whoever sent the message
+    let mut state = MatrixState::new(Scene::Rainbow(Rainbow::default()));
+    assert_eq!(
+        state.try_update(
+            Some(MatrixStateMessage::UpdateImage(VariantMessage::Update(
+                SceneMessage::Solid((1, 2, 3))
+            ))),
+            &mut display
+        ),
+        Err(wrong_variant.clone())
+    );
+    let error = ServerMessage::<MatrixSnapshot<SceneMessage>>::Error(wrong_variant);
+    let mut buffer = [0; 32];
+    let encoded = postcard::to_slice(&error, &mut buffer).unwrap();
+    assert_eq!(encoded, b"\x00\x01\x07Rainbow\x05Solid");
+    // which the clients can decode
+    let decoded: ServerMessage<MatrixSnapshot<SceneMessage>> =
+        postcard::from_bytes(encoded).unwrap();
+    assert_eq!(decoded, error);
+}
+
+#[test]
+fn snapshots_describe_the_scene() {
+    let mut display = FrameBuffer::<4, 4>::new();
+    let mut state = MatrixState::new(Scene::Solid(Solid::default()));
+    let fire = FireMessage {
+        cooling: 80,
+        sparking: 40,
+        palette: Palette::Single((0, 0, 255)),
+    };
+    for message in [
+        MatrixStateMessage::UpdateBrightness(0.25),
+        MatrixStateMessage::UpdateImage(VariantMessage::Switch(SceneMessage::Fire(fire.clone()))),
+    ] {
+        state.update(Some(message), &mut display);
+    }
+    state.update(None, &mut display);
+    let snapshot = state.snapshot();
+    assert_eq!(
+        snapshot,
+        MatrixSnapshot {
+            brightness: 0.25,
+            paused: false,
+            image: SceneMessage::Fire(fire),
+        }
+    );
+
+    // the scene's snapshot is the message that switches to it
+    let mut copy = Scene::Solid(Solid::default());
+    copy.switch(snapshot.image.clone(), &mut display).unwrap();
+    assert_eq!(copy.snapshot(), snapshot.image);
+
+    state.update(
+        Some(MatrixStateMessage::DrawPixels(PixelRun {
+            start: 0,
+            pixels: [(1, 2, 3)].into_iter().collect(),
+        })),
+        &mut display,
+    );
+    assert!(state.snapshot().paused);
+
+    // and they're sent to clients as postcard
+    let message = ServerMessage::State(state.snapshot());
+    let mut buffer = [0; 32];
+    let encoded = postcard::to_slice(&message, &mut buffer).unwrap();
+    let mut expected = vec![1];
+    expected.extend(0.25f32.to_le_bytes());
+    // paused, then the fire variant, its cooling and sparking, and the single colour palette
+    expected.extend([1, 3, 80, 40, 4, 0, 0, 255]);
+    assert_eq!(encoded, expected);
+    // and decoded back into the snapshot on their side
+    let decoded: ServerMessage<MatrixSnapshot<SceneMessage>> =
+        postcard::from_bytes(encoded).unwrap();
+    assert_eq!(decoded, message);
+}
+
+#[test]
+fn server_messages_fit_the_buffer() {
+    let colour = (255, 255, 255);
+    let palette = Palette::Single(colour);
+    let scenes = [
+        SceneMessage::Solid(colour),
+        SceneMessage::Rainbow(RainbowMessage {
+            palette,
+            ..RainbowMessage::default()
+        }),
+        SceneMessage::Plasma(PlasmaMessage {
+            palette,
+            ..PlasmaMessage::default()
+        }),
+        SceneMessage::Fire(FireMessage {
+            palette,
+            ..FireMessage::default()
+        }),
+        SceneMessage::Twinkle(TwinkleMessage {
+            palette,
+            ..TwinkleMessage::default()
+        }),
+        SceneMessage::Rain(RainMessage::default()),
+        SceneMessage::Life(LifeMessage::default()),
+    ];
+    // names longer than an error keeps are cut short
+    let long_name = "x".repeat(100);
+    let errors = [
+        UpdateError::out_of_range(&long_name, u32::MAX, u32::MAX),
+        UpdateError::wrong_variant(&long_name, &long_name),
+        UpdateError::UnsupportedSize {
+            rows: usize::MAX,
+            cols: usize::MAX,
+        },
+    ];
+    let messages = scenes
+        .into_iter()
+        .map(|image| {
+            ServerMessage::State(MatrixSnapshot {
+                brightness: 1.0,
+                paused: true,
+                image,
+            })
+        })
+        .chain(errors.into_iter().map(ServerMessage::Error));
+    let mut buffer = [0; MAX_SERVER_MESSAGE_LEN];
+    for message in messages {
+        assert!(
+            postcard::to_slice(&message, &mut buffer).is_ok(),
+            "{message:?}"
+        );
+    }
+}
@@ -1,4 +1,8 @@
-use std::{path::Path, process::Command};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+};
 
 fn main() {
     let current_dir = std::env::current_dir().expect("Couldn't get current directory");
@@ -44,19 +48,19 @@ fn main() {
 
     let dest_path = route_dir.join("dist");
 
-    let js_file = dest_path.join("frontend.js");
-    let wasm_file = dest_path.join("frontend_bg.wasm");
-    let html_file = dest_path.join("index.html");
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").expect("OUT_DIR to be set"));
 
-    for file in &[&js_file, &wasm_file, &html_file] {
-        let file = std::fs::metadata(file).expect("file to exist");
-        assert!(file.is_file());
+    for (name, env_name) in [
+        ("frontend.js", "FRONTEND_JS"),
+        ("frontend_bg.wasm", "FRONTEND_WASM"),
+        ("index.html", "FRONTEND_HTML"),
+    ] {
+        let file = dest_path.join(name);
+        println!("cargo:rerun-if-changed={}", file.display());
+        let contents = std::fs::read(&file).expect("file to exist");
+        compress_asset(&out_dir, name, env_name, &contents);
     }
 
-    println!("cargo:rustc-env=FRONTEND_JS={}", js_file.display());
-    println!("cargo:rustc-env=FRONTEND_WASM={}", wasm_file.display());
-    println!("cargo:rustc-env=FRONTEND_HTML={}", html_file.display());
-
     // Pass some extra options to rustc, some of which get passed on to the linker.
     // // * linker argument --nmagic turns off page alignment of sections (which saves
     //   flash space)
@@ -72,3 +76,33 @@ fn main() {
     println!("cargo:rustc-link-arg=-Tlink-rp.x");
     println!("cargo:rustc-link-arg=-Tdefmt.x");
 }
+
+/// Write gzip and brotli compressed copies of an asset to `out_dir`, and pass their paths and
+/// their ETags on to the crate in `<env_name>_GZ`, `<env_name>_BR`, `<env_name>_GZ_ETAG` and
+/// `<env_name>_BR_ETAG`. The ETags are a hash of the uncompressed contents, with the encoding
+/// added so the two bodies are told apart.
+fn compress_asset(out_dir: &Path, name: &str, env_name: &str, contents: &[u8]) {
+    let gzip_file = out_dir.join(format!("{name}.gz"));
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder.write_all(contents).expect("to gzip the asset");
+    std::fs::write(&gzip_file, encoder.finish().expect("to gzip the asset"))
+        .expect("to write the gzipped asset");
+
+    let brotli_file = out_dir.join(format!("{name}.br"));
+    let mut brotli = Vec::new();
+    {
+        let mut encoder = brotli::CompressorWriter::new(&mut brotli, 4096, 11, 22);
+        encoder.write_all(contents).expect("to brotli compress the asset");
+    }
+    std::fs::write(&brotli_file, brotli).expect("to write the brotli compressed asset");
+
+    // FNV-1a, which is plenty to tell builds apart
+    let hash = contents.iter().fold(0xcbf29ce484222325u64, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    });
+
+    println!("cargo:rustc-env={env_name}_GZ={}", gzip_file.display());
+    println!("cargo:rustc-env={env_name}_BR={}", brotli_file.display());
+    println!("cargo:rustc-env={env_name}_GZ_ETAG=\"{hash:016x}-gzip\"");
+    println!("cargo:rustc-env={env_name}_BR_ETAG=\"{hash:016x}-br\"");
+}
@@ -0,0 +1,170 @@
+//! The frontend, stored gzip and brotli compressed by `build.rs` to save flash.
+//! Every browser that can run the frontend accepts gzip, so no uncompressed copy is kept, and
+//! gzip is sent to clients that don't say which encodings they accept.
+
+use picoserve::{
+    io::{Read, Write},
+    request::Request,
+    response::{status, Connection, Content, IntoResponse, ResponseWriter},
+    routing::RequestHandler,
+    ResponseSent,
+};
+
+#[derive(Clone, Copy)]
+pub struct Asset {
+    content_type: &'static str,
+    gzip: &'static [u8],
+    brotli: &'static [u8],
+    gzip_etag: &'static str,
+    brotli_etag: &'static str,
+}
+
+pub const INDEX_HTML: Asset = Asset {
+    content_type: "text/html; charset=utf-8",
+    gzip: include_bytes!(env!("FRONTEND_HTML_GZ")),
+    brotli: include_bytes!(env!("FRONTEND_HTML_BR")),
+    gzip_etag: env!("FRONTEND_HTML_GZ_ETAG"),
+    brotli_etag: env!("FRONTEND_HTML_BR_ETAG"),
+};
+
+pub const FRONTEND_JS: Asset = Asset {
+    content_type: "application/javascript; charset=utf-8",
+    gzip: include_bytes!(env!("FRONTEND_JS_GZ")),
+    brotli: include_bytes!(env!("FRONTEND_JS_BR")),
+    gzip_etag: env!("FRONTEND_JS_GZ_ETAG"),
+    brotli_etag: env!("FRONTEND_JS_BR_ETAG"),
+};
+
+pub const FRONTEND_WASM: Asset = Asset {
+    content_type: "application/wasm",
+    gzip: include_bytes!(env!("FRONTEND_WASM_GZ")),
+    brotli: include_bytes!(env!("FRONTEND_WASM_BR")),
+    gzip_etag: env!("FRONTEND_WASM_GZ_ETAG"),
+    brotli_etag: env!("FRONTEND_WASM_BR_ETAG"),
+};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// Pick the best encoding allowed by an Accept-Encoding header. An encoding named in the
+    /// header is accepted unless its q is 0, and `*` covers the encodings that aren't named.
+    fn negotiate(accept_encoding: &str) -> Option<Self> {
+        // for br, gzip and `*`, whether they were named and then whether they were accepted
+        let (mut brotli, mut gzip, mut any) = (None, None, None);
+        for item in accept_encoding.split(',') {
+            let mut parts = item.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let refused = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .is_some_and(|q| q.trim_end_matches(['0', '.']).is_empty())
+            });
+            match name {
+                "br" => brotli = Some(!refused),
+                "gzip" => gzip = Some(!refused),
+                "*" => any = Some(!refused),
+                _ => {}
+            }
+        }
+        let any = any.unwrap_or(false);
+        if brotli.unwrap_or(any) {
+            Some(Encoding::Brotli)
+        } else if gzip.unwrap_or(any) {
+            Some(Encoding::Gzip)
+        } else {
+            None
+        }
+    }
+}
+
+struct EncodedBody {
+    content_type: &'static str,
+    body: &'static [u8],
+}
+
+impl Content for EncodedBody {
+    fn content_type(&self) -> &'static str {
+        self.content_type
+    }
+
+    fn content_length(&self) -> usize {
+        self.body.len()
+    }
+
+    async fn write_content<R: Read, W: Write>(
+        self,
+        _connection: Connection<R>,
+        mut writer: W,
+    ) -> Result<(), W::Error> {
+        writer.write_all(self.body).await
+    }
+}
+
+impl<State, PathParameters> RequestHandler<State, PathParameters> for Asset {
+    async fn call_request_handler<W: ResponseWriter>(
+        &self,
+        _state: &State,
+        _path_parameters: PathParameters,
+        request: Request<'_>,
+        response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+        let headers = request.headers();
+
+        // no Accept-Encoding header means any encoding will do
+        let encoding = match headers.get("Accept-Encoding") {
+            None => Some(Encoding::Gzip),
+            Some(accept_encoding) => Encoding::negotiate(accept_encoding),
+        };
+        let Some(encoding) = encoding else {
+            return (
+                status::NOT_ACCEPTABLE,
+                "Only gzip and brotli encodings are available\n",
+            )
+                .write_to(response_writer)
+                .await;
+        };
+
+        let (body, etag) = match encoding {
+            Encoding::Brotli => (self.brotli, self.brotli_etag),
+            Encoding::Gzip => (self.gzip, self.gzip_etag),
+        };
+
+        if headers
+            .get("If-None-Match")
+            .is_some_and(|tags| tags.split(',').map(str::trim).any(|tag| tag == etag))
+        {
+            return (
+                status::NOT_MODIFIED,
+                ("ETag", etag),
+                ("Vary", "Accept-Encoding"),
+                "",
+            )
+                .write_to(response_writer)
+                .await;
+        }
+
+        (
+            ("Content-Encoding", encoding.name()),
+            ("ETag", etag),
+            ("Cache-Control", "no-cache"),
+            ("Vary", "Accept-Encoding"),
+            EncodedBody {
+                content_type: self.content_type,
+                body,
+            },
+        )
+            .write_to(response_writer)
+            .await
+    }
+}
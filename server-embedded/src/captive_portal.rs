@@ -0,0 +1,172 @@
+//! Answers the connectivity checks phones and laptops make when they join the access point.
+//! While the portal is enabled every check is sent to the controller UI, so the OS pops it up;
+//! once it's switched off the checks get the answers each OS expects from the internet, so the
+//! popup closes and the device stays connected. Requests for any other host are sent to the UI.
+
+use core::net::Ipv4Addr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use picoserve::{
+    request::Request,
+    response::{status, IntoResponse, ResponseWriter},
+    routing::{Layer, Next},
+    KeepAlive, ResponseSent,
+};
+
+use crate::config::NetworkConfig;
+
+/// Whether connectivity checks are sent to the UI. Shared by every client, and reset on restart.
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// The connectivity checks made by each OS
+#[derive(Clone, Copy)]
+enum Probe {
+    /// Android and ChromeOS, expecting an empty 204
+    Android,
+    /// iOS and macOS, expecting a page saying "Success"
+    Apple,
+    /// Windows 10 and later
+    WindowsConnectTest,
+    /// older Windows
+    WindowsNcsi,
+    /// Firefox's `canonical.html`
+    FirefoxCanonical,
+    /// Firefox's `success.txt`
+    FirefoxSuccess,
+}
+
+impl Probe {
+    fn recognise(host: &str, path: &str) -> Option<Self> {
+        if host == "detectportal.firefox.com" {
+            return match path {
+                "/canonical.html" => Some(Probe::FirefoxCanonical),
+                "/success.txt" => Some(Probe::FirefoxSuccess),
+                _ => None,
+            };
+        }
+        match path {
+            "/generate_204" | "/gen_204" => Some(Probe::Android),
+            "/hotspot-detect.html" | "/library/test/success.html" => Some(Probe::Apple),
+            "/connecttest.txt" => Some(Probe::WindowsConnectTest),
+            "/ncsi.txt" => Some(Probe::WindowsNcsi),
+            _ => None,
+        }
+    }
+
+    /// The response that tells the OS it's online
+    async fn write_online<W: ResponseWriter>(self, writer: W) -> Result<ResponseSent, W::Error> {
+        let body = match self {
+            Probe::Android => return (status::NO_CONTENT, "").write_to(writer).await,
+            Probe::Apple => {
+                return (
+                    ("Content-Type", "text/html"),
+                    "<HTML><HEAD><TITLE>Success</TITLE></HEAD><BODY>Success</BODY></HTML>",
+                )
+                    .write_to(writer)
+                    .await
+            }
+            Probe::WindowsConnectTest => "Microsoft Connect Test",
+            Probe::WindowsNcsi => "Microsoft NCSI",
+            Probe::FirefoxCanonical => {
+                return (
+                    ("Content-Type", "text/html"),
+                    "<meta http-equiv=\"refresh\" \
+                     content=\"0;url=https://support.mozilla.org/kb/captive-portal\"/>",
+                )
+                    .write_to(writer)
+                    .await
+            }
+            Probe::FirefoxSuccess => "success\n",
+        };
+        body.write_to(writer).await
+    }
+}
+
+/// The address of the web server on the access point
+#[derive(Clone, Copy)]
+struct Location(&'static NetworkConfig);
+
+impl core::fmt::Display for Location {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "http://{}.{}/", self.0.hostname, self.0.domain)
+    }
+}
+
+/// Whether a Host header, without its port, names the web server: `<hostname>.<domain>`, the
+/// bare hostname, or the device's address. Names are compared ignoring case, like DNS does.
+fn is_ours(host: &str, network: &NetworkConfig) -> bool {
+    let name = host.strip_suffix('.').unwrap_or(host);
+    let hostname = network.hostname.as_str();
+    let full_name = name.split_once('.').is_some_and(|(host, domain)| {
+        host.eq_ignore_ascii_case(hostname) && domain.eq_ignore_ascii_case(&network.domain)
+    });
+    full_name
+        || name.eq_ignore_ascii_case(hostname)
+        || name.parse() == Ok(Ipv4Addr::from(network.address))
+}
+
+/// Sits in front of the app while the device runs its own access point
+pub struct CaptivePortal {
+    /// the network settings giving the web server's name, or `None` to pass everything through
+    network: Option<&'static NetworkConfig>,
+}
+
+impl CaptivePortal {
+    pub fn new(network: Option<&'static NetworkConfig>) -> Self {
+        Self { network }
+    }
+}
+
+impl<State, PathParameters> Layer<State, PathParameters> for CaptivePortal {
+    type NextState = State;
+
+    type NextPathParameters = PathParameters;
+
+    async fn call_layer<
+        NextLayer: Next<Self::NextState, Self::NextPathParameters>,
+        W: ResponseWriter,
+    >(
+        &self,
+        next: NextLayer,
+        state: &State,
+        path_parameters: PathParameters,
+        request: Request<'_>,
+        response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+        let Some(network) = self.network else {
+            return next.run(state, path_parameters, response_writer).await;
+        };
+
+        let host = request.headers().get("Host").unwrap_or_default();
+        let host = host.rsplit_once(':').map_or(host, |(host, _port)| host);
+        if is_ours(host, network) {
+            return next.run(state, path_parameters, response_writer).await;
+        }
+
+        if let Some(probe) = Probe::recognise(host, request.path().encoded()) {
+            log::info!("Connectivity check for {host}");
+            if !is_enabled() {
+                return probe.write_online(response_writer).await;
+            }
+        }
+
+        let location = Location(network);
+        (
+            status::FOUND,
+            ("Location", location),
+            ("Cache-Control", "no-store"),
+            ("Connection", KeepAlive::Close),
+            format_args!("{}\n", location),
+        )
+            .write_to(response_writer)
+            .await
+    }
+}
@@ -0,0 +1,414 @@
+//! The device configuration, kept in the last two sectors of flash so it survives reflashing the
+//! firmware. The linker script must leave those sectors out of FLASH.
+//!
+//! Each save goes to the sector not holding the newest record, so a save that's interrupted
+//! part way leaves the previous configuration intact, and both sectors wear at the same rate.
+//! Records carry a format version and a CRC, and the newest valid one is used at startup.
+
+use core::fmt::Write;
+
+use embassy_rp::flash::{self, Blocking, Flash, ERASE_SIZE};
+use embassy_rp::peripherals::FLASH;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+use heapless::String;
+use heapless::Vec;
+use matrix_state::scene::SceneMessage;
+use matrix_state::{Layout, Wiring};
+use serde::{Deserialize, Serialize};
+
+use crate::dhcp_server::MAX_POOL_SIZE;
+use crate::MAX_LEDS;
+
+/// The Pico W has 2MB of flash
+pub const FLASH_SIZE: usize = 2 * 1024 * 1024;
+const SLOT_OFFSETS: [u32; 2] = [
+    (FLASH_SIZE - 2 * ERASE_SIZE) as u32,
+    (FLASH_SIZE - ERASE_SIZE) as u32,
+];
+const MAGIC: [u8; 4] = *b"PMCF";
+/// Bump this whenever `Config` changes shape. Records from other versions are ignored.
+const CONFIG_VERSION: u16 = 1;
+/// magic, version, payload length, sequence number and CRC
+const HEADER_SIZE: usize = 16;
+const RECORD_SIZE: usize = 512;
+
+/// The configuration store, put here in `main` so the web server can change the configuration
+pub static CONFIG_STORE: Mutex<CriticalSectionRawMutex, Option<ConfigStore>> = Mutex::new(None);
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct Credentials {
+    pub ssid: String<32>,
+    /// empty for an open network
+    pub passphrase: String<63>,
+}
+
+impl Credentials {
+    /// WPA2 passphrases are 8 to 63 characters long
+    pub fn is_valid(&self) -> bool {
+        !self.ssid.is_empty() && (self.passphrase.is_empty() || self.passphrase.len() >= 8)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct AccessPointConfig {
+    /// empty for `pico-` followed by the end of the MAC address, so each device has its own
+    pub ssid: String<32>,
+    /// empty for a passphrase made from the flash chip's unique ID, which is logged at startup
+    pub passphrase: String<63>,
+    pub channel: u8,
+}
+
+impl AccessPointConfig {
+    /// The SSID to use, given the device's MAC address
+    pub fn ssid_for(&self, mac: [u8; 6]) -> String<32> {
+        if !self.ssid.is_empty() {
+            return self.ssid.clone();
+        }
+        let mut ssid = String::new();
+        let [.., a, b, c] = mac;
+        // "pico-" and six hex digits always fit
+        let _ = write!(ssid, "pico-{a:02x}{b:02x}{c:02x}");
+        ssid
+    }
+
+    /// The passphrase to use, given the flash chip's unique ID. Unlike the MAC address, the ID
+    /// isn't broadcast, so nobody nearby can work out the default passphrase.
+    pub fn passphrase_for(&self, unique_id: [u8; 8]) -> String<63> {
+        if !self.passphrase.is_empty() {
+            return self.passphrase.clone();
+        }
+        // FNV-1a, spelt out with letters and digits that can't be mistaken for each other
+        const ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+        let mut hash = unique_id.iter().fold(0xcbf29ce484222325u64, |hash, &b| {
+            (hash ^ b as u64).wrapping_mul(0x100000001b3)
+        });
+        let mut passphrase = String::new();
+        for _ in 0..12 {
+            let _ = passphrase.push(ALPHABET[(hash % ALPHABET.len() as u64) as usize] as char);
+            hash /= ALPHABET.len() as u64;
+        }
+        passphrase
+    }
+}
+
+pub const MAX_RESERVATIONS: usize = 8;
+
+/// An address the DHCP server only gives to the device with `mac`
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct Reservation {
+    pub mac: [u8; 6],
+    pub address: [u8; 4],
+}
+
+/// The DHCP server on the access point
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct DhcpConfig {
+    /// the first address handed out, with the rest of the pool counting up from it
+    pub pool_start: [u8; 4],
+    pub pool_size: u8,
+    pub subnet_mask: [u8; 4],
+    /// how long leases last, in seconds
+    pub lease_time: u32,
+    /// addresses in the pool kept for particular devices
+    pub reservations: Vec<Reservation, MAX_RESERVATIONS>,
+}
+
+impl DhcpConfig {
+    /// The number of leading ones in the subnet mask
+    pub fn prefix_len(&self) -> u8 {
+        u32::from_be_bytes(self.subnet_mask).leading_ones() as u8
+    }
+
+    /// Whether `address` is in the pool
+    pub fn contains(&self, address: [u8; 4]) -> bool {
+        let start = u32::from_be_bytes(self.pool_start);
+        u32::from_be_bytes(address).wrapping_sub(start) < self.pool_size as u32
+    }
+}
+
+/// The DNS server on the access point
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct DnsConfig {
+    /// how long answers can be cached for, in seconds
+    pub ttl: u32,
+    /// the answer for names other than the device's, or `None` to say they don't exist. The
+    /// device's own address sends connectivity checks to the captive portal.
+    pub wildcard: Option<[u8; 4]>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct NetworkConfig {
+    /// the network to join as a station, if any
+    pub station: Option<Credentials>,
+    /// the network to run when there's no station network to join
+    pub access_point: AccessPointConfig,
+    /// the device's address on its own access point
+    pub address: [u8; 4],
+    /// the web server is at `<hostname>.<domain>` on the access point
+    pub hostname: String<32>,
+    pub domain: String<32>,
+    /// the device is at `<mdns_name>.local` on a station network. Empty for `pico-matrix-`
+    /// followed by the end of the MAC address, so devices on the same network don't clash.
+    pub mdns_name: String<32>,
+    pub dhcp: DhcpConfig,
+    pub dns: DnsConfig,
+}
+
+impl NetworkConfig {
+    /// The mDNS name to use, given the device's MAC address
+    pub fn mdns_name_for(&self, mac: [u8; 6]) -> String<32> {
+        if !self.mdns_name.is_empty() {
+            return self.mdns_name.clone();
+        }
+        let mut name = String::new();
+        let [.., a, b, c] = mac;
+        // "pico-matrix-" and six hex digits always fit
+        let _ = write!(name, "pico-matrix-{a:02x}{b:02x}{c:02x}");
+        name
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct MatrixConfig {
+    pub layout: Layout,
+    /// the brightness at startup, from 0 to 1
+    pub brightness: f32,
+    /// sent to the scene at startup
+    pub boot_scene: SceneMessage,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct Config {
+    pub network: NetworkConfig,
+    pub matrix: MatrixConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            network: NetworkConfig {
+                station: None,
+                access_point: AccessPointConfig {
+                    ssid: String::new(),
+                    passphrase: String::new(),
+                    channel: 5,
+                },
+                address: [169, 254, 1, 1],
+                hostname: String::try_from("picohttp").unwrap(),
+                domain: String::try_from("piconet.local").unwrap(),
+                mdns_name: String::new(),
+                dhcp: DhcpConfig {
+                    pool_start: [169, 254, 1, 2],
+                    pool_size: 10,
+                    subnet_mask: [255, 255, 255, 0],
+                    lease_time: 60 * 60,
+                    reservations: Vec::new(),
+                },
+                dns: DnsConfig {
+                    ttl: 60,
+                    wildcard: Some([169, 254, 1, 1]),
+                },
+            },
+            matrix: MatrixConfig {
+                layout: Layout::new(16, 16).with_wiring(Wiring::Serpentine),
+                brightness: 1.0,
+                boot_scene: SceneMessage::Solid((0, 0, 0)),
+            },
+        }
+    }
+}
+
+impl Config {
+    /// Check the configuration is one the device can run with, describing the problem if not
+    pub fn validate(&self) -> Result<(), &'static str> {
+        let network = &self.network;
+        if network.station.as_ref().is_some_and(|c| !c.is_valid()) {
+            return Err("station SSIDs can't be empty, and passphrases need 8 characters");
+        }
+        let passphrase = &network.access_point.passphrase;
+        if !passphrase.is_empty() && passphrase.len() < 8 {
+            return Err("the access point passphrase needs 8 characters");
+        }
+        if !(1..=13).contains(&network.access_point.channel) {
+            return Err("the access point channel must be from 1 to 13");
+        }
+        if network.hostname.is_empty() || network.domain.is_empty() {
+            return Err("the hostname and domain can't be empty");
+        }
+        let is_label_char = |c: char| c.is_ascii_alphanumeric() || c == '-';
+        if !network.mdns_name.chars().all(is_label_char) {
+            return Err("the mDNS name must be letters, digits and hyphens");
+        }
+        let dhcp = &network.dhcp;
+        let mask = u32::from_be_bytes(dhcp.subnet_mask);
+        if mask.leading_ones() + mask.trailing_zeros() != 32 || mask.leading_ones() > 30 {
+            return Err("the subnet mask must be ones then zeros, with room for some hosts");
+        }
+        if dhcp.pool_size == 0 || dhcp.pool_size as usize > MAX_POOL_SIZE {
+            return Err("the DHCP pool size must be from 1 to 32");
+        }
+        let subnet = |address: [u8; 4]| u32::from_be_bytes(address) & mask;
+        let start = u32::from_be_bytes(dhcp.pool_start);
+        let end = start.checked_add(dhcp.pool_size as u32 - 1);
+        let pool_in_subnet = end.is_some_and(|end| {
+            subnet(dhcp.pool_start) == subnet(network.address) && end & mask == start & mask
+        });
+        if !pool_in_subnet || dhcp.contains(network.address) {
+            return Err("the DHCP pool must be in the device's subnet, without its address");
+        }
+        if dhcp.reservations.iter().any(|r| !dhcp.contains(r.address)) {
+            return Err("reserved addresses must be in the DHCP pool");
+        }
+        if dhcp.lease_time == 0 {
+            return Err("the lease time can't be zero");
+        }
+        if self.matrix.layout.is_empty() || self.matrix.layout.len() > MAX_LEDS {
+            return Err("the matrix layout has no LEDs, or more than the firmware supports");
+        }
+        if !(0.0..=1.0).contains(&self.matrix.brightness) {
+            return Err("the brightness must be from 0 to 1");
+        }
+        Ok(())
+    }
+}
+
+/// CRC-32 (IEEE), computed a bit at a time as records are small and rarely read
+fn crc32(chunks: &[&[u8]]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in chunks.iter().flat_map(|chunk| chunk.iter()) {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// The sequence number and configuration stored in a record, if it's valid
+fn decode(record: &[u8; RECORD_SIZE]) -> Option<(u32, Config)> {
+    let field = |range: core::ops::Range<usize>| &record[range];
+    if field(0..4) != MAGIC {
+        return None;
+    }
+    let version = u16::from_le_bytes(field(4..6).try_into().ok()?);
+    let length = u16::from_le_bytes(field(6..8).try_into().ok()?) as usize;
+    let sequence = u32::from_le_bytes(field(8..12).try_into().ok()?);
+    let crc = u32::from_le_bytes(field(12..16).try_into().ok()?);
+    if version != CONFIG_VERSION {
+        log::warn!("Ignoring a version {version} configuration record");
+        return None;
+    }
+    let payload = record.get(HEADER_SIZE..HEADER_SIZE + length)?;
+    if crc32(&[field(4..12), payload]) != crc {
+        log::warn!("Ignoring a configuration record with a bad CRC");
+        return None;
+    }
+    Some((sequence, postcard::from_bytes(payload).ok()?))
+}
+
+fn encode(sequence: u32, config: &Config) -> [u8; RECORD_SIZE] {
+    let mut record = [0xff; RECORD_SIZE];
+    let length = postcard::to_slice(config, &mut record[HEADER_SIZE..])
+        .expect("the configuration to fit in a record")
+        .len();
+    record[0..4].copy_from_slice(&MAGIC);
+    record[4..6].copy_from_slice(&CONFIG_VERSION.to_le_bytes());
+    record[6..8].copy_from_slice(&(length as u16).to_le_bytes());
+    record[8..12].copy_from_slice(&sequence.to_le_bytes());
+    let crc = crc32(&[&record[4..12], &record[HEADER_SIZE..HEADER_SIZE + length]]);
+    record[12..16].copy_from_slice(&crc.to_le_bytes());
+    record
+}
+
+pub struct ConfigStore {
+    flash: Flash<'static, FLASH, Blocking, FLASH_SIZE>,
+    config: Config,
+    /// the slot holding the newest record, and its sequence number
+    newest: Option<(usize, u32)>,
+    unique_id: [u8; 8],
+}
+
+impl ConfigStore {
+    /// Load the newest valid configuration, or the defaults if there isn't one
+    pub fn new(flash: FLASH) -> Self {
+        let mut flash = Flash::new_blocking(flash);
+        let mut unique_id = [0; 8];
+        if let Err(err) = flash.blocking_unique_id(&mut unique_id) {
+            log::error!("Couldn't read the flash chip's unique ID: {:?}", err);
+        }
+        let mut newest: Option<(usize, u32, Config)> = None;
+        for (slot, &offset) in SLOT_OFFSETS.iter().enumerate() {
+            let mut record = [0; RECORD_SIZE];
+            if flash.blocking_read(offset, &mut record).is_err() {
+                continue;
+            }
+            if let Some((sequence, config)) = decode(&record) {
+                // sequence numbers wrap, so compare them by their difference
+                let is_newer = newest.as_ref().map_or(true, |(_, newest, _)| {
+                    (sequence.wrapping_sub(*newest) as i32) > 0
+                });
+                if is_newer {
+                    newest = Some((slot, sequence, config));
+                }
+            }
+        }
+
+        match newest {
+            Some((slot, sequence, config)) => Self {
+                flash,
+                config,
+                newest: Some((slot, sequence)),
+                unique_id,
+            },
+            None => {
+                log::info!("No stored configuration, using the defaults");
+                Self {
+                    flash,
+                    config: Config::default(),
+                    newest: None,
+                    unique_id,
+                }
+            }
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The flash chip's unique ID, which the default access point passphrase is made from
+    pub fn unique_id(&self) -> [u8; 8] {
+        self.unique_id
+    }
+
+    /// Write the configuration to the older slot, doing nothing if it hasn't changed
+    pub fn save(&mut self, config: Config) -> Result<(), flash::Error> {
+        if self.newest.is_some() && config == self.config {
+            return Ok(());
+        }
+        let (slot, sequence) = match self.newest {
+            Some((slot, sequence)) => (1 - slot, sequence.wrapping_add(1)),
+            None => (0, 0),
+        };
+        let offset = SLOT_OFFSETS[slot];
+        self.flash
+            .blocking_erase(offset, offset + ERASE_SIZE as u32)?;
+        self.flash
+            .blocking_write(offset, &encode(sequence, &config))?;
+        self.config = config;
+        self.newest = Some((slot, sequence));
+        Ok(())
+    }
+
+    /// Erase both slots, going back to the default configuration
+    pub fn factory_reset(&mut self) -> Result<(), flash::Error> {
+        for offset in SLOT_OFFSETS {
+            self.flash
+                .blocking_erase(offset, offset + ERASE_SIZE as u32)?;
+        }
+        self.config = Config::default();
+        self.newest = None;
+        Ok(())
+    }
+}
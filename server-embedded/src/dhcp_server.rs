@@ -1,431 +1,160 @@
-use defmt::unwrap;
-use embassy_net::udp::{PacketMetadata, UdpSocket};
-use embassy_time::{Duration, Instant};
-use heapless::Vec;
-use smoltcp::wire::{
-    DhcpMessageType, DhcpOption, DhcpPacket, DhcpRepr, EthernetAddress, IpEndpoint, Ipv4Address,
-    Result,
-};
-
-pub const HOSTNAME: &str = "piconet.local";
-
-const OPTIONS: &[DhcpOption<'static>] = &[
-    DhcpOption {
-        kind: 15,
-        data: HOSTNAME.as_bytes(),
-    },
-    DhcpOption {
-        kind: 51,
-        data: &[0x00, 0x01, 0x51, 0x80],
-    },
-];
+//! Runs the lease logic from `dhcp-state` on a UDP socket
 
+use defmt::unwrap;
+use dhcp_state::{DhcpServer, Reservation, ServerConfig, Transport, MAX_HOSTNAME_LEN};
+use embassy_net::udp::{PacketMetadata, RecvError, SendError, UdpSocket};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+use heapless::{String, Vec};
+use serde::Serialize;
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::{DhcpOption, EthernetAddress, IpEndpoint, Ipv4Address};
+use static_cell::make_static;
+
+use crate::config::{NetworkConfig, MAX_RESERVATIONS};
+
+const SERVER_PORT: u16 = 67;
+const CLIENT_PORT: u16 = 68;
+/// The most addresses the pool can be configured with
+pub const MAX_POOL_SIZE: usize = 32;
+
+/// The server, put here by the task so the web server can list its leases
+static SERVER: Mutex<CriticalSectionRawMutex, Option<DhcpServer<'static, MAX_POOL_SIZE>>> =
+    Mutex::new(None);
+
+/// Only ever logged
+#[allow(dead_code)]
 #[derive(Debug)]
-enum DhcpAssignment {
-    Offered {
-        transaction_id: u32,
-        identifier: EthernetAddress,
-    },
-    Assigned {
-        transaction_id: u32,
-        identifier: EthernetAddress,
-        lease_end_time: Instant,
-    },
-    Free,
+enum SocketError {
+    Receive(RecvError),
+    Send(SendError),
 }
 
-struct DhcpServer<
-    'a,
-    const N_ADDRESSES: usize,
-    const SERVER_PORT: u16,
-    const CLIENT_PORT: u16,
-    const DATA_BUFFER_LEN: usize,
-> {
-    server_address: Ipv4Address,
-    assignments: [DhcpAssignment; N_ADDRESSES],
+/// Receives requests on the server port and broadcasts replies to the client port, as clients
+/// don't have an address to reply to yet
+struct SocketTransport<'a> {
     socket: UdpSocket<'a>,
-    data_buffer: [u8; DATA_BUFFER_LEN],
-    lease_time: Duration,
 }
 
-impl<
-        'a,
-        const N_ADDRESSES: usize,
-        const SERVER_PORT: u16,
-        const CLIENT_PORT: u16,
-        const DATA_BUFFER_LEN: usize,
-    > DhcpServer<'a, N_ADDRESSES, SERVER_PORT, CLIENT_PORT, DATA_BUFFER_LEN>
-{
-    fn construct_packet_repr(
-        message_type: DhcpMessageType,
-        server_ip: Ipv4Address,
-        client_ip: Ipv4Address,
-        client_hardware_address: EthernetAddress,
-        transaction_id: u32,
-        assigned_address: Ipv4Address,
-        lease_duration_seconds: Option<u32>,
-    ) -> DhcpRepr<'a> {
-        DhcpRepr {
-            message_type,
-            transaction_id,
-            secs: 0,
-            client_hardware_address,
-            client_ip,
-            your_ip: assigned_address,
-            server_ip: Ipv4Address::new(0, 0, 0, 0),
-            router: Some(server_ip),
-            subnet_mask: Some(Ipv4Address::new(255, 255, 255, 0)),
-            relay_agent_ip: Ipv4Address::new(0, 0, 0, 0),
-            broadcast: false,
-            requested_ip: None,
-            client_identifier: None,
-            server_identifier: Some(server_ip),
-            parameter_request_list: None,
-            dns_servers: Some(unwrap!(Vec::from_slice(&[server_ip]))),
-            max_size: None,
-            lease_duration: None,
-            renew_duration: None,
-            rebind_duration: None,
-            additional_options: OPTIONS,
-        }
-    }
+impl Transport for SocketTransport<'_> {
+    type Error = SocketError;
 
-    fn construct_offer(
-        server_ip: Ipv4Address,
-        client_hardware_address: EthernetAddress,
-        transaction_id: u32,
-        assigned_address: Ipv4Address,
-        lease_duration_seconds: u32,
-    ) -> DhcpRepr<'a> {
-        Self::construct_packet_repr(
-            DhcpMessageType::Offer,
-            server_ip,
-            Ipv4Address::new(0, 0, 0, 0),
-            client_hardware_address,
-            transaction_id,
-            assigned_address,
-            Some(lease_duration_seconds),
-        )
-    }
-
-    fn construct_ack(
-        server_ip: Ipv4Address,
-        client_hardware_address: EthernetAddress,
-        client_ip: Ipv4Address,
-        transaction_id: u32,
-        address: Ipv4Address,
-    ) -> DhcpRepr<'a> {
-        Self::construct_packet_repr(
-            DhcpMessageType::Ack,
-            server_ip,
-            client_ip,
-            client_hardware_address,
-            transaction_id,
-            address,
-            None,
-        )
-    }
-
-    fn construct_nack(
-        client_hardware_address: EthernetAddress,
-        client_ip: Ipv4Address,
-        transaction_id: u32,
-    ) -> DhcpRepr<'a> {
-        Self::construct_packet_repr(
-            DhcpMessageType::Nak,
-            Ipv4Address::new(0, 0, 0, 0),
-            client_ip,
-            client_hardware_address,
-            transaction_id,
-            Ipv4Address::new(0, 0, 0, 0),
-            None,
-        )
-    }
-
-    async fn construct_and_send_offer(
-        &mut self,
-        client_hardware_address: EthernetAddress,
-        transaction_id: u32,
-        index: u8,
-    ) -> Result<()> {
-        let mut packet = DhcpPacket::new_checked(&mut self.data_buffer)?;
-        let address = Self::construct_address(index);
-        let packet_repr = Self::construct_offer(
-            self.server_address,
-            client_hardware_address,
-            transaction_id,
-            address,
-            self.lease_time.as_secs() as u32,
-        );
-        let len = packet_repr.buffer_len();
-
-        packet_repr.emit(&mut packet)?;
-        packet.set_your_ip(address);
-        self.socket
-            .send_to(
-                &self.data_buffer[..len],
-                IpEndpoint::new(Ipv4Address::BROADCAST.into(), CLIENT_PORT),
-            )
-            .await
-            .map_err(|_| smoltcp::wire::Error)?;
-        Ok(())
-    }
-
-    async fn construct_and_send_ack(
-        &mut self,
-        client_hardware_address: EthernetAddress,
-        transaction_id: u32,
-        index: u8,
-        client_ip: Ipv4Address,
-    ) -> Result<()> {
-        let mut packet = DhcpPacket::new_checked(&mut self.data_buffer)?;
-        let address = Self::construct_address(index);
-        let packet_repr = Self::construct_ack(
-            self.server_address,
-            client_hardware_address,
-            client_ip,
-            transaction_id,
-            address,
-        );
-        let len = packet_repr.buffer_len();
-
-        packet_repr.emit(&mut packet)?;
-	packet.set_your_ip(client_ip);
-        self.socket
-            .send_to(
-                &self.data_buffer[..len],
-                IpEndpoint::new(Ipv4Address::BROADCAST.into(), CLIENT_PORT),
-            )
+    async fn receive(&mut self, buffer: &mut [u8]) -> Result<usize, SocketError> {
+        let (len, _) = self
+            .socket
+            .recv_from(buffer)
             .await
-            .map_err(|_| smoltcp::wire::Error)?;
-        Ok(())
+            .map_err(SocketError::Receive)?;
+        Ok(len)
     }
 
-    async fn construct_and_send_nack(
-        &mut self,
-        client_hardware_address: EthernetAddress,
-        transaction_id: u32,
-        client_ip: Ipv4Address,
-    ) -> Result<()> {
-        let mut packet = DhcpPacket::new_checked(&mut self.data_buffer)?;
-        let packet_repr = Self::construct_nack(client_hardware_address, client_ip, transaction_id);
-        let len = packet_repr.buffer_len();
-
-        packet_repr.emit(&mut packet)?;
+    async fn send(&mut self, data: &[u8]) -> Result<(), SocketError> {
         self.socket
             .send_to(
-                &self.data_buffer[..len],
+                data,
                 IpEndpoint::new(Ipv4Address::BROADCAST.into(), CLIENT_PORT),
             )
             .await
-            .map_err(|_| smoltcp::wire::Error)?;
-        Ok(())
-    }
-
-    fn new(
-        mut socket: UdpSocket<'a>,
-        server_address: Ipv4Address,
-        lease_time: Duration,
-    ) -> Option<Self> {
-        if socket.endpoint().is_specified() {
-            None
-        } else {
-            socket.bind(SERVER_PORT).ok()?;
-            Some(Self {
-                lease_time,
-                server_address,
-                assignments: [(); N_ADDRESSES].map(|_| DhcpAssignment::Free),
-                socket,
-                data_buffer: [0u8; DATA_BUFFER_LEN],
-            })
-        }
-    }
-
-    /// this function constructs the addresses that we'll use.
-    /// TODO: probably update this???
-    fn construct_address(i: u8) -> Ipv4Address {
-        Ipv4Address::new(169, 254, 1, i + 2)
-    }
-
-    /// Given a discover message from a client, go through the list of addresses.
-    /// If there is already an offer out to the same identifier, update the transaction_id and
-    /// send a new offer out
-    /// If the offer is to a different identifier, and this is the first offer like that found,
-    /// make this offer be possibly overwritten
-    /// If there is already an assignment, send out an offer with the assignment's address and
-    /// new transaction ID
-    /// If there is a free spot, create an offer in that spot and send out an offer.
-    /// If there are no free spots, use the first different ID offer found.
-    async fn process_discover(
-        &mut self,
-        client_hardware_address: EthernetAddress,
-        client_identifier: Option<EthernetAddress>,
-        transaction_id: u32,
-    ) -> Result<()> {
-        let id = client_identifier.unwrap_or(client_hardware_address);
-        let mut first_offered_index = None;
-        log::info!("Checking through the assignments, {:?}", transaction_id);
-        for (index, assignment) in self.assignments.iter_mut().enumerate() {
-            match assignment {
-                // if the lease has been offered to another index, then this can possibly be taken
-                // if there are no more spots
-                DhcpAssignment::Offered {
-                    identifier: a_identifier,
-                    ..
-                } if a_identifier != &id => first_offered_index = Some(index),
-                // if the assignment is given to a different identifier, and the lease time on that assignment is not up yet, then this spot can't be used
-                DhcpAssignment::Assigned {
-                    identifier: a_identifier,
-                    lease_end_time,
-                    ..
-                } if (*a_identifier != id) && (Instant::now() < *lease_end_time) => {}
-                _ => {
-                    // we are free to use this assignment spot
-                    *assignment = DhcpAssignment::Offered {
-                        transaction_id,
-                        identifier: id,
-                    };
-                    return self
-                        .construct_and_send_offer(
-                            client_hardware_address,
-                            transaction_id,
-                            index as u8,
-                        )
-                        .await;
-                }
-            }
-        }
-        log::info!("OFFER: {:?}", first_offered_index);
-        if let Some(i) = first_offered_index {
-            *unsafe { self.assignments.get_unchecked_mut(i) } = DhcpAssignment::Offered {
-                identifier: id,
-                transaction_id,
-            };
-            self.construct_and_send_offer(client_hardware_address, transaction_id, i as u8)
-                .await
-        } else {
-            Ok(())
-        }
+            .map_err(SocketError::Send)
     }
+}
 
-    async fn process_request(
-        &mut self,
-        client_ip: Ipv4Address,
-        client_hardware_address: EthernetAddress,
-        client_identifier: Option<EthernetAddress>,
-        transaction_id: u32,
-    ) -> Result<()> {
-        let id = client_identifier.unwrap_or(client_hardware_address);
-        let mut assigned_index = None;
-        for (i, assignment) in self.assignments.iter_mut().enumerate() {
-            match assignment {
-                // we have offered an assignment with this transaction_id to this identifier
-                DhcpAssignment::Offered {
-                    identifier: a_identifier,
-                    transaction_id: a_transaction_id,
-                } if (*a_identifier == id) && (*a_transaction_id == transaction_id) => {
-                    *assignment = DhcpAssignment::Assigned {
-                        identifier: id,
-                        lease_end_time: Instant::now()
-                            .checked_add(self.lease_time)
-                            .ok_or(smoltcp::wire::Error)?,
-                        transaction_id,
-                    };
-                    assigned_index = Some(i);
-                    break;
-                }
-                // we have already assigned an ip to this identifier with this transaction_id, and the
-                // lease time isn't up
-                DhcpAssignment::Assigned {
-                    identifier: a_identifier,
-                    transaction_id: a_transaction_id,
-                    lease_end_time,
-                } if (*a_identifier == id)
-                    && (*a_transaction_id == transaction_id)
-                    && (Instant::now() < *lease_end_time) =>
-                {
-                    *lease_end_time = Instant::now()
-                        .checked_add(self.lease_time)
-                        .ok_or(smoltcp::wire::Error)?;
-                    assigned_index = Some(i);
-                    break;
-                }
-                _ => {}
-            }
-        }
-        if let Some(i) = assigned_index {
-            self.construct_and_send_ack(client_hardware_address, transaction_id, i as u8, client_ip)
-                .await
-        } else {
-            self.construct_and_send_nack(client_hardware_address, transaction_id, client_ip)
-                .await
-        }
-    }
+fn now() -> Instant {
+    Instant::from_micros(embassy_time::Instant::now().as_micros() as i64)
+}
 
-    async fn process_packet(&mut self) -> Result<()> {
-        let packet = DhcpPacket::new_checked(&self.data_buffer)?;
-        let packet_repr = DhcpRepr::parse(&packet)?;
-        match packet_repr.message_type {
-            DhcpMessageType::Discover => {
-                self.process_discover(
-                    packet_repr.client_hardware_address,
-                    packet_repr.client_identifier,
-                    packet_repr.transaction_id,
-                )
-                .await
-            }
-            DhcpMessageType::Request => {
-                self.process_request(
-                    packet_repr.client_ip,
-                    packet_repr.client_hardware_address,
-                    packet_repr.client_identifier,
-                    packet_repr.transaction_id,
-                )
-                .await
-            }
-            _ => Ok(()),
-        }
-    }
+/// A running lease, as sent by the web server
+#[derive(Serialize)]
+pub struct LeaseInfo {
+    pub address: [u8; 4],
+    pub mac: [u8; 6],
+    /// empty if the device didn't send one
+    pub hostname: String<MAX_HOSTNAME_LEN>,
+    /// seconds until the lease ends
+    pub expires_in: u64,
+}
 
-    async fn run(&mut self) -> ! {
-        loop {
-            match self.socket.recv_from(&mut self.data_buffer).await {
-                Ok((_, _)) => {
-                    if let Err(_) = self.process_packet().await {
-                        log::warn!("Error processing dhcp packet!!!");
-                    }
-                }
-                Err(_) => {
-                    log::info!("Error receiving data")
-                }
-            }
-        }
-    }
+/// The running leases, or none if the DHCP server isn't running
+pub async fn leases() -> Vec<LeaseInfo, MAX_POOL_SIZE> {
+    let server = SERVER.lock().await;
+    let Some(server) = server.as_ref() else {
+        return Vec::new();
+    };
+    let now = now();
+    server
+        .leases(now)
+        .map(|lease| LeaseInfo {
+            address: lease.address.0,
+            mac: lease.identifier.0,
+            hostname: String::try_from(lease.hostname).unwrap_or_default(),
+            expires_in: (lease.lease_end - now).secs(),
+        })
+        .collect()
 }
 
+/// Hand out addresses from the configured pool to clients of the access point
 #[embassy_executor::task]
 pub async fn dhcp_server_task(
     stack: &'static embassy_net::Stack<cyw43::NetDriver<'static>>,
-    assigned_address: Ipv4Address,
+    network: &'static NetworkConfig,
 ) -> ! {
-    let mut rx_meta = [PacketMetadata::EMPTY; 1024];
-    let mut rx_buffer = [0; 1024];
-    let mut tx_meta = [PacketMetadata::EMPTY; 1024];
-    let mut tx_buffer = [0; 1024];
+    let mut rx_meta = [PacketMetadata::EMPTY; 16];
+    let mut rx_buffer = [0; 2048];
+    let mut tx_meta = [PacketMetadata::EMPTY; 16];
+    let mut tx_buffer = [0; 2048];
 
-    let socket = embassy_net::udp::UdpSocket::new(
+    let mut socket = UdpSocket::new(
         stack,
         &mut rx_meta,
         &mut rx_buffer,
         &mut tx_meta,
         &mut tx_buffer,
     );
+    unwrap!(socket.bind(SERVER_PORT));
+    let mut transport = SocketTransport { socket };
 
-    let mut server: DhcpServer<'_, 10, 67, 68, 2048> = unwrap!(DhcpServer::new(
-        socket,
-        assigned_address,
-        Duration::from_secs(60 * 60)
-    ));
-
-    server.run().await
+    let options = make_static!([DhcpOption {
+        kind: 15,
+        data: network.domain.as_bytes(),
+    }]);
+    let dhcp = &network.dhcp;
+    let reservations: &'static Vec<Reservation, MAX_RESERVATIONS> = make_static!(dhcp
+        .reservations
+        .iter()
+        .map(|r| Reservation {
+            hardware_address: EthernetAddress(r.mac),
+            address: Ipv4Address(r.address),
+        })
+        .collect());
+
+    SERVER.lock().await.replace(DhcpServer::new(ServerConfig {
+        server_address: Ipv4Address(network.address),
+        subnet_mask: Ipv4Address(dhcp.subnet_mask),
+        pool_start: Ipv4Address(dhcp.pool_start),
+        pool_size: dhcp.pool_size as usize,
+        lease_time: Duration::from_secs(dhcp.lease_time as u64),
+        reservations,
+        options,
+    }));
+
+    let mut buffer = [0; dhcp_state::REQUEST_BUFFER_LEN];
+    loop {
+        let len = match transport.receive(&mut buffer).await {
+            Ok(len) => len,
+            Err(err) => {
+                log::warn!("Error receiving a DHCP packet: {:?}", err);
+                continue;
+            }
+        };
+        let mut server = SERVER.lock().await;
+        let Some(server) = server.as_mut() else {
+            continue;
+        };
+        if let Err(err) = server
+            .process_packet(now(), &buffer[..len], &mut transport)
+            .await
+        {
+            log::warn!("Error processing a DHCP packet: {:?}", err);
+        }
+    }
 }
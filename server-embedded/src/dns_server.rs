@@ -1,65 +1,48 @@
+use core::fmt::Write;
+
+use dns_packet::Responder;
 use embassy_net::udp::{PacketMetadata, UdpSocket};
-use smoltcp::wire::{IpEndpoint, Ipv4Address};
+use heapless::String;
+use smoltcp::wire::Ipv4Address;
 
-use crate::dns_packet::{DnsHeader, DnsPacket};
+use crate::config::NetworkConfig;
 
 struct DNSServer<'a, const SERVER_PORT: u16, const DATA_BUFFER_LEN: usize> {
     socket: UdpSocket<'a>,
-    data_buffer: [u8; DATA_BUFFER_LEN],
-    primary_address: Ipv4Address,
-    secondary_address: Ipv4Address,
+    query: [u8; DATA_BUFFER_LEN],
+    response: [u8; DATA_BUFFER_LEN],
+    responder: Responder<'a>,
 }
 
 impl<'a, const SERVER_PORT: u16, const DATA_BUFFER_LEN: usize>
     DNSServer<'a, SERVER_PORT, DATA_BUFFER_LEN>
 {
-    fn new(
-        mut socket: UdpSocket<'a>,
-        primary_address: Ipv4Address,
-        secondary_address: Ipv4Address,
-    ) -> Option<Self> {
+    fn new(mut socket: UdpSocket<'a>, responder: Responder<'a>) -> Option<Self> {
         if socket.endpoint().is_specified() {
             None
         } else {
             socket.bind(SERVER_PORT).ok()?;
             Some(Self {
                 socket,
-                data_buffer: [0; DATA_BUFFER_LEN],
-                primary_address,
-                secondary_address,
+                query: [0; DATA_BUFFER_LEN],
+                response: [0; DATA_BUFFER_LEN],
+                responder,
             })
         }
     }
 
-    async fn process_packet(
-        data_buffer: &mut [u8],
-        assigned_address: Ipv4Address,
-        secondary_address: Ipv4Address,
-    ) -> Option<&[u8]> {
-        DnsPacket::transform_query_to_response(data_buffer, assigned_address, secondary_address)
-    }
-
     async fn run(&mut self) -> ! {
-        log::info!("In the run function");
         loop {
-            let DNSServer {
-                data_buffer,
-                primary_address,
-                secondary_address,
-                ..
-            } = self;
-            match self.socket.recv_from(data_buffer).await {
-                Ok((_, endpoint)) => {
-                    log::info!("Got a dns packet");
-                    if let Some(response_buffer) =
-                        Self::process_packet(data_buffer, *primary_address, *secondary_address)
-                            .await
-                    {
-                        log::info!("Sending response buffer: {:?}", response_buffer);
-                        self.socket
-                            .send_to(response_buffer, endpoint)
-                            .await
-                            .unwrap();
+            match self.socket.recv_from(&mut self.query).await {
+                Ok((len, endpoint)) => {
+                    let Some(response) = self
+                        .responder
+                        .respond(&self.query[..len], &mut self.response)
+                    else {
+                        continue;
+                    };
+                    if let Err(err) = self.socket.send_to(response, endpoint).await {
+                        log::warn!("Couldn't send a DNS response: {:?}", err);
                     }
                 }
                 Err(_) => log::info!("Error receiving data"),
@@ -68,15 +51,15 @@ impl<'a, const SERVER_PORT: u16, const DATA_BUFFER_LEN: usize>
     }
 }
 
+/// Answer for `<hostname>` and `<hostname>.<domain>` with the device's address
 #[embassy_executor::task]
 pub async fn dns_server_task(
     stack: &'static embassy_net::Stack<cyw43::NetDriver<'static>>,
-    primary_address: Ipv4Address,
-    secondary_address: Ipv4Address,
+    network: &'static NetworkConfig,
 ) -> ! {
-    let mut rx_meta = [PacketMetadata::EMPTY; 1024];
+    let mut rx_meta = [PacketMetadata::EMPTY; 16];
     let mut rx_buffer = [0; 1024];
-    let mut tx_meta = [PacketMetadata::EMPTY; 1024];
+    let mut tx_meta = [PacketMetadata::EMPTY; 16];
     let mut tx_buffer = [0; 1024];
 
     let socket = embassy_net::udp::UdpSocket::new(
@@ -87,8 +70,17 @@ pub async fn dns_server_task(
         &mut tx_buffer,
     );
 
-    let mut server: DNSServer<'_, 53, 2048> =
-        DNSServer::new(socket, primary_address, secondary_address).unwrap();
-    log::info!("RUNNING DNS SERVER");
+    let mut full_name = String::<65>::new();
+    // a hostname and domain of 32 characters each always fit
+    let _ = write!(full_name, "{}.{}", network.hostname, network.domain);
+    let names = [network.hostname.as_str(), full_name.as_str()];
+
+    let responder = Responder {
+        names: &names,
+        address: Ipv4Address(network.address),
+        wildcard: network.dns.wildcard.map(Ipv4Address),
+        ttl: network.dns.ttl,
+    };
+    let mut server: DNSServer<'_, 53, 1024> = DNSServer::new(socket, responder).unwrap();
     server.run().await
 }
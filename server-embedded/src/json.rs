@@ -0,0 +1,51 @@
+//! picoserve can write JSON responses but not read JSON requests, so this extractor fills the gap
+
+use picoserve::{
+    extract::FromRequest,
+    request::Request,
+    response::{status, IntoResponse, ResponseWriter},
+    ResponseSent,
+};
+use serde::de::DeserializeOwned;
+
+/// Extracts the body of a request as JSON. serde_json_core leaves escape sequences in strings
+/// as they were sent, so bodies with any are rejected rather than, say, saving a passphrase
+/// with a stray backslash. Strings with `"` or `\` in them have to be sent as forms instead.
+pub struct JsonBody<T: DeserializeOwned>(pub T);
+
+pub enum JsonRejection {
+    BadJson,
+    EscapedString,
+}
+
+impl IntoResponse for JsonRejection {
+    async fn write_to<W: ResponseWriter>(
+        self,
+        response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+        let message = match self {
+            JsonRejection::BadJson => "Bad JSON\n",
+            JsonRejection::EscapedString => {
+                "JSON strings can't contain escape sequences, so passphrases with \" or \\ \
+                 in them have to be set with the /api/wifi or /api/access-point forms\n"
+            }
+        };
+        (status::BAD_REQUEST, message)
+            .write_to(response_writer)
+            .await
+    }
+}
+
+impl<State, T: DeserializeOwned> FromRequest<State> for JsonBody<T> {
+    type Rejection = JsonRejection;
+
+    async fn from_request(_state: &State, request: &Request<'_>) -> Result<Self, JsonRejection> {
+        // outside of strings, JSON has no backslashes
+        if request.body().contains(&b'\\') {
+            return Err(JsonRejection::EscapedString);
+        }
+        serde_json_core::from_slice(request.body())
+            .map(|(value, _)| JsonBody(value))
+            .map_err(|_| JsonRejection::BadJson)
+    }
+}
@@ -2,37 +2,52 @@
 #![no_main]
 #![feature(type_alias_impl_trait)]
 
-use crate::network::set_up_network_stack;
+use crate::config::{ConfigStore, CONFIG_STORE};
+use crate::network::{set_up_network_stack, NetworkMode};
 use cyw43::NetDriver;
 use defmt as _;
 use defmt_rtt as _;
 use dhcp_server::dhcp_server_task;
 use dns_server::dns_server_task;
 use embassy_net::{tcp::TcpSocket, Stack};
+use embassy_rp::pio::Pio;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
 use embassy_time::Timer;
 use embedded_io_async::Write;
-use matrix_state;
+use matrix_state::scene::{Scene, Solid};
+use matrix_state::{MatrixState, MatrixStateMessage, Updateable, VariantMessage};
+use mdns_server::{mdns_server_task, MDNS_HARDWARE_ADDRESS};
 use panic_probe as _;
-use smoltcp::wire::Ipv4Address;
+use render::{render_task, MESSAGES};
+use static_cell::make_static;
 use web::start_server;
+use ws2812::Ws2812;
 
+mod assets;
+mod captive_portal;
+mod config;
 mod dhcp_server;
-mod dns_packet;
 mod dns_server;
+mod json;
+mod mdns_server;
 mod network;
+mod render;
 mod web;
+mod ws2812;
 
 embassy_rp::bind_interrupts!(
     struct Irqs {
         PIO0_IRQ_0 => embassy_rp::pio::InterruptHandler<embassy_rp::peripherals::PIO0>;
+        PIO1_IRQ_0 => embassy_rp::pio::InterruptHandler<embassy_rp::peripherals::PIO1>;
 	USBCTRL_IRQ => embassy_rp::usb::InterruptHandler<embassy_rp::peripherals::USB>;
     }
 );
 
 const WEB_TASK_POOL_SIZE: usize = 10;
-const INDEX_HTML: &str = include_str!(env!("FRONTEND_HTML"));
-const FRONTEND_JS: &str = include_str!(env!("FRONTEND_JS"));
-const FRONTEND_WASM: &[u8] = include_bytes!(env!("FRONTEND_WASM"));
+/// the most LEDs the configured matrix layout can use
+const MAX_LEDS: usize = 1024;
+pub const FIRMWARE_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 #[embassy_executor::task]
 async fn logger_task(usb: embassy_rp::peripherals::USB) {
@@ -40,6 +55,17 @@ async fn logger_task(usb: embassy_rp::peripherals::USB) {
     embassy_usb_logger::run!(1024, log::LevelFilter::Info, driver);
 }
 
+/// Signalled to restart the device, once a response has had time to go out
+pub static RESTART: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+#[embassy_executor::task]
+async fn restart_task() {
+    RESTART.wait().await;
+    log::info!("Restarting...");
+    Timer::after_secs(1).await;
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
 #[embassy_executor::task]
 async fn alive() {
     loop {
@@ -53,9 +79,37 @@ async fn main(spawner: embassy_executor::Spawner) {
     let p = embassy_rp::init(Default::default());
 
     spawner.must_spawn(logger_task(p.USB));
-    let server_address = Ipv4Address::new(169, 254, 1, 1);
-    let outside_address = Ipv4Address::new(198, 51, 100, 0);
-    let (_, stack) = set_up_network_stack(
+
+    let config_store = ConfigStore::new(p.FLASH);
+    let config = &*make_static!(config_store.config().clone());
+    let unique_id = config_store.unique_id();
+    CONFIG_STORE.lock().await.replace(config_store);
+    spawner.must_spawn(restart_task());
+
+    // the matrix data line is on GP16
+    let Pio {
+        mut common, sm0, ..
+    } = Pio::new(p.PIO1, Irqs);
+    let mut display: Ws2812<'_, _, 0, MAX_LEDS> = Ws2812::new(
+        &mut common,
+        sm0,
+        p.DMA_CH1,
+        p.PIN_16,
+        config.matrix.layout,
+    );
+    // clear whatever the LEDs powered up showing
+    display.push().await;
+    let mut state = MatrixState::new(Scene::Solid(Solid::default()));
+    state.set_brightness(config.matrix.brightness);
+    state.update(
+        Some(MatrixStateMessage::UpdateImage(VariantMessage::Switch(
+            config.matrix.boot_scene.clone(),
+        ))),
+        &mut display,
+    );
+    spawner.must_spawn(render_task(display, state, MESSAGES.receiver()));
+
+    let (mut control, stack, mode) = set_up_network_stack(
         &spawner,
         p.PIN_23,
         p.PIN_25,
@@ -63,13 +117,24 @@ async fn main(spawner: embassy_executor::Spawner) {
         p.PIN_24,
         p.PIN_29,
         p.DMA_CH0,
-        server_address,
-        outside_address,
+        &config.network,
+        unique_id,
     )
     .await;
 
-    // spawner.must_spawn(dhcp_server_task(stack, server_address));
-    // spawner.must_spawn(dns_server_task(stack, server_address, outside_address));
-    // start_server(&spawner, stack).await;
+    if mode == NetworkMode::AccessPoint {
+        // clients of the access point need an address, and the DNS server points them at the
+        // web server and captive portal
+        spawner.must_spawn(dhcp_server_task(stack, &config.network));
+        spawner.must_spawn(dns_server_task(stack, &config.network));
+    }
+    if mode == NetworkMode::Station {
+        // the WiFi chip drops multicast frames for groups it hasn't been told about
+        if let Err(err) = control.add_multicast_address(MDNS_HARDWARE_ADDRESS).await {
+            log::warn!("Couldn't accept mDNS packets: {:?}", err);
+        }
+        spawner.must_spawn(mdns_server_task(stack, config));
+    }
+    start_server(&spawner, stack, mode, config).await;
     spawner.must_spawn(alive());
 }
@@ -0,0 +1,116 @@
+//! Answers multicast DNS queries on a station network, so the device can be found as
+//! `<mdns_name>.local` and its services browsed, without the network's resolver knowing it
+
+use core::fmt::Write;
+
+use defmt::unwrap;
+use dns_packet::mdns::{MdnsResponder, Service, MDNS_ADDRESS, MDNS_PORT};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_time::Timer;
+use heapless::String;
+use smoltcp::wire::{HardwareAddress, IpEndpoint, Ipv4Address};
+
+use crate::config::Config;
+use crate::FIRMWARE_VERSION;
+
+/// The Ethernet address 224.0.0.251 maps to, which the WiFi chip has to be told to accept
+pub const MDNS_HARDWARE_ADDRESS: [u8; 6] = [0x01, 0x00, 0x5e, 0x00, 0x00, 0xfb];
+/// mDNS messages can be larger than classic DNS ones, up to the size of a packet
+const BUFFER_LEN: usize = 1500;
+/// RFC 6762 section 8.3 asks for at least two announcements, a second apart
+const ANNOUNCEMENTS: usize = 2;
+/// the control protocol's service type, so tools can find every matrix on the network
+const CONTROL_SERVICE: &str = "_pico-matrix._tcp";
+
+/// Advertise the device's name, web server and control protocol on a station network
+#[embassy_executor::task]
+pub async fn mdns_server_task(
+    stack: &'static embassy_net::Stack<cyw43::NetDriver<'static>>,
+    config: &'static Config,
+) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; BUFFER_LEN];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; BUFFER_LEN];
+
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    // responses to port 5353 must come from it, with a hop limit of 255 (RFC 6762 section 11)
+    unwrap!(socket.bind(MDNS_PORT));
+    socket.set_hop_limit(Some(255));
+    if let Err(err) = stack.join_multicast_group(MDNS_ADDRESS).await {
+        log::warn!("Couldn't join the mDNS group: {:?}", err);
+    }
+
+    let name = match stack.hardware_address() {
+        HardwareAddress::Ethernet(mac) => config.network.mdns_name_for(mac.0),
+        #[allow(unreachable_patterns)]
+        _ => config.network.mdns_name_for([0; 6]),
+    };
+    let (rows, cols) = config.matrix.layout.size();
+    let mut size = String::<32>::new();
+    let _ = write!(size, "size={cols}x{rows}");
+    let mut version = String::<32>::new();
+    let _ = write!(version, "version={FIRMWARE_VERSION}");
+    let http_txt = [size.as_str(), version.as_str(), "path=/"];
+    let control_txt = [size.as_str(), version.as_str(), "path=/ws/ws"];
+    let services = [
+        Service {
+            service_type: "_http._tcp",
+            port: 80,
+            txt: &http_txt,
+        },
+        Service {
+            service_type: CONTROL_SERVICE,
+            port: 80,
+            txt: &control_txt,
+        },
+    ];
+    // the address from the network's DHCP server, which can change when the lease is renewed
+    let responder = || MdnsResponder {
+        hostname: &name,
+        address: stack
+            .config_v4()
+            .map_or(Ipv4Address::UNSPECIFIED, |c| c.address.address()),
+        services: &services,
+    };
+    let group = IpEndpoint::new(MDNS_ADDRESS.into(), MDNS_PORT);
+
+    let mut query = [0; BUFFER_LEN];
+    let mut response = [0; BUFFER_LEN];
+    for i in 0..ANNOUNCEMENTS {
+        if i > 0 {
+            Timer::after_secs(1).await;
+        }
+        if let Some(announcement) = responder().announcement(&mut response) {
+            if let Err(err) = socket.send_to(announcement, group).await {
+                log::warn!("Couldn't send an mDNS announcement: {:?}", err);
+            }
+        }
+    }
+    log::info!("Answering mDNS queries as {name}.local");
+
+    loop {
+        let (len, endpoint) = match socket.recv_from(&mut query).await {
+            Ok(received) => received,
+            Err(err) => {
+                log::warn!("Error receiving an mDNS query: {:?}", err);
+                continue;
+            }
+        };
+        // queries from other ports are from ordinary resolvers, which expect a direct reply
+        let legacy = endpoint.port != MDNS_PORT;
+        let Some(answer) = responder().respond(&query[..len], legacy, &mut response) else {
+            continue;
+        };
+        let destination = if legacy { endpoint } else { group };
+        if let Err(err) = socket.send_to(answer, destination).await {
+            log::warn!("Couldn't send an mDNS response: {:?}", err);
+        }
+    }
+}
@@ -22,7 +22,7 @@ use embassy_rp::peripherals::PIN_29;
 use embassy_rp::peripherals::PIO0;
 use embassy_rp::pio::Pio;
 use embassy_sync::waitqueue::WakerRegistration;
-use embassy_time::Instant;
+use embassy_time::{with_timeout, Duration, Instant};
 use smoltcp::iface::{Interface, SocketSet};
 use smoltcp::phy::Medium;
 use smoltcp::time::Instant as SmolInstant;
@@ -30,10 +30,11 @@ use smoltcp::wire::HardwareAddress;
 
 use embassy_rp::Peripherals;
 use heapless::Vec;
-use log::info;
+use log::{info, warn};
 use rand::Rng;
 use static_cell::make_static;
 
+use crate::config::{Credentials, NetworkConfig};
 use crate::Irqs;
 use crate::WEB_TASK_POOL_SIZE;
 
@@ -105,6 +106,58 @@ where
     }
 }
 
+/// How the device is connected
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NetworkMode {
+    /// joined an existing network, with an address from its DHCP server
+    Station,
+    /// running its own network, with a captive portal pointing at the web server
+    AccessPoint,
+}
+
+const JOIN_ATTEMPTS: usize = 3;
+/// how long to wait for the network's DHCP server before giving up on it
+const DHCP_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Join the network described by `credentials`, and wait for an address from its DHCP server
+async fn join_network(
+    control: &mut Control<'static>,
+    stack: &Stack<NetDriver<'static>>,
+    credentials: &Credentials,
+) -> bool {
+    info!("Joining {}...", credentials.ssid);
+    for attempt in 1..=JOIN_ATTEMPTS {
+        let joined = if credentials.passphrase.is_empty() {
+            control.join_open(&credentials.ssid).await
+        } else {
+            control
+                .join_wpa2(&credentials.ssid, &credentials.passphrase)
+                .await
+        };
+        match joined {
+            Ok(()) => {
+                return match with_timeout(DHCP_TIMEOUT, stack.wait_config_up()).await {
+                    Ok(()) => {
+                        info!("Joined {} as {:?}", credentials.ssid, stack.config_v4());
+                        true
+                    }
+                    Err(_) => {
+                        warn!("No address from the DHCP server on {}", credentials.ssid);
+                        false
+                    }
+                };
+            }
+            Err(err) => warn!(
+                "Couldn't join {} (attempt {attempt}): status {}",
+                credentials.ssid, err.status
+            ),
+        }
+    }
+    false
+}
+
+/// Bring up the WiFi chip and network stack. With station credentials the device joins that
+/// network; without them, or if joining fails, it starts its own access point.
 pub async fn set_up_network_stack(
     spawner: &Spawner,
     power_pin: PIN_23,
@@ -113,9 +166,9 @@ pub async fn set_up_network_stack(
     dio: PIN_24,
     clk: PIN_29,
     dma: DMA_CH0,
-    server_ip_address: embassy_net::Ipv4Address,
-    outside_ip_address: embassy_net::Ipv4Address,
-) -> (Control<'static>, &'static Stack<NetDriver<'static>>) {
+    config: &NetworkConfig,
+    unique_id: [u8; 8],
+) -> (Control<'static>, &'static Stack<NetDriver<'static>>, NetworkMode) {
     let fw = include_bytes!("../firmware/43439A0.bin");
     let clm = include_bytes!("../firmware/43439A0_clm.bin");
 
@@ -141,21 +194,49 @@ pub async fn set_up_network_stack(
         .await;
     let stack = &*make_static!(Stack::new(
         net_device,
-        embassy_net::Config::ipv4_static(embassy_net::StaticConfigV4 {
-            address: embassy_net::Ipv4Cidr::new(server_ip_address, 24),
-            gateway: Some(server_ip_address),
-            dns_servers: Vec::from_slice(&[server_ip_address]).unwrap(),
-        }),
+        embassy_net::Config::dhcpv4(Default::default()),
         make_static!(embassy_net::StackResources::<WEB_TASK_POOL_SIZE>::new()),
         embassy_rp::clocks::RoscRng.gen(),
     ));
 
     spawner.must_spawn(net_task(stack));
+
+    if let Some(credentials) = &config.station {
+        if join_network(&mut control, stack, credentials).await {
+            return (control, stack, NetworkMode::Station);
+        }
+        warn!(
+            "Couldn't join {}, falling back to an access point",
+            credentials.ssid
+        );
+        control.leave().await;
+    }
+
+    let [a, b, c, d] = config.address;
+    let server_ip_address = Ipv4Address::new(a, b, c, d);
+    stack.set_config_v4(ConfigV4::Static(embassy_net::StaticConfigV4 {
+        address: embassy_net::Ipv4Cidr::new(server_ip_address, config.dhcp.prefix_len()),
+        gateway: Some(server_ip_address),
+        dns_servers: Vec::from_slice(&[server_ip_address]).unwrap(),
+    }));
     stack.wait_config_up().await;
 
     info!("Starting access point...");
 
-    control.start_ap_open("pico", 5).await;
+    let access_point = &config.access_point;
+    let ssid = match stack.hardware_address() {
+        HardwareAddress::Ethernet(mac) => access_point.ssid_for(mac.0),
+        #[allow(unreachable_patterns)]
+        _ => access_point.ssid_for([0; 6]),
+    };
+    let passphrase = access_point.passphrase_for(unique_id);
+    if access_point.passphrase.is_empty() {
+        // the only way to find out the default, short of working it out from the flash chip
+        info!("{ssid} has the default passphrase {passphrase}");
+    }
+    control
+        .start_ap_wpa2(&ssid, &passphrase, access_point.channel)
+        .await;
 
-    (control, stack)
+    (control, stack, NetworkMode::AccessPoint)
 }
@@ -0,0 +1,168 @@
+use core::cell::{Cell, RefCell};
+
+use embassy_futures::select::{select, Either};
+use embassy_futures::yield_now;
+use embassy_rp::peripherals::PIO1;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::channel::{Channel, Receiver, Sender};
+use embassy_sync::pubsub::{PubSubChannel, Subscriber};
+use embassy_sync::signal::Signal;
+use embassy_time::{Instant, Timer};
+use matrix_state::scene::{Scene, SceneMessage};
+use matrix_state::{
+    FramePacer, FrameTime, MatrixSnapshot, MatrixState, MatrixStateMessage, Overrun, ServerMessage,
+    Snapshot, UpdateError, Updateable, VariantMessage,
+};
+
+use crate::web::WEB_TASK_POOL_SIZE;
+use crate::ws2812::Ws2812;
+use crate::MAX_LEDS;
+
+const MESSAGE_QUEUE_SIZE: usize = 8;
+const EVENT_QUEUE_SIZE: usize = 4;
+/// how many missed frames to run before giving up and dropping the rest
+const MAX_CATCH_UP_FRAMES: u32 = 4;
+
+pub type Message = MatrixStateMessage<VariantMessage<SceneMessage>>;
+pub type StateSnapshot = MatrixSnapshot<SceneMessage>;
+pub type Event = ServerMessage<StateSnapshot>;
+/// A message for the render task, with the reply to say whether it was applied in and the id
+/// of the request it's for
+pub type Request = (Message, &'static Reply, u32);
+type Reply = Signal<CriticalSectionRawMutex, (u32, Result<(), UpdateError>)>;
+pub type MessageSender = Sender<'static, CriticalSectionRawMutex, Request, MESSAGE_QUEUE_SIZE>;
+type MessageReceiver = Receiver<'static, CriticalSectionRawMutex, Request, MESSAGE_QUEUE_SIZE>;
+pub type Display = Ws2812<'static, PIO1, 0, MAX_LEDS>;
+
+/// Messages for the render task, sent from the web server with `apply`
+pub static MESSAGES: Channel<CriticalSectionRawMutex, Request, MESSAGE_QUEUE_SIZE> = Channel::new();
+
+/// A reply for each web task, as each of them waits for one message at a time
+static REPLIES: [Reply; WEB_TASK_POOL_SIZE] = [const { Signal::new() }; WEB_TASK_POOL_SIZE];
+static REPLIES_IN_USE: Mutex<CriticalSectionRawMutex, Cell<[bool; WEB_TASK_POOL_SIZE]>> =
+    Mutex::new(Cell::new([false; WEB_TASK_POOL_SIZE]));
+/// The id for the next request, so a reply to a request that stopped waiting isn't taken for
+/// the reply to the next one
+static NEXT_REQUEST: Mutex<CriticalSectionRawMutex, Cell<u32>> = Mutex::new(Cell::new(0));
+
+/// What the render task tells the WebSocket clients, with room for a client on every web task
+pub static EVENTS: PubSubChannel<
+    CriticalSectionRawMutex,
+    Event,
+    EVENT_QUEUE_SIZE,
+    WEB_TASK_POOL_SIZE,
+    0,
+> = PubSubChannel::new();
+pub type EventSubscriber =
+    Subscriber<'static, CriticalSectionRawMutex, Event, EVENT_QUEUE_SIZE, WEB_TASK_POOL_SIZE, 0>;
+
+/// What the state was showing after the last message, for clients that have just connected
+static SNAPSHOT: Mutex<CriticalSectionRawMutex, RefCell<Option<StateSnapshot>>> =
+    Mutex::new(RefCell::new(None));
+
+/// One of `REPLIES`, which is freed again when this is dropped
+struct ClaimedReply(usize);
+
+impl ClaimedReply {
+    fn claim() -> Option<Self> {
+        REPLIES_IN_USE.lock(|in_use| {
+            let mut claimed = in_use.get();
+            let index = claimed.iter().position(|&used| !used)?;
+            claimed[index] = true;
+            in_use.set(claimed);
+            Some(ClaimedReply(index))
+        })
+    }
+}
+
+impl Drop for ClaimedReply {
+    fn drop(&mut self) {
+        REPLIES_IN_USE.lock(|in_use| {
+            let mut claimed = in_use.get();
+            claimed[self.0] = false;
+            in_use.set(claimed);
+        });
+    }
+}
+
+/// Send a message to the render task, and wait to hear whether it could be applied
+pub async fn apply(messages: &MessageSender, message: Message) -> Result<(), UpdateError> {
+    let claimed = loop {
+        match ClaimedReply::claim() {
+            Some(claimed) => break claimed,
+            // only while another web task is dropping its reply
+            None => yield_now().await,
+        }
+    };
+    let reply = &REPLIES[claimed.0];
+    let request = NEXT_REQUEST.lock(|next| {
+        let id = next.get();
+        next.set(id.wrapping_add(1));
+        id
+    });
+    reply.reset();
+    messages.send((message, reply, request)).await;
+    loop {
+        match reply.wait().await {
+            (id, result) if id == request => return result,
+            _ => continue,
+        }
+    }
+}
+
+/// What the matrix is showing, once the render task has started
+pub fn snapshot() -> Option<StateSnapshot> {
+    SNAPSHOT.lock(|snapshot| snapshot.borrow().clone())
+}
+
+fn publish_snapshot(state: &MatrixState<Scene>) {
+    let snapshot = state.snapshot();
+    SNAPSHOT.lock(|latest| latest.replace(Some(snapshot.clone())));
+    // the oldest events are dropped if a client isn't keeping up
+    EVENTS
+        .immediate_publisher()
+        .publish_immediate(ServerMessage::State(snapshot));
+}
+
+/// Owns the matrix state and the display. Messages are applied and shown as soon as they
+/// arrive, and the state is ticked every `frame_time` milliseconds in between. The clients are
+/// sent a snapshot of the state after every message, and whoever sent the message is told
+/// whether it could be applied.
+#[embassy_executor::task]
+pub async fn render_task(
+    mut display: Display,
+    mut state: MatrixState<Scene>,
+    messages: MessageReceiver,
+) -> ! {
+    let mut pacer = FramePacer::new(
+        Instant::now().as_millis(),
+        Overrun::CatchUp {
+            max_frames: MAX_CATCH_UP_FRAMES,
+        },
+    );
+    publish_snapshot(&state);
+    loop {
+        let next_frame = Instant::from_millis(pacer.next_frame());
+        match select(messages.receive(), Timer::at(next_frame)).await {
+            Either::First((message, reply, request)) => {
+                let result = state.try_update(Some(message), &mut display);
+                if let Err(err) = &result {
+                    log::warn!("Couldn't apply a message: {}", err);
+                }
+                reply.signal((request, result));
+                publish_snapshot(&state);
+            }
+            Either::Second(()) => {
+                let updates = pacer.tick(Instant::now().as_millis(), state.frame_time());
+                if updates > 1 {
+                    log::debug!("Render loop is {} frames behind", updates - 1);
+                }
+                for _ in 0..updates {
+                    state.update(None, &mut display);
+                }
+            }
+        }
+        display.push().await;
+    }
+}
@@ -1,16 +1,43 @@
+use core::future::pending;
+
 use cyw43::NetDriver;
 use embassy_executor::Spawner;
+use embassy_futures::select::{select, Either};
 use embassy_net::Stack;
-use embassy_time::Duration;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_time::{Duration, Instant};
+use heapless::String;
+use matrix_state::api::{self, Brightness, Frame, Info};
+use matrix_state::scene::{Scene, SceneMessage, MAX_SERVER_MESSAGE_LEN};
+use matrix_state::{MatrixStateMessage, ServerMessage, UpdateError, VariantMessage};
 use picoserve::{
+    extract::{Form, FromRequest},
+    io::{Read, Write},
+    request::Request,
     response::{
-        self, status::TEMPORARY_REDIRECT, IntoResponse, Json, Redirect, Response, StatusCode,
+        status::{self, TEMPORARY_REDIRECT},
+        ws::{Message, ReadMessageError, SocketRx, SocketTx, WebSocketCallback, WebSocketUpgrade},
+        IntoResponse, Json, Redirect, Response, ResponseWriter, StatusCode,
+    },
+    routing::{
+        get, get_service, parse_path_segment, post, post_service, MethodHandler, OnePathParameter,
+        PathRouter, RequestHandler,
     },
-    routing::{get, Layer, PathRouter},
-    KeepAlive, ResponseSent, Router,
+    ResponseSent, Router,
 };
+use serde::Deserialize;
 use static_cell::make_static;
 
+use crate::assets::{FRONTEND_JS, FRONTEND_WASM, INDEX_HTML};
+use crate::captive_portal::{self, CaptivePortal};
+use crate::config::{AccessPointConfig, Config, ConfigStore, Credentials, CONFIG_STORE};
+use crate::dhcp_server;
+use crate::json::JsonBody;
+use crate::network::NetworkMode;
+use crate::render::{self, Event, EventSubscriber, MessageSender, EVENTS, MESSAGES};
+use crate::{FIRMWARE_VERSION, RESTART};
+
 pub const WEB_TASK_POOL_SIZE: usize = 3;
 
 struct EmbassyTimer;
@@ -60,6 +87,7 @@ async fn web_task(
             app,
             EmbassyTimer,
             config,
+            // holds a whole request, so POST /api/frame bodies bigger than this are sent in parts
             &mut [0; 2048],
             socket_rx,
             socket_tx,
@@ -77,52 +105,426 @@ async fn web_task(
     }
 }
 
-struct S;
+/// The close reason when a message for a WebSocket client doesn't fit `MAX_SERVER_MESSAGE_LEN`
+const ENCODING_FAILED: (u16, &str) = (1011, "Couldn't encode a message");
+
+/// Receives postcard encoded `MatrixStateMessage`s and passes them on to the render task, and
+/// sends the client a snapshot of the state followed by the `ServerMessage`s the render task
+/// publishes, along with an error for each of its messages that couldn't be applied
+struct ControlSocket {
+    messages: MessageSender,
+    /// `None` when every subscriber slot is taken, and the client isn't sent anything
+    events: Option<EventSubscriber>,
+}
+
+impl WebSocketCallback for ControlSocket {
+    async fn run<R: Read, W: Write<Error = R::Error>>(
+        self,
+        mut rx: SocketRx<R>,
+        tx: SocketTx<W>,
+    ) -> Result<(), W::Error> {
+        let ControlSocket { messages, events } = self;
+        // both the pongs and the events are sent while waiting for the next message
+        let tx = Mutex::<NoopRawMutex, _>::new(tx);
+        let receive = async {
+            let mut buffer = [0; 512];
+            let close_reason = loop {
+                match rx.next_message(&mut buffer).await {
+                    Ok(Message::Binary(data)) => {
+                        let Ok(message) = postcard::from_bytes::<render::Message>(data) else {
+                            log::warn!("Couldn't decode a matrix message");
+                            continue;
+                        };
+                        let Err(err) = render::apply(&messages, message).await else {
+                            continue;
+                        };
+                        let mut encoded = [0; MAX_SERVER_MESSAGE_LEN];
+                        let Ok(data) = postcard::to_slice(&Event::Error(err), &mut encoded) else {
+                            log::error!("Couldn't encode an error for a websocket client");
+                            break Some(ENCODING_FAILED);
+                        };
+                        tx.lock().await.send_binary(data).await
+                    }
+                    Ok(Message::Text(_)) => {
+                        log::warn!("Ignoring a text message, matrix messages are binary");
+                        continue;
+                    }
+                    Ok(Message::Ping(data)) => tx.lock().await.send_pong(data).await,
+                    Ok(Message::Pong(_)) => continue,
+                    Ok(Message::Close(reason)) => {
+                        log::info!("Websocket closed: {:?}", reason);
+                        break None;
+                    }
+                    Err(err) => {
+                        log::error!("Websocket error: {:?}", err);
+                        let code = match err {
+                            ReadMessageError::Io(err) => return Err(err),
+                            ReadMessageError::ReadFrameError(_)
+                            | ReadMessageError::MessageStartsWithContinuation
+                            | ReadMessageError::UnexpectedMessageStart => 1002,
+                            ReadMessageError::ReservedOpcode(_) => 1003,
+                            ReadMessageError::TextIsNotUtf8 => 1007,
+                        };
+                        break Some((code, "Websocket Error"));
+                    }
+                }?;
+            };
+            Ok::<_, W::Error>(close_reason)
+        };
+        let forward = async {
+            let Some(mut events) = events else {
+                return pending().await;
+            };
+            let mut buffer = [0; MAX_SERVER_MESSAGE_LEN];
+            // the client starts with what's showing, then hears about every change
+            let mut latest = render::snapshot().map(ServerMessage::State);
+            loop {
+                let event = match latest.take() {
+                    Some(event) => event,
+                    None => events.next_message_pure().await,
+                };
+                let Ok(data) = postcard::to_slice(&event, &mut buffer) else {
+                    log::error!("Couldn't encode a message for a websocket client");
+                    return Ok::<_, W::Error>(Some(ENCODING_FAILED));
+                };
+                tx.lock().await.send_binary(data).await?;
+            }
+        };
+        // the client is disconnected rather than quietly missing a message
+        let close_reason = match select(receive, forward).await {
+            Either::First(close_reason) | Either::Second(close_reason) => close_reason?,
+        };
+        tx.into_inner().close(close_reason).await
+    }
+}
+
+/// Apply `change` to the stored configuration and save it, then restart so it takes effect
+async fn change_config(change: impl FnOnce(&mut Config)) -> (StatusCode, &'static str) {
+    let mut store = CONFIG_STORE.lock().await;
+    let Some(store) = store.as_mut() else {
+        return (
+            status::SERVICE_UNAVAILABLE,
+            "The configuration store isn't ready yet\n",
+        );
+    };
+    let mut config = store.config().clone();
+    change(&mut config);
+    if let Err(problem) = config.validate() {
+        return (status::BAD_REQUEST, problem);
+    }
+    if let Err(err) = store.save(config) {
+        log::error!("Couldn't save the configuration: {:?}", err);
+        return (
+            status::INTERNAL_SERVER_ERROR,
+            "Couldn't save the configuration\n",
+        );
+    }
+
+    RESTART.signal(());
+    (status::OK, "Saved, restarting...\n")
+}
+
+/// The stored configuration, without the passphrases
+async fn get_config() -> impl IntoResponse {
+    let store = CONFIG_STORE.lock().await;
+    let mut config = store.as_ref().map(|store| store.config().clone());
+    if let Some(network) = config.as_mut().map(|c| &mut c.network) {
+        if let Some(station) = network.station.as_mut() {
+            station.passphrase.clear();
+        }
+        network.access_point.passphrase.clear();
+    }
+    Json(config)
+}
+
+/// Replace the configuration. As `get_config` leaves out the passphrases, an empty one for the
+/// same SSID keeps the stored passphrase.
+async fn set_config(JsonBody(mut config): JsonBody<Config>) -> impl IntoResponse {
+    change_config(|stored| {
+        if let (Some(new), Some(old)) = (&mut config.network.station, &stored.network.station) {
+            if new.ssid == old.ssid && new.passphrase.is_empty() {
+                new.passphrase = old.passphrase.clone();
+            }
+        }
+        let (new, old) = (
+            &mut config.network.access_point,
+            &stored.network.access_point,
+        );
+        if new.ssid == old.ssid && new.passphrase.is_empty() {
+            new.passphrase = old.passphrase.clone();
+        }
+        *stored = config;
+    })
+    .await
+}
+
+/// Change the access point's settings. An empty SSID or passphrase uses the per-device default.
+async fn set_access_point(Form(access_point): Form<AccessPointConfig>) -> impl IntoResponse {
+    change_config(|config| config.network.access_point = access_point).await
+}
+
+/// Save the credentials for a network to join, or forget them if the SSID is empty
+async fn set_wifi_credentials(Form(credentials): Form<Credentials>) -> impl IntoResponse {
+    change_config(|config| {
+        config.network.station = (!credentials.ssid.is_empty()).then_some(credentials);
+    })
+    .await
+}
+
+async fn factory_reset() -> impl IntoResponse {
+    let mut store = CONFIG_STORE.lock().await;
+    match store.as_mut().map(ConfigStore::factory_reset) {
+        Some(Ok(())) => {
+            RESTART.signal(());
+            (status::OK, "Reset, restarting...\n")
+        }
+        Some(Err(err)) => {
+            log::error!("Couldn't reset the configuration: {:?}", err);
+            (
+                status::INTERNAL_SERVER_ERROR,
+                "Couldn't reset the configuration\n",
+            )
+        }
+        None => (
+            status::SERVICE_UNAVAILABLE,
+            "The configuration store isn't ready yet\n",
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+struct CaptivePortalForm {
+    enabled: bool,
+}
 
-impl<State, PathParameters> Layer<State, PathParameters> for S {
-    type NextState = State;
+/// Switch the captive portal off once the UI has been reached, so phones stop showing it in a
+/// popup and stay connected, or back on again
+async fn set_captive_portal(Form(form): Form<CaptivePortalForm>) -> impl IntoResponse {
+    captive_portal::set_enabled(form.enabled);
+    if form.enabled {
+        "Captive portal enabled\n"
+    } else {
+        "Captive portal disabled\n"
+    }
+}
 
-    type NextPathParameters = PathParameters;
+/// picoserve only routes GET and POST, so this sends PUT requests to `put` and everything else
+/// on to `others`
+struct WithPut<M, P> {
+    others: M,
+    put: P,
+}
 
-    async fn call_layer<
-        NextLayer: picoserve::routing::Next<Self::NextState, Self::NextPathParameters>,
-        W: response::ResponseWriter,
-    >(
+impl<State, PathParameters, M, P> MethodHandler<State, PathParameters> for WithPut<M, P>
+where
+    M: MethodHandler<State, PathParameters>,
+    P: RequestHandler<State, PathParameters>,
+{
+    async fn call_method_handler<W: ResponseWriter>(
         &self,
-        next: NextLayer,
         state: &State,
         path_parameters: PathParameters,
-        request: picoserve::request::Request<'_>,
+        request: Request<'_>,
         response_writer: W,
     ) -> Result<ResponseSent, W::Error> {
-        if request
-            .headers()
-            .get("Host")
-            .and_then(|h| h.split_once(".").map(|(prefix, _)| prefix == "picohttp"))
-            .unwrap_or(false)
-        {
-            response_writer
-                .write_response(Json("hi").into_response().with_header("Connection", KeepAlive::Close))
+        if request.method() == "PUT" {
+            self.put
+                .call_request_handler(state, path_parameters, request, response_writer)
                 .await
         } else {
-            let location = "http://picohttp.piconet.local";
-            (
-                StatusCode::new(302),
-                ("Location", location),
-                ("Connection", KeepAlive::Close),
-                format_args!("{}\n", location),
+            self.others
+                .call_method_handler(state, path_parameters, request, response_writer)
+                .await
+        }
+    }
+}
+
+/// `PUT /api/brightness`
+struct SetBrightness;
+
+impl<State, PathParameters> RequestHandler<State, PathParameters> for SetBrightness {
+    async fn call_request_handler<W: ResponseWriter>(
+        &self,
+        state: &State,
+        _path_parameters: PathParameters,
+        request: Request<'_>,
+        response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+        match JsonBody::<Brightness>::from_request(state, &request).await {
+            Ok(JsonBody(Brightness { brightness })) => {
+                let message = MatrixStateMessage::UpdateBrightness(brightness);
+                let result = render::apply(&MESSAGES.sender(), message).await;
+                reply(result, "Brightness set\n", response_writer).await
+            }
+            Err(rejection) => rejection.write_to(response_writer).await,
+        }
+    }
+}
+
+/// `POST /api/scene/{name}`, with the scene's message as the body, like `[255, 0, 0]` for
+/// `Solid`. This is a handler rather than a function so the body can be read with the name.
+struct SelectScene;
+
+impl<State> RequestHandler<State, OnePathParameter<String<32>>> for SelectScene {
+    async fn call_request_handler<W: ResponseWriter>(
+        &self,
+        _state: &State,
+        OnePathParameter(name): OnePathParameter<String<32>>,
+        request: Request<'_>,
+        response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+        let mut body = serde_json_core::de::Deserializer::new(request.body());
+        let message = api::from_variant::<SceneMessage, _>(&name, &mut body)
+            .and_then(|message| body.end().map(|_| message));
+        match message {
+            Ok(message) => {
+                let message = MatrixStateMessage::UpdateImage(VariantMessage::Switch(message));
+                let result = render::apply(&MESSAGES.sender(), message).await;
+                reply(result, "Scene selected\n", response_writer).await
+            }
+            Err(_) => {
+                (
+                    status::BAD_REQUEST,
+                    "Unknown scene, or bad parameters for it\n",
+                )
+                    .write_to(response_writer)
+                    .await
+            }
+        }
+    }
+}
+
+/// `POST /api/frame`, drawing the pixels over the scene until it's next sent a message
+struct DrawFrame;
+
+impl<State, PathParameters> RequestHandler<State, PathParameters> for DrawFrame {
+    async fn call_request_handler<W: ResponseWriter>(
+        &self,
+        _state: &State,
+        _path_parameters: PathParameters,
+        request: Request<'_>,
+        response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+        let frame = serde_json_core::from_slice::<Frame>(request.body());
+        let Some(runs) = frame.ok().and_then(|(frame, _)| frame.runs()) else {
+            return (
+                status::BAD_REQUEST,
+                "Pixels must be rrggbb hex colours, one after another\n",
             )
+                .write_to(response_writer)
+                .await;
+        };
+        let messages = MESSAGES.sender();
+        let mut result = Ok(());
+        for run in runs {
+            result = render::apply(&messages, MatrixStateMessage::DrawPixels(run)).await;
+            if result.is_err() {
+                break;
+            }
+        }
+        reply(result, "Frame drawn\n", response_writer).await
+    }
+}
+
+/// Reply to a request with `done`, or with why its message couldn't be applied
+async fn reply<W: ResponseWriter>(
+    result: Result<(), UpdateError>,
+    done: &'static str,
+    response_writer: W,
+) -> Result<ResponseSent, W::Error> {
+    match result {
+        Ok(()) => done.write_to(response_writer).await,
+        Err(err) => {
+            (status::BAD_REQUEST, format_args!("{}\n", err))
                 .write_to(response_writer)
                 .await
         }
     }
 }
-fn make_app() -> picoserve::Router<AppRouter> {
-    Router::new().layer(S)
+
+/// RAM not used by statics or the stack, between the end of `.bss` and the stack pointer, as
+/// there's no heap
+fn free_memory() -> usize {
+    extern "C" {
+        // from cortex-m-rt's linker script
+        static __sheap: u8;
+    }
+    let end_of_statics = unsafe { core::ptr::addr_of!(__sheap) } as usize;
+    (cortex_m::register::msp::read() as usize).saturating_sub(end_of_statics)
+}
+
+fn make_app(mode: NetworkMode, config: &'static Config) -> picoserve::Router<AppRouter> {
+    let network = &config.network;
+    let layout = config.matrix.layout;
+    Router::new()
+        .route("/", get_service(INDEX_HTML))
+        .route("/frontend.js", get_service(FRONTEND_JS))
+        .route("/frontend_bg.wasm", get_service(FRONTEND_WASM))
+        .route("/api/wifi", post(set_wifi_credentials))
+        .route("/api/access-point", post(set_access_point))
+        .route("/api/config", get(get_config).post(set_config))
+        .route("/api/config/reset", post(factory_reset))
+        .route(
+            "/api/brightness",
+            WithPut {
+                others: get(|| async {
+                    Json(Brightness {
+                        brightness: render::snapshot().map_or(0.0, |state| state.brightness),
+                    })
+                }),
+                put: SetBrightness,
+            },
+        )
+        .route("/api/scenes", get(|| async { Json(Scene::VARIANTS) }))
+        .route("/api/state", get(|| async { Json(render::snapshot()) }))
+        .route(
+            ("/api/scene", parse_path_segment::<String<32>>()),
+            post_service(SelectScene),
+        )
+        .route(
+            "/api/info",
+            get(move || async move {
+                let (rows, cols) = layout.size();
+                Json(Info {
+                    rows,
+                    cols,
+                    layout,
+                    version: FIRMWARE_VERSION,
+                    uptime: Instant::now().as_secs(),
+                    free_memory: Some(free_memory()),
+                })
+            }),
+        )
+        .route("/api/frame", post_service(DrawFrame))
+        .route(
+            "/api/leases",
+            get(|| async { Json(dhcp_server::leases().await) }),
+        )
+        .route(
+            "/api/captive-portal",
+            get(|| async { Json(captive_portal::is_enabled()) }).post(set_captive_portal),
+        )
+        .route(
+            "/ws/ws",
+            get(|upgrade: WebSocketUpgrade| {
+                upgrade.on_upgrade(ControlSocket {
+                    messages: MESSAGES.sender(),
+                    events: EVENTS.subscriber().ok(),
+                })
+            }),
+        )
+        .layer(CaptivePortal::new(
+            (mode == NetworkMode::AccessPoint).then_some(network),
+        ))
 }
 
-pub async fn start_server(spawner: &Spawner, stack: &'static Stack<NetDriver<'static>>) {
-    let app = make_static!(make_app());
+pub async fn start_server(
+    spawner: &Spawner,
+    stack: &'static Stack<NetDriver<'static>>,
+    mode: NetworkMode,
+    config: &'static Config,
+) {
+    let app = make_static!(make_app(mode, config));
 
     let config = make_static!(picoserve::Config::new(picoserve::Timeouts {
         start_read_request: Some(Duration::from_secs(5)),
@@ -0,0 +1,136 @@
+use embassy_rp::clocks;
+use embassy_rp::dma::{AnyChannel, Channel};
+use embassy_rp::pio::{
+    Common, Config, FifoJoin, Instance, PioPin, ShiftConfig, ShiftDirection, StateMachine,
+};
+use embassy_rp::{into_ref, Peripheral, PeripheralRef};
+use embassy_time::Timer;
+use fixed::types::U24F8;
+use matrix_state::{ColourCorrection, Layout, MatrixDisplay, RGB8};
+
+/// The line has to be held low for at least this long for the LEDs to latch the frame
+const RESET_TIME_US: u64 = 300;
+/// The time taken to clock out the 24 bits of one LED at 800kHz
+const LED_TIME_US: u64 = 30;
+
+/// A WS2812B strip of up to `N` LEDs driven by PIO state machine `S`, with the pixels fed in by
+/// DMA. Pixels are addressed in logical coordinates and placed on the strip by the `Layout`,
+/// which sets how many of the `N` LEDs are actually used.
+/// The frame is stored as `RGB8`, whose (padding, b, r, g) layout reads as the word
+/// `0xGGRRBB00`, so the state machine can shift the top 24 bits straight out.
+pub struct Ws2812<'d, P: Instance, const S: usize, const N: usize> {
+    dma: PeripheralRef<'d, AnyChannel>,
+    sm: StateMachine<'d, P, S>,
+    layout: Layout,
+    frame: [RGB8; N],
+    output: [RGB8; N],
+    correction: ColourCorrection,
+}
+
+impl<'d, P: Instance, const S: usize, const N: usize> Ws2812<'d, P, S, N> {
+    pub fn new(
+        pio: &mut Common<'d, P>,
+        mut sm: StateMachine<'d, P, S>,
+        dma: impl Peripheral<P = impl Channel> + 'd,
+        pin: impl PioPin,
+        layout: Layout,
+    ) -> Self {
+        assert!(layout.len() <= N, "the layout has more LEDs than the strip");
+        into_ref!(dma);
+
+        let side_set = pio::SideSet::new(false, 1, false);
+        let mut a: pio::Assembler<32> = pio::Assembler::new_with_side_set(side_set);
+
+        const T1: u8 = 2; // start bit
+        const T2: u8 = 5; // data bit
+        const T3: u8 = 3; // stop bit
+        const CYCLES_PER_BIT: u32 = (T1 + T2 + T3) as u32;
+
+        let mut wrap_target = a.label();
+        let mut wrap_source = a.label();
+        let mut do_zero = a.label();
+        a.set_with_side_set(pio::SetDestination::PINDIRS, 1, 0);
+        a.bind(&mut wrap_target);
+        // stop bit
+        a.out_with_delay_and_side_set(pio::OutDestination::X, 1, T3 - 1, 0);
+        // start bit
+        a.jmp_with_delay_and_side_set(pio::JmpCondition::XIsZero, &mut do_zero, T1 - 1, 1);
+        // data bit = 1
+        a.jmp_with_delay_and_side_set(pio::JmpCondition::Always, &mut wrap_target, T2 - 1, 1);
+        a.bind(&mut do_zero);
+        // data bit = 0
+        a.nop_with_delay_and_side_set(T2 - 1, 0);
+        a.bind(&mut wrap_source);
+
+        let program = a.assemble_with_wrap(wrap_source, wrap_target);
+
+        let mut cfg = Config::default();
+        let out_pin = pio.make_pio_pin(pin);
+        cfg.set_out_pins(&[&out_pin]);
+        cfg.set_set_pins(&[&out_pin]);
+        cfg.use_program(&pio.load_program(&program), &[&out_pin]);
+
+        // measured in kHz to avoid overflows
+        let clock_freq = U24F8::from_num(clocks::clk_sys_freq() / 1000);
+        let ws2812_freq = U24F8::from_num(800);
+        cfg.clock_divider = clock_freq / (ws2812_freq * CYCLES_PER_BIT);
+
+        cfg.fifo_join = FifoJoin::TxOnly;
+        cfg.shift_out = ShiftConfig {
+            auto_fill: true,
+            threshold: 24,
+            direction: ShiftDirection::Left,
+        };
+
+        sm.set_config(&cfg);
+        sm.set_enable(true);
+
+        Self {
+            dma: dma.map_into(),
+            sm,
+            layout,
+            frame: [RGB8::default(); N],
+            output: [RGB8::default(); N],
+            correction: ColourCorrection::default(),
+        }
+    }
+
+    /// Send the current frame to the LEDs, returning once the reset time has passed and the
+    /// frame has been latched
+    pub async fn push(&mut self) {
+        let len = self.layout.len();
+        for (out, pixel) in self.output[..len].iter_mut().zip(self.frame.iter()) {
+            *out = self.correction.apply(*pixel);
+            out.padding = 0;
+        }
+
+        // SAFETY: RGB8 is repr(C, align(4)) and 4 bytes long, so it has the layout of a u32
+        let words =
+            unsafe { core::slice::from_raw_parts(self.output.as_ptr() as *const u32, len) };
+        self.sm.tx().dma_push(self.dma.reborrow(), words).await;
+
+        // the DMA is done once the last word is in the FIFO, so wait for it to drain
+        while self.sm.tx().level() > 0 {
+            Timer::after_micros(LED_TIME_US).await;
+        }
+        Timer::after_micros(LED_TIME_US + RESET_TIME_US).await;
+    }
+}
+
+impl<'d, P: Instance, const S: usize, const N: usize> MatrixDisplay for Ws2812<'d, P, S, N> {
+    fn get_mut(&mut self, row: usize, col: usize) -> Option<&mut RGB8> {
+        self.layout.index(row, col).map(|i| &mut self.frame[i])
+    }
+
+    fn get(&self, row: usize, col: usize) -> Option<&RGB8> {
+        self.layout.index(row, col).map(|i| &self.frame[i])
+    }
+
+    fn size(&self) -> (usize, usize) {
+        self.layout.size()
+    }
+
+    fn set_correction(&mut self, correction: ColourCorrection) {
+        self.correction = correction;
+    }
+}
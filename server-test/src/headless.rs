@@ -0,0 +1,154 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+};
+
+use clap::ValueEnum;
+use image::{
+    codecs::gif::{GifEncoder, Repeat},
+    Delay, Frame, Rgba, RgbaImage,
+};
+use matrix_state::{FrameBuffer, FrameTime, MatrixDisplay, Updateable, RGB8};
+
+use crate::transform_colour;
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum OutputFormat {
+    /// a directory of numbered PNG files
+    Png,
+    /// a single animated GIF
+    Gif,
+    /// the corrected r, g, b bytes sent to the LEDs, one frame after another in row-major order
+    Raw,
+}
+
+pub struct HeadlessOptions {
+    pub output: PathBuf,
+    pub format: OutputFormat,
+    /// stop after this many frames
+    pub frames: Option<u64>,
+    /// stop once this many milliseconds of animation have been rendered
+    pub duration: Option<u64>,
+    /// the size in pixels of each LED in the PNG and GIF outputs
+    pub scale: u32,
+}
+
+impl HeadlessOptions {
+    fn done(&self, frames: u64, elapsed: u64) -> bool {
+        match (self.frames, self.duration) {
+            (Some(limit), _) => frames >= limit,
+            (None, Some(limit)) => elapsed >= limit,
+            (None, None) => frames >= 1,
+        }
+    }
+}
+
+/// Where the frames are written to
+enum Sink {
+    Png { directory: PathBuf },
+    Gif(Box<GifEncoder<BufWriter<File>>>),
+    Raw(BufWriter<File>),
+}
+
+impl Sink {
+    fn new(options: &HeadlessOptions) -> io::Result<Self> {
+        Ok(match options.format {
+            OutputFormat::Png => {
+                std::fs::create_dir_all(&options.output)?;
+                Sink::Png {
+                    directory: options.output.clone(),
+                }
+            }
+            OutputFormat::Gif => {
+                let mut encoder = GifEncoder::new(BufWriter::new(File::create(&options.output)?));
+                encoder.set_repeat(Repeat::Infinite).map_err(io::Error::other)?;
+                Sink::Gif(Box::new(encoder))
+            }
+            OutputFormat::Raw => Sink::Raw(BufWriter::new(File::create(&options.output)?)),
+        })
+    }
+
+    fn write<const ROWS: usize, const COLS: usize>(
+        &mut self,
+        index: u64,
+        frame_time: u64,
+        display: &FrameBuffer<ROWS, COLS>,
+        scale: u32,
+    ) -> io::Result<()> {
+        match self {
+            Sink::Png { directory } => to_image(display, scale)
+                .save(directory.join(format!("frame_{index:05}.png")))
+                .map_err(io::Error::other),
+            Sink::Gif(encoder) => encoder
+                .encode_frame(Frame::from_parts(
+                    to_image(display, scale),
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(frame_time as u32, 1),
+                ))
+                .map_err(io::Error::other),
+            Sink::Raw(writer) => {
+                for (row, col) in FrameBuffer::<ROWS, COLS>::iter_coords_helper(ROWS, COLS) {
+                    let RGB8 { r, g, b, .. } = display.corrected(row, col).unwrap();
+                    writer.write_all(&[r, g, b])?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            // the GIF trailer is written when the encoder is dropped
+            Sink::Png { .. } | Sink::Gif(_) => Ok(()),
+            Sink::Raw(mut writer) => writer.flush(),
+        }
+    }
+}
+
+/// Render the display as the simulator window would show it
+fn to_image<const ROWS: usize, const COLS: usize>(
+    display: &FrameBuffer<ROWS, COLS>,
+    scale: u32,
+) -> RgbaImage {
+    let scale = scale.max(1);
+    RgbaImage::from_fn(COLS as u32 * scale, ROWS as u32 * scale, |x, y| {
+        let colour = display
+            .corrected((y / scale) as usize, (x / scale) as usize)
+            .unwrap();
+        Rgba(transform_colour(colour).map(|c| (c * 255.0).round() as u8))
+    })
+}
+
+/// Run `state` against an in-memory display, writing out a frame every tick. Time is simulated,
+/// so each frame is rendered as fast as possible and the output doesn't depend on the machine.
+/// The first frame is drawn with `message`, which is an error if the state rejects it.
+pub fn run<S, const ROWS: usize, const COLS: usize>(
+    mut state: S,
+    mut message: Option<S::Message>,
+    options: &HeadlessOptions,
+) -> io::Result<()>
+where
+    S: Updateable + FrameTime,
+{
+    let mut display = FrameBuffer::<ROWS, COLS>::new();
+    let mut sink = Sink::new(options)?;
+    let (mut frames, mut elapsed) = (0, 0);
+
+    while !options.done(frames, elapsed) {
+        state
+            .try_update(message.take(), &mut display)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+        let frame_time = state.frame_time();
+        sink.write(frames, frame_time, &display, options.scale)?;
+        frames += 1;
+        elapsed += frame_time.max(1);
+    }
+
+    log::info!(
+        "Rendered {frames} frames ({elapsed} ms) to {}",
+        options.output.display()
+    );
+    sink.finish()
+}
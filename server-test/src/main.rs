@@ -1,35 +1,55 @@
-#![feature(adt_const_params)]
-
-use matrix_state::{MatrixDisplay, RGB8};
+use matrix_state::{
+    api::{self, Brightness, Frame, Info},
+    scene::{Scene, SceneMessage, Solid},
+    ColourCorrection, FramePacer, FrameTime, Layout, MatrixDisplay, MatrixSnapshot, MatrixState,
+    MatrixStateMessage, Overrun, ServerMessage, Snapshot, UpdateError, Updateable, VariantMessage,
+    RGB8,
+};
 use std::{
-    marker::PhantomData,
+    collections::HashMap,
     net::{IpAddr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
     str::FromStr,
-    thread,
-    time::Duration,
+    time::Instant,
 };
 
 use axum::{
-    body::Body,
-    extract::{ws::WebSocket, ConnectInfo, WebSocketUpgrade},
-    http::{Response, StatusCode},
+    body::Bytes,
+    extract::{
+        ws::{Message as WsMessage, WebSocket},
+        ConnectInfo, Path, State, WebSocketUpgrade,
+    },
+    http::StatusCode,
     response::IntoResponse,
-    routing::get,
-    Router,
+    routing::{get, post},
+    Form, Json, Router,
 };
 use clap::Parser;
-use macroquad::prelude::*;
-use macroquad::ui::widgets::Window;
-use piston_window::ellipse::circle;
+use headless::{HeadlessOptions, OutputFormat};
 use piston_window::*;
+use tokio::sync::mpsc::Sender;
 use tokio::{
     runtime::{Builder, Runtime},
-    sync::mpsc::{self, Receiver},
+    sync::{
+        mpsc::{self, error::TryRecvError, Receiver},
+        oneshot, watch,
+    },
 };
-use tokio::{sync::mpsc::Sender, time::sleep};
 use tower::{ServiceBuilder, ServiceExt};
 use tower_http::{services::ServeDir, trace::TraceLayer};
 
+mod headless;
+
+type Message = MatrixStateMessage<VariantMessage<SceneMessage>>;
+type StateSnapshot = MatrixSnapshot<SceneMessage>;
+/// A message for the display, and where to say whether it could be applied
+type Request<M> = (M, oneshot::Sender<Result<(), UpdateError>>);
+
+/// The size of the simulated matrix
+const LAYOUT: Layout = Layout::new(16, 16);
+const ROWS: usize = LAYOUT.size().0;
+const COLS: usize = LAYOUT.size().1;
+
 #[derive(Parser, Debug)]
 #[clap(
     name = "server",
@@ -44,38 +64,64 @@ struct Opt {
     log_level: String,
     #[clap(long = "static-dir", default_value = "./dist")]
     static_dir: String,
+    /// Render frames to files instead of opening a window
+    #[clap(long = "headless")]
+    headless: bool,
+    /// The file (or directory, for PNGs) to write headless frames to
+    #[clap(short = 'o', long = "output", default_value = "frames")]
+    output: PathBuf,
+    #[clap(long = "format", value_enum, default_value = "png")]
+    format: OutputFormat,
+    /// The number of frames to render in headless mode
+    #[clap(long = "frames", conflicts_with = "duration")]
+    frames: Option<u64>,
+    /// The number of milliseconds of animation to render in headless mode
+    #[clap(long = "duration")]
+    duration: Option<u64>,
+    /// The size of each LED in pixels in PNG and GIF output
+    #[clap(long = "scale", default_value = "10")]
+    scale: u32,
+    /// The scene to start with, as the JSON of a scene message, such as
+    /// '{"Fire":{"cooling":55,"sparking":120,"palette":"Heat"}}'
+    #[clap(long = "scene", value_parser = parse_scene)]
+    scene: Option<SceneMessage>,
+}
+
+fn parse_scene(json: &str) -> Result<SceneMessage, serde_json::Error> {
+    serde_json::from_str(json)
 }
 
-struct DisplayWindow<State, Message, const ROWS: usize, const COLS: usize> {
-    _state: PhantomData<(State, Message)>,
+struct DisplayWindow {
+    layout: Layout,
     pixel_size: u32,
     pixel_buffer: Vec<RGB8>,
     pixel_offset: f64,
+    correction: ColourCorrection,
 }
 
-impl<State, Message, const ROWS: usize, const COLS: usize> MatrixDisplay
-    for DisplayWindow<State, Message, ROWS, COLS>
-{
+impl MatrixDisplay for DisplayWindow {
     fn get_mut(&mut self, row: usize, col: usize) -> Option<&mut RGB8> {
-        self.pixel_buffer.get_mut(row * COLS + col)
+        self.pixel_buffer.get_mut(self.layout.index(row, col)?)
     }
 
     fn get(&self, row: usize, col: usize) -> Option<&RGB8> {
-        self.pixel_buffer.get(row * COLS + col)
+        self.pixel_buffer.get(self.layout.index(row, col)?)
     }
 
     fn size(&self) -> (usize, usize) {
-        (ROWS, COLS)
+        self.layout.size()
+    }
+
+    fn set_correction(&mut self, correction: ColourCorrection) {
+        self.correction = correction;
     }
 }
 
-impl<State, Message, const ROWS: usize, const COLS: usize>
-    DisplayWindow<State, Message, ROWS, COLS>
-{
-    pub fn new(pixel_size: u32, pixel_offset: f64) -> Self {
+impl DisplayWindow {
+    pub fn new(layout: Layout, pixel_size: u32, pixel_offset: f64) -> Self {
         assert!(pixel_offset <= 1.0);
         Self {
-            _state: PhantomData,
+            layout,
             pixel_size,
             pixel_buffer: vec![
                 RGB8 {
@@ -84,42 +130,71 @@ impl<State, Message, const ROWS: usize, const COLS: usize>
                     g: 0,
                     b: 0
                 };
-                (ROWS * COLS) as usize
+                layout.len()
             ],
             pixel_offset,
+            correction: ColourCorrection::default(),
         }
     }
 
-    pub fn run(&mut self, rx: Receiver<Message>) {
+    /// Show the window, ticking `state` at its frame rate and applying the messages from `rx`.
+    /// Whoever sent each message is told whether it could be applied, and the state's snapshot
+    /// is sent to `snapshots` after every message.
+    pub fn run<S, M>(
+        &mut self,
+        mut state: S,
+        mut rx: Receiver<Request<M>>,
+        snapshots: watch::Sender<S::Snapshot>,
+    ) where
+        S: Updateable<Message = M> + FrameTime + Snapshot,
+    {
+        let (rows, cols) = self.size();
         let mut window: PistonWindow = WindowSettings::new(
             "Matrix test server",
-            [COLS as u32 * self.pixel_size, ROWS as u32 * self.pixel_size],
+            [cols as u32 * self.pixel_size, rows as u32 * self.pixel_size],
         )
         .exit_on_esc(true)
         .build()
         .unwrap();
 
         let pixel_size = self.pixel_size;
+        let start = Instant::now();
+        let mut pacer = FramePacer::new(0, Overrun::CatchUp { max_frames: 4 });
 
         while let Some(e) = window.next() {
+            loop {
+                match rx.try_recv() {
+                    Ok((message, reply)) => {
+                        let result = state.try_update(Some(message), self);
+                        if let Err(e) = &result {
+                            log::warn!("Couldn't apply a message: {e}");
+                        }
+                        // whoever sent it may have stopped waiting
+                        let _ = reply.send(result);
+                        snapshots.send_replace(state.snapshot());
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            }
+            for _ in 0..pacer.tick(start.elapsed().as_millis() as u64, state.frame_time()) {
+                state.update(None, self);
+            }
+
             window.draw_2d(&e, |c, g, _device| {
                 clear([1.0; 4], g);
                 let offset = self.pixel_size as f64 * self.pixel_offset;
                 let square_size = self.pixel_size as f64 * (1.0 - self.pixel_offset * 2.0);
-                for ((row, col), colour) in (0..ROWS)
-                    .flat_map(|r| {
-                        (0..COLS).map(move |c| {
-                            (
-                                (r as u32 * pixel_size) as f64 + offset,
-                                (c as u32 * pixel_size) as f64 + offset,
-                            )
-                        })
-                    })
-                    .zip(self.pixel_buffer.iter())
-                {
+                for (row, col) in Self::iter_coords_helper(rows, cols) {
+                    let colour = *self.get(row, col).unwrap();
                     rectangle(
-                        transform_colour(*colour),
-                        [col, row, square_size, square_size],
+                        transform_colour(self.correction.apply(colour)),
+                        [
+                            (col as u32 * pixel_size) as f64 + offset,
+                            (row as u32 * pixel_size) as f64 + offset,
+                            square_size,
+                            square_size,
+                        ],
                         c.transform,
                         g,
                     );
@@ -129,41 +204,105 @@ impl<State, Message, const ROWS: usize, const COLS: usize>
     }
 }
 
-fn transform_colour(RGB8 { r, b, g, padding }: RGB8) -> [f32; 4] {
-    [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0]
+pub(crate) fn transform_colour(RGB8 { r, b, g, .. }: RGB8) -> [f32; 4] {
+    let encode = |c: u8| (c as f32 / 255.0).powf(1.0 / 2.2);
+    [encode(r), encode(g), encode(b), 1.0]
 }
 
 fn main() {
-    let (tx, rx) = mpsc::channel::<()>(10);
-    let tokio_rt = spawn_tokio_runtime(tx);
+    let opt = Opt::parse();
+
+    if std::env::var("RUST_LOG").is_err() {
+        std::env::set_var("RUST_LOG", format!("{},hyper=info,mio=info", opt.log_level))
+    }
 
-    DisplayWindow::<(), (), 16, 16>::new(30, 0.3).run(rx);
+    tracing_subscriber::fmt::init();
+
+    let state = MatrixState::new(Scene::Solid(Solid::default()));
+
+    if opt.headless {
+        let options = HeadlessOptions {
+            output: opt.output,
+            format: opt.format,
+            frames: opt.frames,
+            duration: opt.duration,
+            scale: opt.scale,
+        };
+        let message = opt
+            .scene
+            .map(|scene| MatrixStateMessage::UpdateImage(VariantMessage::Switch(scene)));
+        if let Err(e) = headless::run::<_, ROWS, COLS>(state, message, &options) {
+            log::error!("Couldn't render frames: {e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let (tx, rx) = mpsc::channel::<Request<Message>>(10);
+    if let Some(scene) = opt.scene.clone() {
+        // the display applies it before anything else is sent
+        let (reply, _) = oneshot::channel();
+        let _ = tx.try_send((Message::UpdateImage(VariantMessage::Switch(scene)), reply));
+    }
+    let (snapshots, snapshot) = watch::channel(state.snapshot());
+    let tokio_rt = spawn_tokio_runtime(
+        opt,
+        AppState {
+            tx,
+            snapshot,
+            started: Instant::now(),
+        },
+    );
+
+    DisplayWindow::new(LAYOUT, 30, 0.3).run(state, rx, snapshots);
 
     tokio_rt.shutdown_background();
 }
 
-fn spawn_tokio_runtime<Message: Send + 'static>(tx: Sender<Message>) -> Runtime {
+fn spawn_tokio_runtime(opt: Opt, state: AppState) -> Runtime {
     let runtime = Builder::new_multi_thread()
         .worker_threads(4)
         .enable_all()
         .build()
         .unwrap();
 
-    runtime.spawn(start_app(tx));
+    runtime.spawn(start_app(opt, state));
     runtime
 }
 
-async fn start_app<Message>(tx: Sender<Message>) {
-    let opt = Opt::parse();
+/// Shared by the request handlers
+#[derive(Clone)]
+struct AppState {
+    tx: Sender<Request<Message>>,
+    /// what the display is showing, as the display thread owns the matrix state
+    snapshot: watch::Receiver<StateSnapshot>,
+    started: Instant,
+}
 
-    if std::env::var("RUST_LOG").is_err() {
-        std::env::set_var("RUST_LOG", format!("{},hyper=info,mio=info", opt.log_level))
+impl AppState {
+    /// Pass a message on to the display and wait to hear whether it could be applied, or
+    /// `None` if the display has closed
+    async fn send(&self, message: Message) -> Option<Result<(), UpdateError>> {
+        let (reply, result) = oneshot::channel();
+        self.tx.send((message, reply)).await.ok()?;
+        result.await.ok()
     }
+}
 
-    tracing_subscriber::fmt::init();
-
+async fn start_app(opt: Opt, state: AppState) {
     let app = Router::new()
         .route("/ws/ws", get(ws_handler))
+        .route("/api/wifi", post(wifi_handler))
+        .route("/api/access-point", post(access_point_handler))
+        .route("/api/captive-portal", post(captive_portal_handler))
+        .route("/api/config/reset", post(factory_reset_handler))
+        .route("/api/brightness", get(get_brightness).put(set_brightness))
+        .route("/api/scenes", get(|| async { Json(Scene::VARIANTS) }))
+        .route("/api/scene/:name", post(select_scene))
+        .route("/api/info", get(info))
+        .route("/api/state", get(get_state))
+        .route("/api/frame", post(draw_frame))
+        .with_state(state)
         .fallback_service(get(|req| async move {
             ServeDir::new(opt.static_dir).oneshot(req).await
         }))
@@ -177,28 +316,198 @@ async fn start_app<Message>(tx: Sender<Message>) {
     log::info!("listening on http://{}", sock_addr);
 
     axum_server::bind(sock_addr)
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .expect("Unable to start server");
 }
 
-async fn hello() -> impl IntoResponse {
-    "hello from server!"
+/// The simulator has no WiFi to configure, so just show what the device would have been sent
+async fn wifi_handler(Form(form): Form<HashMap<String, String>>) -> impl IntoResponse {
+    let ssid = form.get("ssid").map(String::as_str).unwrap_or_default();
+    log::info!("Ignoring WiFi credentials for \"{ssid}\", the simulator has no WiFi");
+    "The simulator has no WiFi, ignoring the credentials\n"
+}
+
+/// The simulator has no access point either, so the settings are only logged
+async fn access_point_handler(Form(form): Form<HashMap<String, String>>) -> impl IntoResponse {
+    let ssid = form.get("ssid").map(String::as_str).unwrap_or_default();
+    log::info!("Ignoring access point settings for \"{ssid}\", the simulator has no WiFi");
+    "The simulator has no access point, ignoring the settings\n"
+}
+
+/// The simulator doesn't run a captive portal, so there's nothing to switch off
+async fn captive_portal_handler(Form(form): Form<HashMap<String, String>>) -> impl IntoResponse {
+    let enabled = form.get("enabled").map(String::as_str).unwrap_or_default();
+    log::info!("Ignoring captive portal enabled={enabled}, the simulator has no captive portal");
+    "The simulator has no captive portal\n"
+}
+
+/// The simulator keeps no settings between runs, so there's nothing to reset
+async fn factory_reset_handler() -> impl IntoResponse {
+    log::info!("Ignoring a factory reset, the simulator saves no settings");
+    "The simulator saves no settings, so there's nothing to reset\n"
+}
+
+async fn get_state(State(state): State<AppState>) -> Json<StateSnapshot> {
+    Json(state.snapshot.borrow().clone())
+}
+
+async fn get_brightness(State(state): State<AppState>) -> Json<Brightness> {
+    Json(Brightness {
+        brightness: state.snapshot.borrow().brightness,
+    })
+}
+
+async fn set_brightness(
+    State(state): State<AppState>,
+    Json(Brightness { brightness }): Json<Brightness>,
+) -> (StatusCode, String) {
+    let result = state
+        .send(MatrixStateMessage::UpdateBrightness(brightness))
+        .await;
+    reply(result, "Brightness set\n")
+}
+
+/// Switch to a scene, with its message as the body, like `[255, 0, 0]` for `Solid`
+async fn select_scene(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    body: Bytes,
+) -> (StatusCode, String) {
+    let mut body = serde_json::Deserializer::from_slice(&body);
+    let message = api::from_variant::<SceneMessage, _>(&name, &mut body)
+        .and_then(|message| body.end().map(|()| message));
+    match message {
+        Ok(message) => {
+            let result = state
+                .send(MatrixStateMessage::UpdateImage(VariantMessage::Switch(
+                    message,
+                )))
+                .await;
+            reply(result, "Scene selected\n")
+        }
+        Err(e) => {
+            log::warn!("Couldn't select scene {name}: {e}");
+            (
+                StatusCode::BAD_REQUEST,
+                "Unknown scene, or bad parameters for it\n".into(),
+            )
+        }
+    }
+}
+
+async fn info(State(state): State<AppState>) -> impl IntoResponse {
+    let (rows, cols) = LAYOUT.size();
+    Json(Info {
+        rows,
+        cols,
+        layout: LAYOUT,
+        version: env!("CARGO_PKG_VERSION"),
+        uptime: state.started.elapsed().as_secs(),
+        free_memory: None,
+    })
+}
+
+/// Draw a frame over the scene until it's next sent a message
+async fn draw_frame(State(state): State<AppState>, body: Bytes) -> (StatusCode, String) {
+    let frame = serde_json::from_slice::<Frame>(&body);
+    let Some(runs) = frame.as_ref().ok().and_then(Frame::runs) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            "Pixels must be rrggbb hex colours, one after another\n".into(),
+        );
+    };
+    let mut result = Some(Ok(()));
+    for run in runs {
+        result = state.send(MatrixStateMessage::DrawPixels(run)).await;
+        if result != Some(Ok(())) {
+            break;
+        }
+    }
+    reply(result, "Frame drawn\n")
+}
+
+/// The response to a request, once the display has said whether it applied its message
+fn reply(result: Option<Result<(), UpdateError>>, done: &str) -> (StatusCode, String) {
+    match result {
+        Some(Ok(())) => (StatusCode::OK, done.into()),
+        Some(Err(e)) => (StatusCode::BAD_REQUEST, format!("{e}\n")),
+        None => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The display has closed\n".into(),
+        ),
+    }
 }
 
 async fn ws_handler(
     ws: WebSocketUpgrade,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<AppState>,
 ) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, addr))
+    ws.on_upgrade(move |socket| handle_socket(socket, addr, state))
 }
 
-async fn handle_socket(mut socket: WebSocket, who: SocketAddr) {
+/// Decode the postcard encoded messages from a client and pass them on to the display, and keep
+/// the client up to date with what the display is showing and which of its messages couldn't
+/// be applied
+async fn handle_socket(mut socket: WebSocket, who: SocketAddr, state: AppState) {
+    let mut snapshot = state.snapshot.clone();
+    // the first snapshot is sent straight away
+    snapshot.mark_changed();
     loop {
-        sleep(Duration::from_secs(1)).await;
-        socket
-            .send(axum::extract::ws::Message::Text("Hiiii".into()))
-            .await
-            .unwrap();
+        let message = tokio::select! {
+            received = socket.recv() => {
+                let Some(received) = received else {
+                    break;
+                };
+                received
+            }
+            changed = snapshot.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                let event = ServerMessage::State(snapshot.borrow_and_update().clone());
+                if send_event(&mut socket, &event).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+        };
+        match message {
+            Ok(WsMessage::Binary(data)) => match postcard::from_bytes::<Message>(&data) {
+                Ok(message) => match state.send(message).await {
+                    Some(Ok(())) => {}
+                    Some(Err(error)) => {
+                        if send_event(&mut socket, &ServerMessage::Error(error))
+                            .await
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                    None => return,
+                },
+                Err(e) => log::warn!("Couldn't decode a matrix message from {who}: {e}"),
+            },
+            Ok(WsMessage::Text(_)) => {
+                log::warn!("Ignoring a text message from {who}, matrix messages are binary")
+            }
+            Ok(WsMessage::Close(_)) => break,
+            Ok(_) => {}
+            Err(e) => {
+                log::warn!("Websocket error from {who}: {e}");
+                break;
+            }
+        }
     }
+    log::info!("{who} disconnected");
+}
+
+/// Send a client a postcard encoded message from the display
+async fn send_event(
+    socket: &mut WebSocket,
+    event: &ServerMessage<StateSnapshot>,
+) -> Result<(), axum::Error> {
+    let data = postcard::to_stdvec(event).unwrap();
+    socket.send(WsMessage::Binary(data)).await
 }